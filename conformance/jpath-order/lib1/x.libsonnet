@@ -0,0 +1 @@
+"lib1/x.libsonnet"
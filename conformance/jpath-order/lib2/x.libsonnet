@@ -0,0 +1 @@
+"lib2/x.libsonnet"
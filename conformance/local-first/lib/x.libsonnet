@@ -0,0 +1 @@
+"lib/x.libsonnet"
@@ -0,0 +1 @@
+"env/common.libsonnet"
@@ -0,0 +1 @@
+"real/x.libsonnet"
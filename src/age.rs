@@ -0,0 +1,127 @@
+use std::path::{Path, PathBuf};
+#[cfg(feature = "git")]
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub struct Settings {
+	// Use the time of the last git commit touching each file instead of its mtime.
+	pub git: bool,
+	// Flag deps last changed more than this many seconds ago.
+	pub older_than: Option<u64>,
+	// Flag deps last changed less than this many seconds ago.
+	pub newer_than: Option<u64>,
+}
+
+// When a file was last changed, in seconds since the unix epoch.
+// None means we couldn't find out, eg. the file doesn't exist or isn't tracked by git.
+#[cfg_attr(not(feature = "git"), allow(unused_variables))]
+fn last_changed(settings: &Settings, path: &Path) -> Result<Option<u64>, String> {
+	#[cfg(feature = "git")]
+	if settings.git {
+		return git_last_changed(path);
+	}
+	let metadata = match std::fs::metadata(path) {
+		Ok(metadata) => metadata,
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+		Err(e) => return Err(format!("Could not stat {}: {}", path.display(), e)),
+	};
+	let mtime = metadata.modified().map_err(|e|
+		format!("Could not get mtime of {}: {}", path.display(), e)
+	)?;
+	// Times before the epoch aren't meaningful here, treat them as the epoch.
+	Ok(Some(mtime.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())))
+}
+
+#[cfg(feature = "git")]
+fn git_last_changed(path: &Path) -> Result<Option<u64>, String> {
+	// Run git from the file's own directory, so we find the right repository
+	// even if it isn't the one we're running in.
+	let dir = match path.parent() {
+		Some(dir) if !dir.as_os_str().is_empty() => dir,
+		_ => Path::new("."),
+	};
+	// We know the path has a file name as we resolved it from an import.
+	let name = path.file_name().unwrap();
+	let output = Command::new("git")
+		.arg("-C").arg(dir)
+		.args(["log", "-1", "--format=%ct", "--"])
+		.arg(name)
+		.output()
+		.map_err(|e| format!("Failed to run git: {}", e))?;
+	if !output.status.success() {
+		// Most likely the file is outside any repository.
+		return Ok(None);
+	}
+	let stdout = String::from_utf8_lossy(&output.stdout);
+	let stdout = stdout.trim();
+	// No output means the file has never been committed.
+	if stdout.is_empty() {
+		return Ok(None);
+	}
+	stdout.parse().map(Some).map_err(|e|
+		format!("Bad commit time {:?} from git for {}: {}", stdout, path.display(), e)
+	)
+}
+
+// Format a unix timestamp as a UTC date and time.
+fn format_time(secs: u64) -> String {
+	let days = (secs / 86400) as i64;
+	let rem = secs % 86400;
+	// Convert days since the epoch into a civil date.
+	// See http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+	let z = days + 719468;
+	let era = z.div_euclid(146097);
+	let doe = z.rem_euclid(146097);
+	let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+	let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+	let mp = (5 * doy + 2) / 153;
+	let day = doy - (153 * mp + 2) / 5 + 1;
+	let month = if mp < 10 { mp + 3 } else { mp - 9 };
+	let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+	format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}", year, month, day, rem / 3600, rem % 3600 / 60, rem % 60)
+}
+
+// Print each dep of the root along with when it last changed, oldest first.
+// The root line lists which flags were raised by any of its deps.
+pub fn report(settings: &Settings, root: &Path, deps: &[PathBuf]) -> Result<(), String> {
+	let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+	let mut entries = Vec::new();
+	for dep in deps {
+		entries.push((last_changed(settings, dep)?, dep));
+	}
+	// Unknown times sort first, as they are likely to be the most interesting.
+	entries.sort();
+
+	let mut lines = Vec::new();
+	let mut root_flags: Vec<&str> = Vec::new();
+	for (time, dep) in entries {
+		let (time_str, flag) = match time {
+			None => ("-".to_string(), Some(if settings.git { "untracked" } else { "missing" })),
+			Some(time) => {
+				let age = now.saturating_sub(time);
+				let flag = if settings.older_than.is_some_and(|limit| age > limit) {
+					Some("old")
+				} else if settings.newer_than.is_some_and(|limit| age < limit) {
+					Some("new")
+				} else {
+					None
+				};
+				(format_time(time), flag)
+			}
+		};
+		match flag {
+			Some(flag) => {
+				if !root_flags.contains(&flag) {
+					root_flags.push(flag);
+				}
+				lines.push(format!("\t{} {} {}", time_str, dep.display(), flag));
+			},
+			None => lines.push(format!("\t{} {}", time_str, dep.display())),
+		}
+	}
+	println!("{}: {}", root.display(), root_flags.join(" "));
+	for line in lines {
+		println!("{}", line);
+	}
+	Ok(())
+}
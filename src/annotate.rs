@@ -0,0 +1,149 @@
+// Annotating a root's source with what each of its imports costs, so a reviewer can see the real impact of an
+// import line without leaving the diff. The root is output as a diff which adds a comment to the end of each line
+// with an import, giving what the import resolved to and how many files and bytes it brings in, as with --stats.
+
+use crate::output::shown;
+use crate::scan::ImportKind;
+use crate::stats::{format_size, total_size};
+use crate::{closure, Cache, Deadline, Options, Root};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+// The annotation for each line of the root with an import, by line number counting from 1.
+// An import written across several lines is annotated on the line it starts on.
+fn annotations(options: &Options, cache: &Cache, deadline: Deadline, root: &Root) -> Result<BTreeMap<usize, Vec<String>>, String> {
+	let imports = cache.parsed(options, &root.path)?;
+	let analysis = cache.analysis(options, &root.jpaths, &root.path)?;
+	let mut sizes = HashMap::new();
+	let mut annotations: BTreeMap<usize, Vec<String>> = BTreeMap::new();
+	for (import, target) in imports.iter().zip(&analysis.targets) {
+		let deps = match import.kind {
+			ImportKind::Import => closure(options, cache, deadline, &root.jpaths, vec![target.clone()], Some(&root.path))?,
+			ImportKind::ImportStr => HashSet::from([target.clone()]),
+		};
+		let unresolved = match analysis.unresolved.contains(target) {
+			true => " (unresolved)",
+			false => "",
+		};
+		let annotation = format!(
+			"{}{}: {} files, {}",
+			shown(options, target).display(), unresolved, deps.len(), format_size(total_size(&mut sizes, &deps)?),
+		);
+		let line = annotations.entry(import.span.line).or_default();
+		if !line.contains(&annotation) {
+			line.push(annotation);
+		}
+	}
+	Ok(annotations)
+}
+
+// Whether the end of each line is in code (or a comment which ends with the line), so a // comment can be added
+// there without changing what the file means. It isn't if the line ends inside a string, a /* comment or a |||
+// text block, including the line that opens one.
+fn ends_in_code(contents: &str) -> Vec<bool> {
+	enum State {
+		Code,
+		LineComment,
+		BlockComment,
+		// The quote, and whether it's a verbatim @ string, where a doubled quote is the only escape.
+		Str(char, bool),
+		TextBlock,
+	}
+	let mut state = State::Code;
+	let mut ends = Vec::new();
+	let mut chars = contents.chars().peekable();
+	let mut line_start = true;
+	while let Some(c) = chars.next() {
+		let at_line_start = std::mem::replace(&mut line_start, c == '\n');
+		if c == '\n' {
+			ends.push(matches!(state, State::Code | State::LineComment));
+			if matches!(state, State::LineComment) {
+				state = State::Code;
+			}
+			continue;
+		}
+		state = match state {
+			State::Code => match c {
+				'#' => State::LineComment,
+				'/' if chars.next_if_eq(&'/').is_some() => State::LineComment,
+				'/' if chars.next_if_eq(&'*').is_some() => State::BlockComment,
+				'"' | '\'' => State::Str(c, false),
+				'@' if chars.peek().is_some_and(|c| *c == '"' || *c == '\'') => State::Str(chars.next().unwrap(), true),
+				'|' if chars.next_if_eq(&'|').is_some() => match chars.next_if_eq(&'|') {
+					Some(_) => State::TextBlock,
+					None => State::Code,
+				},
+				_ => State::Code,
+			},
+			State::LineComment => State::LineComment,
+			State::BlockComment => match c {
+				'*' if chars.next_if_eq(&'/').is_some() => State::Code,
+				_ => State::BlockComment,
+			},
+			State::Str(quote, verbatim) => match c {
+				'\\' if !verbatim => {
+					chars.next();
+					State::Str(quote, verbatim)
+				},
+				c if c == quote && verbatim && chars.next_if_eq(&quote).is_some() => State::Str(quote, verbatim),
+				c if c == quote => State::Code,
+				_ => State::Str(quote, verbatim),
+			},
+			// A text block ends at the first line starting with |||, after its indentation.
+			State::TextBlock if at_line_start => {
+				let mut rest = std::iter::once(c).chain(chars.clone()).skip_while(|c| *c == ' ' || *c == '\t');
+				if rest.by_ref().take(3).eq("|||".chars()) {
+					let mut bars = usize::from(c == '|');
+					while bars < 3 {
+						bars += usize::from(chars.next() == Some('|'));
+					}
+					State::Code
+				} else {
+					State::TextBlock
+				}
+			},
+			State::TextBlock => State::TextBlock,
+		};
+	}
+	ends.push(matches!(state, State::Code | State::LineComment));
+	ends
+}
+
+// Print the root as a unified diff from itself to itself with the annotations added, all as one hunk.
+// Lines keep their endings, so the diff applies to the file as it is, whatever they are.
+pub fn report(options: &Options, cache: &Cache, deadline: Deadline, root: &Root) -> Result<(), String> {
+	let contents = options.fs.read_to_string(&root.path).map_err(|e| format!("Failed to read {}: {}", root.path.display(), e))?;
+	let annotations = annotations(options, cache, deadline, root)?;
+	let lines: Vec<&str> = contents.split_inclusive('\n').collect();
+	let ends_in_code = ends_in_code(&contents);
+	let path = shown(options, &root.path);
+	println!("--- {}", path.display());
+	println!("+++ {}", path.display());
+	// A hunk of no lines starts at line 0.
+	let start = usize::from(!lines.is_empty());
+	println!("@@ -{},{} +{},{} @@", start, lines.len(), start, lines.len());
+	let mut skipped = 0;
+	for (i, line) in lines.iter().enumerate() {
+		let body = line.trim_end_matches('\n').trim_end_matches('\r');
+		let ending = &line[body.len()..];
+		// The last line may have no newline, which the diff has to say so it isn't added.
+		let print = |prefix: char, text: &str| match ending {
+			"" => print!("{}{}\n\\ No newline at end of file\n", prefix, text),
+			_ => print!("{}{}{}", prefix, text, ending),
+		};
+		match annotations.get(&(i + 1)) {
+			Some(annotation) if ends_in_code[i] => {
+				print('-', body);
+				print('+', &format!("{}  // {}", body, annotation.join("; ")));
+			},
+			Some(_) => {
+				skipped += 1;
+				print(' ', body);
+			},
+			None => print(' ', body),
+		}
+	}
+	if skipped > 0 {
+		eprintln!("{}: left {} lines without annotations, as they end inside a string, comment or text block", path.display(), skipped);
+	}
+	Ok(())
+}
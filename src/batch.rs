@@ -0,0 +1,115 @@
+use crate::jobserver::{self, Jobserver};
+use crate::{depfile, json, output, resolve_deps, Cache, Deadline, Options, Root};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+struct Job {
+	root: Root,
+	// If not given, output goes to stdout.
+	output: Option<PathBuf>,
+}
+
+// Parse a job of the form {"root": PATH, "jpaths": [PATH, ...], "output": PATH, "meta": {KEY: VALUE, ...}, "target": TARGET}.
+// Only root is required. If jpaths is not given, the global --jpath list is used.
+fn parse_job(line: &str, default_jpaths: &[PathBuf]) -> Result<Job, String> {
+	let value = json::parse(line)?;
+	if !matches!(value, json::Value::Object(_)) {
+		return Err("Job must be an object".into());
+	}
+	let root = value.get("root")
+		.ok_or("Job is missing root")?
+		.as_str().ok_or("Job root must be a string")?;
+	let jpaths = match value.get("jpaths") {
+		None => default_jpaths.to_vec(),
+		Some(jpaths) => jpaths.as_array()
+			.ok_or("Job jpaths must be a list")?
+			.iter()
+			.map(|jpath| jpath.as_str().map(PathBuf::from).ok_or("Job jpaths must be strings"))
+			.collect::<Result<_, _>>()?,
+	};
+	let output = match value.get("output") {
+		None => None,
+		Some(output) => Some(output.as_str().ok_or("Job output must be a string")?.into()),
+	};
+	let meta = match value.get("meta") {
+		None => Vec::new(),
+		Some(json::Value::Object(items)) => items.iter()
+			.map(|(key, value)| value.as_str().map(|value| (key.clone(), value.to_string())).ok_or("Job meta values must be strings"))
+			.collect::<Result<_, _>>()?,
+		Some(_) => return Err("Job meta must be an object".into()),
+	};
+	let target = match value.get("target") {
+		None => None,
+		Some(target) => {
+			let target = target.as_str().ok_or("Job target must be a string")?;
+			output::render_target(target, None)?;
+			Some(target.into())
+		},
+	};
+	Ok(Job { root: Root { path: root.into(), jpaths, meta, target }, output })
+}
+
+fn run_job(options: &Options, cache: &Cache, job: &Job) -> Result<(), String> {
+	let deadline = Deadline::new(&job.root.path);
+	let deps = resolve_deps(options, cache, deadline, &job.root.jpaths, &job.root.path)?;
+	let line = output::format_deps(options, cache, deadline, &job.root, &deps)?;
+	match &job.output {
+		None => output::write_stdout(&[line, options.format.line_end().into()].concat())?,
+		Some(output) => depfile::write(output, &[line, options.format.line_end().into()].concat())?,
+	}
+	Ok(())
+}
+
+// Run all jobs in the given file, using up to the given number of threads, and job slots from make's jobserver if given.
+// A failing job doesn't stop the others, but causes the batch as a whole to fail.
+pub fn run(
+	options: &Options,
+	cache: &Cache,
+	default_jpaths: &[PathBuf],
+	path: &Path,
+	workers: usize,
+	jobserver: Option<Jobserver>,
+) -> Result<(), String> {
+	let contents = std::fs::read_to_string(path).map_err(|e|
+		format!("Failed to read {}: {}", path.display(), e)
+	)?;
+	let mut jobs = Vec::new();
+	for (index, line) in contents.lines().enumerate() {
+		if line.trim().is_empty() {
+			continue;
+		}
+		let mut job = parse_job(line, default_jpaths).map_err(|e|
+			format!("Bad job at {}:{}: {}", path.display(), index + 1, e)
+		)?;
+		if job.root.target.is_some() && !matches!(options.format, output::Format::Make | output::Format::NinjaDepfile) {
+			return Err(format!("Bad job at {}:{}: target can only be used with make or ninja-depfile format", path.display(), index + 1));
+		}
+		job.root.path = options.normalized(job.root.path)?;
+		jobs.push(job);
+	}
+	depfile::check_collisions(jobs.iter().filter_map(|job| Some((job.root.path.as_path(), job.output.clone()?))))?;
+
+	let next = AtomicUsize::new(0);
+	let failures = AtomicUsize::new(0);
+	let tokens = jobserver.map(Jobserver::tokens);
+	let (jobs, next, failures, tokens) = (&jobs, &next, &failures, tokens.as_ref());
+	std::thread::scope(|scope| {
+		for worker in 0..workers.min(jobs.len()) {
+			scope.spawn(move || {
+				while let Some(_token) = jobserver::slot(tokens, worker, || next.load(Ordering::Relaxed) < jobs.len()) {
+					let Some(job) = jobs.get(next.fetch_add(1, Ordering::Relaxed)) else {
+						break;
+					};
+					if let Err(e) = run_job(options, cache, job) {
+						eprintln!("{}", e);
+						failures.fetch_add(1, Ordering::Relaxed);
+					}
+				}
+			});
+		}
+	});
+	match failures.load(Ordering::Relaxed) {
+		0 => Ok(()),
+		failed => Err(format!("{} of {} batch jobs failed", failed, jobs.len())),
+	}
+}
@@ -0,0 +1,85 @@
+// Writing the deps of a root as a Bazel rule, so that a BUILD file's list of jsonnet sources can be generated
+// rather than kept up to date by hand. Labels are relative to the root's workspace, the nearest directory above
+// it with a WORKSPACE, WORKSPACE.bazel or MODULE.bazel file, and as in Bazel each file belongs to the package
+// of the nearest BUILD or BUILD.bazel file above it.
+
+use crate::json::Value;
+use crate::resolve::normalize;
+use crate::Options;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+const WORKSPACE_FILES: [&str; 3] = ["WORKSPACE", "WORKSPACE.bazel", "MODULE.bazel"];
+const BUILD_FILES: [&str; 2] = ["BUILD", "BUILD.bazel"];
+
+fn has_any(options: &Options, dir: &Path, names: &[&str]) -> Result<bool, String> {
+	for name in names {
+		let path = dir.join(name);
+		if options.fs.exists(&path).map_err(|e| format!("Could not check path {}: {}", path.display(), e))? {
+			return Ok(true);
+		}
+	}
+	Ok(false)
+}
+
+// The path relative to the workspace.
+fn relative<'a>(workspace: &Path, absolute: &'a Path) -> Result<&'a Path, String> {
+	absolute.strip_prefix(workspace)
+		.map_err(|_| format!("Can't make a Bazel label for {}, as it is outside the workspace {}", absolute.display(), workspace.display()))
+}
+
+// The label of a file, eg. //lib/k8s:deployment.libsonnet.
+fn label(options: &Options, workspace: &Path, absolute: &Path) -> Result<String, String> {
+	let relative = relative(workspace, absolute)?;
+	let mut package = Path::new("");
+	for dir in relative.parent().unwrap_or(Path::new("")).ancestors() {
+		if has_any(options, &workspace.join(dir), &BUILD_FILES)? {
+			package = dir;
+			break;
+		}
+	}
+	let target = relative.strip_prefix(package).unwrap();
+	Ok(format!("//{}:{}", package.display(), target.display()))
+}
+
+// A name for the root's rule, from its path in the workspace without the extension, eg. env/prod/main.jsonnet is env_prod_main.
+fn rule_name(relative: &Path) -> String {
+	relative.with_extension("").to_string_lossy().chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect()
+}
+
+// The rule, eg. filegroup(name = "main", srcs = [...]), formatted as buildifier would.
+// Unresolved deps are listed in a comment, as for make.
+pub fn rule<'a>(options: &Options, root: &Path, deps: impl Iterator<Item = &'a PathBuf>, unresolved: &BTreeSet<&PathBuf>) -> Result<String, String> {
+	let cwd = std::env::current_dir().map_err(|e| format!("Failed to get current directory: {}", e))?;
+	let root = normalize(&cwd.join(root));
+	let dir = root.parent().unwrap();
+	let mut workspace = None;
+	for dir in dir.ancestors() {
+		if has_any(options, dir, &WORKSPACE_FILES)? {
+			workspace = Some(dir);
+			break;
+		}
+	}
+	let workspace = workspace.ok_or_else(|| format!(
+		"Can't make Bazel labels, as there is no {} in {} or above", WORKSPACE_FILES.join(" or "), dir.display(),
+	))?;
+	let mut labels = BTreeSet::new();
+	for dep in deps {
+		labels.insert(label(options, workspace, &normalize(&cwd.join(dep)))?);
+	}
+	// JSON strings are valid Starlark strings.
+	let string = |s: &str| Value::String(s.into()).to_string();
+	let mut lines = vec![
+		format!("{}(", options.bazel_rule),
+		format!("    name = {},", string(&rule_name(relative(workspace, &root)?))),
+		"    srcs = [".into(),
+	];
+	lines.extend(labels.iter().map(|label| format!("        {},", string(label))));
+	lines.push("    ],".into());
+	if !unresolved.is_empty() {
+		let as_str: Vec<_> = unresolved.iter().map(|p| p.to_string_lossy()).collect();
+		lines.push(format!("    # unresolved: {}", as_str.join(" ")));
+	}
+	lines.push(")".into());
+	Ok(lines.join("\n"))
+}
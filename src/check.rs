@@ -0,0 +1,50 @@
+// Compare a root's deps against a golden list committed alongside it, so that adding
+// or removing a dep has to be done explicitly.
+// The golden file lists one dep per line, sorted. Blank lines and lines starting with # are ignored.
+
+use std::collections::{BTreeSet, HashSet};
+use std::path::{Path, PathBuf};
+
+fn load(path: &Path) -> Result<BTreeSet<PathBuf>, String> {
+	let contents = std::fs::read_to_string(path).map_err(|e|
+		format!("Failed to read {}: {}", path.display(), e)
+	)?;
+	Ok(contents.lines()
+		.map(|line| line.trim())
+		.filter(|line| !line.is_empty() && !line.starts_with('#'))
+		.map(PathBuf::from)
+		.collect())
+}
+
+// Write the deps as the new golden list.
+pub fn update(golden: &Path, deps: &HashSet<PathBuf>) -> Result<(), String> {
+	let deps: BTreeSet<_> = deps.iter().collect();
+	let mut contents = String::new();
+	for dep in deps {
+		contents.push_str(&dep.to_string_lossy());
+		contents.push('\n');
+	}
+	std::fs::write(golden, contents).map_err(|e| format!("Failed to write {}: {}", golden.display(), e))
+}
+
+// Print any differences between the deps and the golden list, and fail if there are any.
+pub fn check(golden: &Path, root: &Path, deps: &HashSet<PathBuf>) -> Result<(), String> {
+	let expected = load(golden)?;
+	let actual: BTreeSet<PathBuf> = deps.iter().cloned().collect();
+	let added: Vec<_> = actual.difference(&expected).collect();
+	let removed: Vec<_> = expected.difference(&actual).collect();
+	if added.is_empty() && removed.is_empty() {
+		return Ok(());
+	}
+	println!("{}: deps differ from {}", root.display(), golden.display());
+	for dep in &removed {
+		println!("- {}", dep.display());
+	}
+	for dep in &added {
+		println!("+ {}", dep.display());
+	}
+	Err(format!(
+		"{} deps added and {} removed compared to {}. If this is intended, re-run with --update to accept it.",
+		added.len(), removed.len(), golden.display(),
+	))
+}
@@ -0,0 +1,116 @@
+// Reading CODEOWNERS files, as used by GitHub and GitLab, to find who owns each file so that
+// notifications about it can be sent to the right people.
+
+use crate::resolve::normalize;
+use std::path::{Path, PathBuf};
+
+// Where a CODEOWNERS file may be, relative to the top of the repository, in the order GitHub looks.
+const LOCATIONS: [&str; 3] = [".github/CODEOWNERS", "CODEOWNERS", "docs/CODEOWNERS"];
+
+struct Rule {
+	// Split on /. A pattern with a slash anywhere but the end only matches from the top of the repository.
+	segments: Vec<String>,
+	anchored: bool,
+	// Whether it matches everything in directories it matches. Patterns ending in /* only match files directly inside.
+	recursive: bool,
+	owners: Vec<String>,
+}
+
+pub struct CodeOwners {
+	// The directory patterns are relative to.
+	root: PathBuf,
+	rules: Vec<Rule>,
+	// What the paths we're given are relative to.
+	cwd: PathBuf,
+}
+
+// Match one segment of a path against one segment of a pattern, where * is any run of characters and ? is any one.
+fn segment_matches(pattern: &[char], name: &[char]) -> bool {
+	match (pattern.first(), name.first()) {
+		(None, None) => true,
+		(Some('*'), _) => segment_matches(&pattern[1..], name) || (!name.is_empty() && segment_matches(pattern, &name[1..])),
+		(Some('?'), Some(_)) => segment_matches(&pattern[1..], &name[1..]),
+		(Some(p), Some(n)) if p == n => segment_matches(&pattern[1..], &name[1..]),
+		_ => false,
+	}
+}
+
+// Match the path's segments from the start, where ** is any number of segments.
+// If recursive, the path may have segments left over, ie. be inside what the pattern matches.
+fn matches(pattern: &[String], path: &[Vec<char>], recursive: bool) -> bool {
+	match pattern.first() {
+		None => recursive || path.is_empty(),
+		Some(segment) if segment == "**" => (0..=path.len()).any(|skip| matches(&pattern[1..], &path[skip..], recursive)),
+		Some(segment) => match path.first() {
+			Some(name) => segment_matches(&segment.chars().collect::<Vec<_>>(), name) && matches(&pattern[1..], &path[1..], recursive),
+			None => false,
+		},
+	}
+}
+
+impl CodeOwners {
+	// The owners of the file, which may be none. As in GitHub, the last matching rule wins.
+	pub fn owners(&self, path: &Path) -> &[String] {
+		let absolute = normalize(&self.cwd.join(path));
+		let Ok(relative) = absolute.strip_prefix(&self.root) else {
+			return &[];
+		};
+		let segments: Vec<Vec<char>> = relative.iter().map(|segment| segment.to_string_lossy().chars().collect()).collect();
+		for rule in self.rules.iter().rev() {
+			let found = if rule.anchored {
+				matches(&rule.segments, &segments, rule.recursive)
+			} else {
+				(0..segments.len()).any(|skip| matches(&rule.segments, &segments[skip..], rule.recursive))
+			};
+			if found {
+				return &rule.owners;
+			}
+		}
+		&[]
+	}
+}
+
+pub fn load(path: &Path) -> Result<CodeOwners, String> {
+	let contents = std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+	let cwd = std::env::current_dir().map_err(|e| format!("Failed to get current directory: {}", e))?;
+	let path = normalize(&cwd.join(path));
+	// Patterns are relative to the top of the repository, which is above .github/ or docs/ if it's in one.
+	let dir = path.parent().unwrap();
+	let root = match dir.file_name().and_then(|name| name.to_str()) {
+		Some(".github" | "docs") => dir.parent().unwrap_or(dir),
+		_ => dir,
+	};
+	let mut rules = Vec::new();
+	for line in contents.lines() {
+		let line = line.trim();
+		// GitLab's [Section] headers don't change which rules apply, so they're skipped as well as comments.
+		if line.is_empty() || line.starts_with('#') || line.starts_with('[') || line.starts_with("^[") {
+			continue;
+		}
+		let mut words = line.split_whitespace();
+		let pattern = words.next().unwrap();
+		let owners = words.take_while(|word| !word.starts_with('#')).map(|word| word.to_string()).collect();
+		let trimmed = pattern.trim_start_matches('/').trim_end_matches('/');
+		rules.push(Rule {
+			segments: trimmed.split('/').filter(|segment| !segment.is_empty()).map(|segment| segment.to_string()).collect(),
+			anchored: trimmed.contains('/') || pattern.starts_with('/'),
+			recursive: !trimmed.ends_with("/*") || pattern.ends_with('/'),
+			owners,
+		});
+	}
+	Ok(CodeOwners { root: root.to_owned(), rules, cwd })
+}
+
+// Load the CODEOWNERS file of the repository the current directory is in, looking in each directory above it.
+pub fn find() -> Result<CodeOwners, String> {
+	let cwd = std::env::current_dir().map_err(|e| format!("Failed to get current directory: {}", e))?;
+	for dir in cwd.ancestors() {
+		for location in LOCATIONS {
+			let path = dir.join(location);
+			if path.is_file() {
+				return load(&path);
+			}
+		}
+	}
+	Err(format!("No CODEOWNERS file found in {} or above. Pass one with --codeowners FILE", cwd.display()))
+}
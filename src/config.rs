@@ -0,0 +1,94 @@
+// The config file given with --config, for settings that are a property of the repo rather than of one run.
+// It is a JSON object of the form:
+//   {"scopes": [{"dir": PATH, "jpaths": [PATH, ...]}, ...]}
+// Paths are relative to the directory containing the config file.
+
+use crate::json::{self, Value};
+use crate::resolve::normalize;
+use std::path::{Path, PathBuf};
+
+// Files within dir resolve their imports with these jpaths, instead of the ones given for the root.
+struct Scope {
+	dir: PathBuf,
+	jpaths: Vec<PathBuf>,
+}
+
+#[derive(Default)]
+pub struct Config {
+	// The file it was loaded from, if any.
+	path: Option<PathBuf>,
+	scopes: Vec<Scope>,
+}
+
+fn paths(path: &Path, value: &Value, what: &str) -> Result<Vec<PathBuf>, String> {
+	value.as_array()
+		.ok_or_else(|| format!("{}: {} must be a list", path.display(), what))?
+		.iter()
+		.map(|item| item.as_str().map(PathBuf::from).ok_or_else(|| format!("{}: {} must be strings", path.display(), what)))
+		.collect()
+}
+
+pub fn load(path: &Path) -> Result<Config, String> {
+	let contents = std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+	let value = json::parse(&contents).map_err(|e| format!("Failed to parse {}: {}", path.display(), e))?;
+	if !matches!(value, Value::Object(_)) {
+		return Err(format!("{}: config must be an object", path.display()));
+	}
+	let base = path.parent().unwrap_or(Path::new(""));
+	let mut config = Config { path: Some(path.to_owned()), scopes: Vec::new() };
+	let scopes = match value.get("scopes") {
+		None => &[][..],
+		Some(scopes) => scopes.as_array().ok_or_else(|| format!("{}: scopes must be a list", path.display()))?,
+	};
+	for scope in scopes {
+		let dir = scope.get("dir").and_then(|dir| dir.as_str())
+			.ok_or_else(|| format!("{}: each scope must have a dir", path.display()))?;
+		let jpaths = scope.get("jpaths").ok_or_else(|| format!("{}: scope {} is missing jpaths", path.display(), dir))?;
+		config.scopes.push(Scope {
+			dir: normalize(&base.join(dir)),
+			jpaths: paths(path, jpaths, "scope jpaths")?.iter().map(|jpath| base.join(jpath)).collect(),
+		});
+	}
+	Ok(config)
+}
+
+impl Config {
+	pub fn to_json(&self) -> Value {
+		let paths = |paths: &[PathBuf]| Value::Array(paths.iter().map(|path| Value::String(path.to_string_lossy().into_owned())).collect());
+		Value::Object(vec![
+			("path".into(), self.path.as_ref().map_or(Value::Null, |path| Value::String(path.to_string_lossy().into_owned()))),
+			("scopes".into(), Value::Array(self.scopes.iter().map(|scope| Value::Object(vec![
+				("dir".into(), Value::String(scope.dir.to_string_lossy().into_owned())),
+				("jpaths".into(), paths(&scope.jpaths)),
+			])).collect())),
+		])
+	}
+
+	// Every path in the config.
+	pub fn paths(&self) -> impl Iterator<Item = &Path> {
+		self.scopes.iter().flat_map(|scope| std::iter::once(&scope.dir).chain(scope.jpaths.iter())).map(|path| path.as_path())
+	}
+
+	// The contents of a config file equivalent to this one, with each path changed by map to be relative
+	// to where the file will be. None if no config was loaded.
+	pub fn to_file_json(&self, map: impl Fn(&Path) -> PathBuf) -> Option<Value> {
+		self.path.as_ref()?;
+		let path = |path: &Path| Value::String(map(path).to_string_lossy().into_owned());
+		Some(Value::Object(vec![
+			("scopes".into(), Value::Array(self.scopes.iter().map(|scope| Value::Object(vec![
+				("dir".into(), path(&scope.dir)),
+				("jpaths".into(), Value::Array(scope.jpaths.iter().map(|jpath| path(jpath)).collect())),
+			])).collect())),
+		]))
+	}
+
+	// The jpaths for the scope the file is in, if any. If it's in more than one, the innermost wins.
+	// Paths are compared lexically, so a file only matches if its path is written relative to the same place.
+	pub fn jpaths_for(&self, file: &Path) -> Option<&[PathBuf]> {
+		let file = normalize(file);
+		self.scopes.iter()
+			.filter(|scope| file.starts_with(&scope.dir))
+			.max_by_key(|scope| scope.dir.components().count())
+			.map(|scope| scope.jpaths.as_slice())
+	}
+}
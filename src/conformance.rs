@@ -0,0 +1,136 @@
+// A conformance suite for import resolution: directories of fixtures saying which file each import
+// should load, which can be checked against this tool and also against a real jsonnet binary,
+// to confirm they agree before trusting our deps.
+//
+// Each case is a directory containing a case.json of the form:
+//   {"root": PATH, "jpaths": [PATH, ...], "expect": [PATH, ...]}
+// where expect lists the file each of root's imports should load, in order. Paths are relative to the case directory.
+// So that jsonnet can tell us which files it loaded, every file a case imports should evaluate to a string
+// of its own path (for importstr, contain just its path), and the root should be an array of its imports.
+
+use crate::json::{self, Value};
+use crate::resolve::normalize;
+use crate::{Cache, Options};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const CASE_FILE: &str = "case.json";
+
+struct Case {
+	root: PathBuf,
+	jpaths: Vec<PathBuf>,
+	expect: Vec<PathBuf>,
+}
+
+fn load(path: &Path) -> Result<Case, String> {
+	let contents = std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+	let value = json::parse(&contents).map_err(|e| format!("Failed to parse {}: {}", path.display(), e))?;
+	let paths = |key: &str| -> Result<Vec<PathBuf>, String> {
+		match value.get(key) {
+			None => Ok(Vec::new()),
+			Some(list) => list.as_array()
+				.ok_or_else(|| format!("{}: {} must be a list", path.display(), key))?
+				.iter()
+				.map(|item| item.as_str().map(PathBuf::from).ok_or_else(|| format!("{}: {} must be strings", path.display(), key)))
+				.collect(),
+		}
+	};
+	Ok(Case {
+		root: value.get("root").and_then(|root| root.as_str()).ok_or_else(|| format!("{}: missing root", path.display()))?.into(),
+		jpaths: paths("jpaths")?,
+		expect: paths("expect")?,
+	})
+}
+
+// The file that would actually be read for path, relative to the case directory. This follows symlinks,
+// as the filesystem does, unless it doesn't exist.
+fn loaded(dir: &Path, path: &Path) -> PathBuf {
+	if let (Ok(path), Ok(dir)) = (path.canonicalize(), dir.canonicalize()) {
+		if let Ok(relative) = path.strip_prefix(&dir) {
+			return relative.to_owned();
+		}
+	}
+	normalize(path.strip_prefix(dir).unwrap_or(path))
+}
+
+fn compare(who: &str, expect: &[PathBuf], actual: &[PathBuf], failures: &mut Vec<String>) {
+	if expect.len() != actual.len() {
+		failures.push(format!("{} found {} imports, expected {}", who, actual.len(), expect.len()));
+		return;
+	}
+	for (i, (expect, actual)) in expect.iter().zip(actual).enumerate() {
+		if expect != actual {
+			failures.push(format!("{} loaded {} for import {}, expected {}", who, actual.display(), i + 1, expect.display()));
+		}
+	}
+}
+
+// Evaluate the case's root with jsonnet, and get the paths it output.
+fn run_jsonnet(jsonnet: &Path, dir: &Path, case: &Case) -> Result<Vec<PathBuf>, String> {
+	let mut command = Command::new(jsonnet);
+	command.current_dir(dir);
+	for jpath in &case.jpaths {
+		command.arg("-J").arg(jpath);
+	}
+	let output = command.arg(&case.root).output().map_err(|e| format!("Failed to run {}: {}", jsonnet.display(), e))?;
+	if !output.status.success() {
+		return Err(format!("jsonnet failed ({}): {}", output.status, String::from_utf8_lossy(&output.stderr).trim()));
+	}
+	let value = json::parse(&String::from_utf8_lossy(&output.stdout))?;
+	value.as_array().ok_or("jsonnet output was not an array")?.iter().map(|item| match item {
+		// importstr gives the whole file, which may end in a newline.
+		Value::String(path) => Ok(PathBuf::from(path.trim())),
+		_ => Err("jsonnet output was not an array of strings".to_string()),
+	}).collect()
+}
+
+// Check each case in dir and report the results, failing if any case fails.
+pub fn run(options: &Options, dir: &Path, jsonnet: Option<&Path>) -> Result<(), String> {
+	let mut case_dirs: Vec<PathBuf> = std::fs::read_dir(dir)
+		.map_err(|e| format!("Failed to read {}: {}", dir.display(), e))?
+		.map(|entry| entry.map(|entry| entry.path()).map_err(|e| format!("Failed to read {}: {}", dir.display(), e)))
+		.collect::<Result<_, _>>()?;
+	case_dirs.retain(|path| path.join(CASE_FILE).is_file());
+	case_dirs.sort();
+	if case_dirs.is_empty() {
+		return Err(format!("No cases found in {}", dir.display()));
+	}
+
+	let mut failed = 0;
+	for case_dir in &case_dirs {
+		let case = load(&case_dir.join(CASE_FILE))?;
+		let mut failures = Vec::new();
+		// Each case gets its own cache, so files with the same path in different cases can't interfere.
+		let cache = Cache::default();
+		let jpaths: Vec<PathBuf> = case.jpaths.iter().map(|jpath| case_dir.join(jpath)).collect();
+		match cache.analysis(options, &jpaths, &case_dir.join(&case.root)) {
+			Ok(analysis) => {
+				let actual: Vec<_> = analysis.targets.iter().map(|target| loaded(case_dir, target)).collect();
+				compare("jsonnet_dep_graph", &case.expect, &actual, &mut failures);
+			},
+			Err(e) => failures.push(format!("jsonnet_dep_graph failed: {}", e)),
+		}
+		// Cases testing what happens when a file doesn't exist can't be evaluated.
+		let evaluable = case.expect.iter().all(|path| case_dir.join(path).exists());
+		if let (Some(jsonnet), true) = (jsonnet, evaluable) {
+			match run_jsonnet(jsonnet, case_dir, &case) {
+				Ok(actual) => compare("jsonnet", &case.expect, &actual, &mut failures),
+				Err(e) => failures.push(e),
+			}
+		}
+		let name = case_dir.file_name().unwrap_or(case_dir.as_os_str()).to_string_lossy();
+		if failures.is_empty() {
+			println!("ok {}", name);
+		} else {
+			failed += 1;
+			println!("FAIL {}", name);
+			for failure in failures {
+				println!("\t{}", failure);
+			}
+		}
+	}
+	match failed {
+		0 => Ok(()),
+		n => Err(format!("{} of {} cases failed", n, case_dirs.len())),
+	}
+}
@@ -0,0 +1,217 @@
+// Writing each root's deps to its own depfile, for make to include. Depfiles whose deps haven't
+// changed are left alone, so make doesn't think everything depending on them is out of date.
+
+use crate::resolve::normalize;
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Component, Path, PathBuf};
+
+// Where to put each root's depfile.
+pub enum Location {
+	// FILE.d, alongside FILE, like gcc -MD.
+	NextToRoot,
+	// DIR/FILE.d.
+	Under(PathBuf),
+}
+
+impl std::fmt::Display for Location {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match self {
+			Location::NextToRoot => write!(f, "alongside their roots"),
+			Location::Under(dir) => write!(f, "in {}", dir.display()),
+		}
+	}
+}
+
+// Where the depfile for root goes: the root's path with ".d" appended, under the directory if there is one.
+pub fn path(location: &Location, root: &Path) -> Result<PathBuf, String> {
+	let dir = match location {
+		Location::NextToRoot => {
+			let mut name = root.as_os_str().to_owned();
+			name.push(".d");
+			return Ok(name.into());
+		},
+		Location::Under(dir) => dir,
+	};
+	let root = normalize(root);
+	let relative: PathBuf = root.components().filter(|c| !matches!(c, Component::RootDir | Component::Prefix(_))).collect();
+	if relative.components().any(|c| c == Component::ParentDir) {
+		return Err(format!("Can't put a depfile for {} under {}, as it's outside the current directory", root.display(), dir.display()));
+	}
+	let mut name = dir.join(relative).into_os_string();
+	name.push(".d");
+	Ok(name.into())
+}
+
+// Check that no two roots would have their output written to the same file, eg. because their names only differ
+// in ways that are lost in the depfile's path, as the file would end up with whichever was written last.
+// The same root given twice is fine, as both would write the same thing.
+pub fn check_collisions<'a>(outputs: impl Iterator<Item = (&'a Path, PathBuf)>) -> Result<(), String> {
+	let mut roots: HashMap<PathBuf, &Path> = HashMap::new();
+	for (root, output) in outputs {
+		let output = normalize(&output);
+		match roots.get(&output) {
+			Some(other) if normalize(other) != normalize(root) => {
+				return Err(format!("{} and {} would both be written to {}", other.display(), root.display(), output.display()));
+			},
+			Some(_) => (),
+			None => { roots.insert(output, root); },
+		}
+	}
+	Ok(())
+}
+
+// The contents of the depfile, or None if there isn't one yet.
+pub fn read(path: &Path) -> Result<Option<String>, String> {
+	match std::fs::read_to_string(path) {
+		Ok(contents) => Ok(Some(contents)),
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+		Err(e) => Err(format!("Failed to read {}: {}", path.display(), e)),
+	}
+}
+
+// Write the contents to the depfile, unless it already has them. Returns whether it was written.
+pub fn patch(path: &Path, contents: &str) -> Result<bool, String> {
+	if read(path)?.as_deref() == Some(contents) {
+		return Ok(false);
+	}
+	if let Some(parent) = path.parent() {
+		std::fs::create_dir_all(parent).map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+	}
+	write(path, contents.as_bytes())?;
+	Ok(true)
+}
+
+// Replace the file with the contents by writing them alongside it then renaming over it, so make (or anything else)
+// never sees a partly written file, even if we're killed halfway through. The temporary name includes our pid,
+// so two runs writing the same file don't write over each other's half-written copies.
+pub fn write(path: &Path, contents: &[u8]) -> Result<(), String> {
+	let mut tmp = path.as_os_str().to_owned();
+	tmp.push(format!(".{}.tmp", std::process::id()));
+	let tmp = PathBuf::from(tmp);
+	std::fs::write(&tmp, contents).map_err(|e| format!("Failed to write {}: {}", tmp.display(), e))?;
+	std::fs::rename(&tmp, path).map_err(|e| {
+		let _ = std::fs::remove_file(&tmp);
+		format!("Failed to rename {} to {}: {}", tmp.display(), path.display(), e)
+	})
+}
+
+// The deps listed in the first rule of a depfile, in either format, ignoring any comments and the rules after it,
+// eg. from --phony. Escapes are undone as make would: a space or # after an odd number of backslashes is part of
+// the path, with the backslashes before it halved, $$ is a $, and a backslash before a newline continues the line.
+// Before output version 2, paths were written without escaping, so a path containing a space is split into words.
+// Where a word doesn't exist but it and the words after it do, joined by single spaces, they're taken to be one path.
+pub fn parse_deps(contents: &str, exists: impl Fn(&Path) -> bool) -> BTreeSet<PathBuf> {
+	// Each word in the deps, and whether it came after a single space.
+	let mut words: Vec<(String, bool)> = Vec::new();
+	let mut word = String::new();
+	let mut in_deps = false;
+	// The whitespace since the last word.
+	let mut gap = String::new();
+	let mut end_word = |word: &mut String, gap: &mut String, in_deps: bool| {
+		if !word.is_empty() {
+			if in_deps {
+				words.push((std::mem::take(word), gap == " "));
+			}
+			word.clear();
+			gap.clear();
+		}
+	};
+	let mut chars = contents.chars().peekable();
+	while let Some(c) = chars.next() {
+		match c {
+			'\\' => {
+				let mut backslashes = 1;
+				while chars.next_if_eq(&'\\').is_some() {
+					backslashes += 1;
+				}
+				match chars.peek().copied() {
+					Some(c @ (' ' | '#')) if backslashes % 2 == 1 => {
+						word.extend(std::iter::repeat_n('\\', backslashes / 2));
+						word.push(c);
+						chars.next();
+					},
+					Some('\n') if backslashes % 2 == 1 => {
+						word.extend(std::iter::repeat_n('\\', backslashes / 2));
+						chars.next();
+						end_word(&mut word, &mut gap, in_deps);
+						gap.push('\n');
+					},
+					Some(' ') => word.extend(std::iter::repeat_n('\\', backslashes / 2)),
+					_ => word.extend(std::iter::repeat_n('\\', backslashes)),
+				}
+			},
+			'$' if chars.next_if_eq(&'$').is_some() => word.push('$'),
+			':' if !in_deps => {
+				in_deps = true;
+				word.clear();
+				gap.clear();
+			},
+			' ' | '\t' => {
+				end_word(&mut word, &mut gap, in_deps);
+				gap.push(c);
+			},
+			// A comment runs to the end of the line. Once in the deps, a # is taken as part of a path, as before output
+			// version 2 they weren't escaped, and we stop at the end of the line anyway.
+			'#' if !in_deps => {
+				word.clear();
+				while chars.next_if(|c| *c != '\n').is_some() {}
+			},
+			'\n' => {
+				end_word(&mut word, &mut gap, in_deps);
+				if in_deps {
+					break;
+				}
+			},
+			c => word.push(c),
+		}
+	}
+	end_word(&mut word, &mut gap, in_deps);
+
+	// How many words to try joining at most, so a line of deps that don't exist doesn't take a stat for every pair.
+	const MAX_JOINED: usize = 8;
+	let mut deps = BTreeSet::new();
+	let mut i = 0;
+	while i < words.len() {
+		let mut end = i + 1;
+		if !exists(Path::new(&words[i].0)) {
+			let mut joined = words[i].0.clone();
+			for (j, (word, single_space)) in words.iter().enumerate().skip(i + 1).take(MAX_JOINED - 1) {
+				if !single_space {
+					break;
+				}
+				joined.push(' ');
+				joined.push_str(word);
+				if exists(Path::new(&joined)) {
+					end = j + 1;
+					break;
+				}
+			}
+		}
+		let path: Vec<&str> = words[i..end].iter().map(|(word, _)| word.as_str()).collect();
+		deps.insert(PathBuf::from(path.join(" ")));
+		i = end;
+	}
+	deps
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn deps(contents: &str, existing: &[&str]) -> Vec<String> {
+		parse_deps(contents, |path| existing.iter().any(|e| Path::new(e) == path))
+			.into_iter().map(|path| path.display().to_string()).collect()
+	}
+
+	#[test]
+	fn escapes_and_continuations() {
+		let contents = "# generated\nout.json: my\\ file.txt c$$d\\#.txt \\\\\\ x \\\n  next.libsonnet\nmy\\ file.txt:\n# unresolved: gen.txt\n";
+		assert_eq!(deps(contents, &[]), ["\\ x", "c$d#.txt", "my file.txt", "next.libsonnet"]);
+	}
+
+	#[test]
+	fn unescaped_spaces_before_version_2() {
+		let contents = "main.jsonnet: a.libsonnet my file.txt c$d#.txt  b.libsonnet\n";
+		assert_eq!(deps(contents, &["my file.txt"]), ["a.libsonnet", "b.libsonnet", "c$d#.txt", "my file.txt"]);
+	}
+}
@@ -0,0 +1,255 @@
+// Output formats for rendering the import graph as a diagram.
+
+use crate::graph::Graph;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Diagram {
+	// A Graphviz digraph, for rendering with eg. `dot -Tsvg`.
+	#[cfg(feature = "visualization")]
+	Dot,
+	// A Mermaid flowchart, for embedding in Markdown docs.
+	#[cfg(feature = "visualization")]
+	Mermaid,
+	// GraphML, for loading into graph tools such as yEd or Gephi.
+	#[cfg(feature = "visualization")]
+	GraphMl,
+	// A PlantUML component diagram, with a package for each directory.
+	#[cfg(feature = "visualization")]
+	PlantUml,
+	// A d2 diagram, with a container for each directory.
+	#[cfg(feature = "visualization")]
+	D2,
+}
+
+// Every diagram format, including any left out of this build.
+const NAMES: &[&str] = &["dot", "mermaid", "graphml", "plantuml", "d2"];
+
+impl Diagram {
+	// None if name isn't a diagram format at all.
+	pub fn parse(name: &str) -> Result<Option<Diagram>, String> {
+		match name {
+			#[cfg(feature = "visualization")]
+			"dot" => Ok(Some(Diagram::Dot)),
+			#[cfg(feature = "visualization")]
+			"mermaid" => Ok(Some(Diagram::Mermaid)),
+			#[cfg(feature = "visualization")]
+			"graphml" => Ok(Some(Diagram::GraphMl)),
+			#[cfg(feature = "visualization")]
+			"plantuml" => Ok(Some(Diagram::PlantUml)),
+			#[cfg(feature = "visualization")]
+			"d2" => Ok(Some(Diagram::D2)),
+			_ if NAMES.contains(&name) => Err(crate::missing_feature(&format!("--format {}", name), "visualization")),
+			_ => Ok(None),
+		}
+	}
+
+	pub fn names() -> &'static [&'static str] {
+		NAMES
+	}
+
+	pub fn name(&self) -> &'static str {
+		match *self {
+			#[cfg(feature = "visualization")]
+			Diagram::Dot => "dot",
+			#[cfg(feature = "visualization")]
+			Diagram::Mermaid => "mermaid",
+			#[cfg(feature = "visualization")]
+			Diagram::GraphMl => "graphml",
+			#[cfg(feature = "visualization")]
+			Diagram::PlantUml => "plantuml",
+			#[cfg(feature = "visualization")]
+			Diagram::D2 => "d2",
+		}
+	}
+
+	#[cfg_attr(not(feature = "visualization"), allow(unused_variables))]
+	pub fn render(&self, graph: &Graph) -> String {
+		match *self {
+			#[cfg(feature = "visualization")]
+			Diagram::Dot => dot(graph),
+			#[cfg(feature = "visualization")]
+			Diagram::Mermaid => mermaid(graph),
+			#[cfg(feature = "visualization")]
+			Diagram::GraphMl => graphml(graph),
+			#[cfg(feature = "visualization")]
+			Diagram::PlantUml => plantuml(graph),
+			#[cfg(feature = "visualization")]
+			Diagram::D2 => d2(graph),
+		}
+	}
+}
+
+#[cfg(feature = "visualization")]
+fn dot_quote(s: &str) -> String {
+	format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+// Nodes are files, and edges are imports. Edges which are only importstr are dashed.
+#[cfg(feature = "visualization")]
+fn dot(graph: &Graph) -> String {
+	let leaf_edges = graph.leaf_edges();
+	let mut lines = vec!["digraph imports {".to_string()];
+	for file in graph.edges.keys() {
+		lines.push(format!("\t{};", dot_quote(&file.to_string_lossy())));
+	}
+	for (from, deps) in &graph.edges {
+		for dep in deps {
+			let style = if leaf_edges.contains(&(from.as_path(), dep.as_path())) { " [style=dashed]" } else { "" };
+			lines.push(format!("\t{} -> {}{};", dot_quote(&from.to_string_lossy()), dot_quote(&dep.to_string_lossy()), style));
+		}
+	}
+	lines.push("}".into());
+	lines.join("\n")
+}
+
+// Mermaid node IDs can't be arbitrary strings, so each file gets an ID by its position and the path as its label.
+// Quotes can't be escaped with a backslash in a label, only as an entity.
+#[cfg(feature = "visualization")]
+fn mermaid(graph: &Graph) -> String {
+	use std::collections::BTreeMap;
+	let leaf_edges = graph.leaf_edges();
+	let ids: BTreeMap<&std::path::Path, usize> = graph.edges.keys().enumerate().map(|(i, file)| (file.as_path(), i)).collect();
+	let mut lines = vec!["graph TD".to_string()];
+	for (file, id) in &ids {
+		lines.push(format!("\tn{}[\"{}\"]", id, file.to_string_lossy().replace('"', "#quot;")));
+	}
+	for (from, deps) in &graph.edges {
+		for dep in deps {
+			let arrow = if leaf_edges.contains(&(from.as_path(), dep.as_path())) { "-.->" } else { "-->" };
+			lines.push(format!("\tn{} {} n{}", ids[from.as_path()], arrow, ids[dep.as_path()]));
+		}
+	}
+	lines.join("\n")
+}
+
+#[cfg(feature = "visualization")]
+fn xml_escape(s: &str) -> String {
+	s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+// Nodes have their path, size (if the file exists) and kind: "root" if nothing imports them,
+// "importstr" if only ever imported with importstr, or "import" otherwise. Edges have the kind of import,
+// which as for dot is importstr only if the file is never imported any other way.
+#[cfg(feature = "visualization")]
+fn graphml(graph: &Graph) -> String {
+	use std::collections::{BTreeMap, BTreeSet};
+	let leaf_edges = graph.leaf_edges();
+	let mut imported_as: BTreeMap<&std::path::Path, BTreeSet<&str>> = BTreeMap::new();
+	for (from, deps) in &graph.edges {
+		for dep in deps {
+			let kind = if leaf_edges.contains(&(from.as_path(), dep.as_path())) { "importstr" } else { "import" };
+			imported_as.entry(dep).or_default().insert(kind);
+		}
+	}
+	let mut lines = vec![
+		r#"<?xml version="1.0" encoding="UTF-8"?>"#.to_string(),
+		r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#.into(),
+		r#"	<key id="path" for="node" attr.name="path" attr.type="string"/>"#.into(),
+		r#"	<key id="kind" for="node" attr.name="kind" attr.type="string"/>"#.into(),
+		r#"	<key id="size" for="node" attr.name="size" attr.type="long"/>"#.into(),
+		r#"	<key id="import" for="edge" attr.name="kind" attr.type="string"/>"#.into(),
+		r#"	<graph id="imports" edgedefault="directed">"#.into(),
+	];
+	let ids: BTreeMap<&std::path::Path, usize> = graph.edges.keys().enumerate().map(|(i, file)| (file.as_path(), i)).collect();
+	for (file, id) in &ids {
+		let kind = match imported_as.get(file) {
+			None => "root",
+			Some(kinds) if !kinds.contains("import") => "importstr",
+			Some(_) => "import",
+		};
+		let mut data = format!(r#"<data key="path">{}</data><data key="kind">{}</data>"#, xml_escape(&file.to_string_lossy()), kind);
+		if let Ok(metadata) = std::fs::metadata(file) {
+			data.push_str(&format!(r#"<data key="size">{}</data>"#, metadata.len()));
+		}
+		lines.push(format!(r#"		<node id="n{}">{}</node>"#, id, data));
+	}
+	for (from, deps) in &graph.edges {
+		for dep in deps {
+			let kind = if leaf_edges.contains(&(from.as_path(), dep.as_path())) { "importstr" } else { "import" };
+			lines.push(format!(
+				r#"		<edge source="n{}" target="n{}"><data key="import">{}</data></edge>"#,
+				ids[from.as_path()], ids[dep.as_path()], kind,
+			));
+		}
+	}
+	lines.push("\t</graph>".into());
+	lines.push("</graphml>".into());
+	lines.join("\n")
+}
+
+// Each file is a component named by its file name, in a package for its directory, with IDs as for mermaid.
+// PlantUML has no way to escape a quote in a name, so they're replaced with single quotes.
+// Edges which are only importstr are dashed, as for dot.
+#[cfg(feature = "visualization")]
+fn plantuml(graph: &Graph) -> String {
+	use std::collections::BTreeMap;
+	use std::path::Path;
+	let quote = |s: &str| format!("\"{}\"", s.replace('"', "'"));
+	let leaf_edges = graph.leaf_edges();
+	let ids: BTreeMap<&Path, usize> = graph.edges.keys().enumerate().map(|(i, file)| (file.as_path(), i)).collect();
+	let mut packages: BTreeMap<&Path, Vec<(&Path, usize)>> = BTreeMap::new();
+	for (file, id) in &ids {
+		packages.entry(file.parent().unwrap_or(Path::new(""))).or_default().push((file, *id));
+	}
+	let mut lines = vec!["@startuml".to_string()];
+	for (dir, files) in &packages {
+		let indent = if dir.as_os_str().is_empty() { "" } else { "\t" };
+		if !indent.is_empty() {
+			lines.push(format!("package {} {{", quote(&dir.to_string_lossy())));
+		}
+		for (file, id) in files {
+			let name = file.file_name().map_or_else(|| file.to_string_lossy(), |name| name.to_string_lossy());
+			lines.push(format!("{}component {} as n{}", indent, quote(&name), id));
+		}
+		if !indent.is_empty() {
+			lines.push("}".into());
+		}
+	}
+	for (from, deps) in &graph.edges {
+		for dep in deps {
+			let arrow = if leaf_edges.contains(&(from.as_path(), dep.as_path())) { "..>" } else { "-->" };
+			lines.push(format!("n{} {} n{}", ids[from.as_path()], arrow, ids[dep.as_path()]));
+		}
+	}
+	lines.push("@enduml".into());
+	lines.join("\n")
+}
+
+// As for plantuml, each file is labelled with its file name, in a container for its directory. Files and
+// containers have IDs by position, as in d2 a . in an ID would nest it. A file in a container is referred
+// to by both IDs, eg. d0.n1. Edges which are only importstr are dashed, as for dot.
+#[cfg(feature = "visualization")]
+fn d2(graph: &Graph) -> String {
+	use std::collections::BTreeMap;
+	use std::path::Path;
+	let quote = |s: &str| format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""));
+	let leaf_edges = graph.leaf_edges();
+	let mut packages: BTreeMap<&Path, Vec<&Path>> = BTreeMap::new();
+	for file in graph.edges.keys() {
+		packages.entry(file.parent().unwrap_or(Path::new(""))).or_default().push(file);
+	}
+	let mut ids: BTreeMap<&Path, String> = BTreeMap::new();
+	let mut lines = Vec::new();
+	for (i, (dir, files)) in packages.iter().enumerate() {
+		let in_container = !dir.as_os_str().is_empty();
+		if in_container {
+			lines.push(format!("d{}: {} {{", i, quote(&dir.to_string_lossy())));
+		}
+		for file in files {
+			let id = format!("n{}", ids.len());
+			let name = file.file_name().map_or_else(|| file.to_string_lossy(), |name| name.to_string_lossy());
+			lines.push(format!("{}{}: {}", if in_container { "\t" } else { "" }, id, quote(&name)));
+			ids.insert(file, if in_container { format!("d{}.{}", i, id) } else { id });
+		}
+		if in_container {
+			lines.push("}".into());
+		}
+	}
+	for (from, deps) in &graph.edges {
+		for dep in deps {
+			let style = if leaf_edges.contains(&(from.as_path(), dep.as_path())) { ": {style.stroke-dash: 3}" } else { "" };
+			lines.push(format!("{} -> {}{}", ids[from.as_path()], ids[dep.as_path()], style));
+		}
+	}
+	lines.join("\n")
+}
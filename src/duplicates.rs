@@ -0,0 +1,73 @@
+use crate::{root_files, Cache, Options, Root};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::path::PathBuf;
+
+// Find the shortest chain of imports from the root to each of its deps.
+// Each dep maps to the file that imports it, or None for the files we started from.
+fn import_tree(options: &Options, cache: &Cache, root: &Root) -> Result<HashMap<PathBuf, Option<PathBuf>>, String> {
+	let mut parents = HashMap::new();
+	let mut to_expand = VecDeque::new();
+	for file in root_files(options, &root.path) {
+		if !parents.contains_key(&file) {
+			parents.insert(file.clone(), None);
+			to_expand.push_back(file);
+		}
+	}
+	for file in &options.str_files {
+		parents.entry(file.clone()).or_insert(None);
+	}
+	// Breadth-first, so the first chain we find to each file is the shortest.
+	while let Some(file) = to_expand.pop_front() {
+		let analysis = cache.analysis(options, &root.jpaths, &file)?;
+		for dep in &analysis.leaf_deps {
+			parents.entry(dep.clone()).or_insert_with(|| Some(file.clone()));
+		}
+		for dep in &analysis.deep_deps {
+			if !parents.contains_key(dep) {
+				parents.insert(dep.clone(), Some(file.clone()));
+				to_expand.push_back(dep.clone());
+			}
+		}
+	}
+	Ok(parents)
+}
+
+fn chain(parents: &HashMap<PathBuf, Option<PathBuf>>, file: &PathBuf) -> String {
+	let mut chain = vec![file.to_string_lossy()];
+	let mut file = file;
+	while let Some(Some(parent)) = parents.get(file) {
+		chain.push(parent.to_string_lossy());
+		file = parent;
+	}
+	chain.reverse();
+	chain.join(" -> ")
+}
+
+// Print each set of distinct files in the root's deps which have identical contents,
+// along with the chain of imports that reaches each copy. Returns how many sets were found.
+pub fn report(options: &Options, cache: &Cache, root: &Root) -> Result<usize, String> {
+	let parents = import_tree(options, cache, root)?;
+	let mut by_hash: BTreeMap<String, Vec<&PathBuf>> = BTreeMap::new();
+	for file in parents.keys() {
+		let contents = match std::fs::read(file) {
+			Ok(contents) => contents,
+			// Leaf deps need not exist, and if they don't they can't be duplicates.
+			Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+			Err(e) => return Err(format!("Failed to read {}: {}", file.display(), e)),
+		};
+		by_hash.entry(crate::sha256::hex_digest(&contents)).or_default().push(file);
+	}
+	let mut found = 0;
+	for (hash, mut files) in by_hash {
+		if files.len() < 2 {
+			continue;
+		}
+		found += 1;
+		files.sort();
+		println!("{}: {} copies of {}", root.path.display(), files.len(), hash);
+		for file in files {
+			println!("\t{}", chain(&parents, file));
+		}
+	}
+	Ok(found)
+}
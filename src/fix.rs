@@ -0,0 +1,147 @@
+// Applies fixes to files as edits to byte ranges, using the spans recorded while scanning,
+// either writing the result back or showing it as a unified diff.
+
+use std::path::Path;
+
+// Lines of context to show around each change in a diff.
+const CONTEXT: usize = 3;
+
+// A change to a file, replacing the bytes from start to end.
+pub struct Edit {
+	pub start: usize,
+	pub end: usize,
+	pub replacement: String,
+}
+
+// Apply the edits to the contents. An edit which overlaps one before it is skipped, as applying both
+// would likely give nonsense. Returns the new contents, the edits that were applied, and for each edit
+// whether it was applied.
+fn apply<'a>(contents: &str, edits: &[&'a Edit]) -> (String, Vec<&'a Edit>, Vec<bool>) {
+	let mut order: Vec<usize> = (0..edits.len()).collect();
+	order.sort_by_key(|&i| (edits[i].start, edits[i].end));
+	let mut applied = vec![false; edits.len()];
+	let mut kept = Vec::new();
+	let mut result = String::new();
+	let mut offset = 0;
+	for i in order {
+		let edit = edits[i];
+		if edit.start < offset || edit.end > contents.len() {
+			continue;
+		}
+		result.push_str(&contents[offset..edit.start]);
+		result.push_str(&edit.replacement);
+		offset = edit.end;
+		applied[i] = true;
+		kept.push(edit);
+	}
+	result.push_str(&contents[offset..]);
+	(result, kept, applied)
+}
+
+fn push_line(diff: &mut String, prefix: char, line: &str) {
+	diff.push(prefix);
+	diff.push_str(line.strip_suffix('\n').unwrap_or(line));
+	diff.push('\n');
+	if !line.ends_with('\n') {
+		diff.push_str("\\ No newline at end of file\n");
+	}
+}
+
+// A run of lines changed by one or more edits, as (first line, last line, new lines).
+type Block = (usize, usize, Vec<String>);
+
+// Show the effect of the edits, which must be sorted and not overlap, as a unified diff.
+// We know exactly which lines each edit changes, so there's no need for a general diff algorithm.
+fn unified_diff(path: &Path, contents: &str, edits: &[&Edit]) -> String {
+	let lines: Vec<&str> = contents.split_inclusive('\n').collect();
+	if lines.is_empty() || edits.is_empty() {
+		return String::new();
+	}
+	let mut starts = Vec::with_capacity(lines.len());
+	let mut offset = 0;
+	for line in &lines {
+		starts.push(offset);
+		offset += line.len();
+	}
+	let line_of = |offset: usize| starts.partition_point(|&start| start <= offset).saturating_sub(1);
+
+	// Group edits that touch the same lines, and work out what those lines become.
+	let mut blocks: Vec<(usize, usize, Vec<&Edit>)> = Vec::new();
+	for edit in edits {
+		let first = line_of(edit.start);
+		let last = line_of(edit.end.max(edit.start + 1) - 1).max(first);
+		match blocks.last_mut() {
+			Some(block) if first <= block.1 => {
+				block.1 = block.1.max(last);
+				block.2.push(edit);
+			},
+			_ => blocks.push((first, last, vec![edit])),
+		}
+	}
+	let blocks: Vec<Block> = blocks.into_iter().map(|(first, last, edits)| {
+		let region_start = starts[first];
+		let region_end = starts.get(last + 1).copied().unwrap_or(contents.len());
+		let mut new = String::new();
+		let mut offset = region_start;
+		for edit in edits {
+			new.push_str(&contents[offset..edit.start]);
+			new.push_str(&edit.replacement);
+			offset = edit.end;
+		}
+		new.push_str(&contents[offset..region_end]);
+		(first, last, new.split_inclusive('\n').map(String::from).collect())
+	}).collect();
+
+	// Then group blocks close enough together that their context would overlap into hunks.
+	let mut hunks: Vec<Vec<&Block>> = Vec::new();
+	for block in &blocks {
+		match hunks.last_mut() {
+			Some(hunk) if block.0 - hunk.last().unwrap().1 <= 2 * CONTEXT + 1 => hunk.push(block),
+			_ => hunks.push(vec![block]),
+		}
+	}
+
+	let mut diff = format!("--- a/{}\n+++ b/{}\n", path.display(), path.display());
+	// How many more lines the new file has than the old one, before the current hunk.
+	let mut delta: isize = 0;
+	for hunk in hunks {
+		let old_start = hunk[0].0.saturating_sub(CONTEXT);
+		let old_end = (hunk.last().unwrap().1 + CONTEXT).min(lines.len() - 1);
+		let old_count = old_end - old_start + 1;
+		let hunk_delta: isize = hunk.iter().map(|(first, last, new)| new.len() as isize - (last - first + 1) as isize).sum();
+		let new_count = (old_count as isize + hunk_delta) as usize;
+		let new_start = old_start as isize + delta;
+		diff.push_str(&format!("@@ -{},{} +{},{} @@\n", old_start + 1, old_count, new_start + 1, new_count));
+		let mut line = old_start;
+		for (first, last, new) in hunk {
+			for context in &lines[line..*first] {
+				push_line(&mut diff, ' ', context);
+			}
+			for old in &lines[*first..=*last] {
+				push_line(&mut diff, '-', old);
+			}
+			for new in new {
+				push_line(&mut diff, '+', new);
+			}
+			line = last + 1;
+		}
+		for context in &lines[line..=old_end] {
+			push_line(&mut diff, ' ', context);
+		}
+		delta += hunk_delta;
+	}
+	diff
+}
+
+// Apply the edits to the file, or if dry_run is set, print a diff of what would change instead.
+// Returns whether each edit was (or would be) applied.
+pub fn apply_file(path: &Path, edits: &[&Edit], dry_run: bool) -> Result<Vec<bool>, String> {
+	let contents = std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+	let (result, kept, applied) = apply(&contents, edits);
+	if dry_run {
+		print!("{}", unified_diff(path, &contents, &kept));
+	} else if result != contents {
+		std::fs::write(path, result).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+	}
+	Ok(applied)
+}
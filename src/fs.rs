@@ -0,0 +1,158 @@
+// Filesystem operations on network and FUSE filesystems occasionally fail for no lasting reason,
+// so we retry those which fail with errors that may be transient.
+// We also limit how many operations happen at once, so that parallel analysis doesn't run out
+// of file descriptors, and optionally how many happen per second, so as not to hog a shared filesystem.
+
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+// EIO has the same value on every unix.
+const EIO: i32 = 5;
+
+// File descriptors to leave free for everything else, eg. stdio, output files and child processes.
+const RESERVED_FILES: usize = 32;
+
+fn is_transient(e: &Error) -> bool {
+	matches!(e.kind(), ErrorKind::Interrupted | ErrorKind::WouldBlock | ErrorKind::StaleNetworkFileHandle)
+		|| e.raw_os_error() == Some(EIO)
+}
+
+// The soft limit on open files for this process, or None if there isn't one or we can't tell.
+// We read this from /proc to avoid needing libc, so it's only detected on Linux.
+fn open_file_limit() -> Option<usize> {
+	let limits = std::fs::read_to_string("/proc/self/limits").ok()?;
+	// The line looks like "Max open files            1024                 4096                 files"
+	let line = limits.lines().find(|line| line.starts_with("Max open files"))?;
+	line["Max open files".len()..].split_whitespace().next()?.parse().ok()
+}
+
+pub struct Budget {
+	// How many times to retry an operation that fails with a transient error.
+	pub retries: u32,
+	// How many operations may be in progress at once, across all threads.
+	pub max_open: usize,
+	// How many operations may start per second, across all threads, or None for no limit.
+	pub ops_per_sec: Option<u32>,
+	in_use: Mutex<usize>,
+	freed: Condvar,
+	// When the next operation may start, if we're limiting the rate.
+	next_start: Mutex<Option<Instant>>,
+	// How much we've read and checked so far, for --resource-summary.
+	reads: AtomicUsize,
+	bytes_read: AtomicU64,
+	stats: AtomicUsize,
+}
+
+impl Budget {
+	// Allow as many operations at once as the open file limit allows, leaving some spare.
+	pub fn new(retries: u32) -> Budget {
+		let max_open = open_file_limit().map_or(usize::MAX, |limit| limit.saturating_sub(RESERVED_FILES).max(1));
+		Budget {
+			retries,
+			max_open,
+			ops_per_sec: None,
+			in_use: Mutex::new(0),
+			freed: Condvar::new(),
+			next_start: Mutex::new(None),
+			reads: AtomicUsize::new(0),
+			bytes_read: AtomicU64::new(0),
+			stats: AtomicUsize::new(0),
+		}
+	}
+
+	// Read the file within the budget, as with run.
+	pub fn read_to_string(&self, path: &Path) -> Result<String, Error> {
+		let contents = self.run(|| std::fs::read_to_string(path))?;
+		self.reads.fetch_add(1, Ordering::Relaxed);
+		self.bytes_read.fetch_add(contents.len() as u64, Ordering::Relaxed);
+		Ok(contents)
+	}
+
+	pub fn read(&self, path: &Path) -> Result<Vec<u8>, Error> {
+		let contents = self.run(|| std::fs::read(path))?;
+		self.reads.fetch_add(1, Ordering::Relaxed);
+		self.bytes_read.fetch_add(contents.len() as u64, Ordering::Relaxed);
+		Ok(contents)
+	}
+
+	// Check whether the path exists within the budget, as with run.
+	pub fn exists(&self, path: &Path) -> Result<bool, Error> {
+		self.stats.fetch_add(1, Ordering::Relaxed);
+		self.run(|| path.try_exists())
+	}
+
+	// The file's metadata, following symlinks, within the budget, as with run.
+	pub fn metadata(&self, path: &Path) -> Result<std::fs::Metadata, Error> {
+		self.stats.fetch_add(1, Ordering::Relaxed);
+		self.run(|| std::fs::metadata(path))
+	}
+
+	// The path with every symlink resolved, within the budget, as with run.
+	pub fn canonicalize(&self, path: &Path) -> Result<PathBuf, Error> {
+		self.stats.fetch_add(1, Ordering::Relaxed);
+		self.run(|| std::fs::canonicalize(path))
+	}
+
+	// How many files have been read, how many bytes that came to, and how many paths have been checked.
+	pub fn usage(&self) -> (usize, u64, usize) {
+		(self.reads.load(Ordering::Relaxed), self.bytes_read.load(Ordering::Relaxed), self.stats.load(Ordering::Relaxed))
+	}
+
+	// Run the operation once there's room in the budget, retrying if it fails with a transient error.
+	// We wait 100ms before the first retry, doubling each time up to 5s.
+	// If it's still failing after all the retries, the error says so, to distinguish a persistent
+	// problem from one that isn't worth retrying at all (eg. the file doesn't exist).
+	pub fn run<T>(&self, mut op: impl FnMut() -> Result<T, Error>) -> Result<T, Error> {
+		let mut delay = Duration::from_millis(100);
+		let mut attempt = 0;
+		loop {
+			self.throttle();
+			let result = self.with_permit(&mut op);
+			match result {
+				Err(e) if is_transient(&e) && attempt < self.retries => {
+					std::thread::sleep(delay);
+					delay = (delay * 2).min(Duration::from_secs(5));
+					attempt += 1;
+				},
+				Err(e) if is_transient(&e) && self.retries > 0 => {
+					return Err(Error::new(e.kind(), format!("{} (still failing after {} attempts)", e, attempt + 1)));
+				},
+				result => return result,
+			}
+		}
+	}
+
+	// Wait until it's time for the next operation under ops_per_sec. Each operation reserves the next slot
+	// as it comes in, so threads take turns rather than all waking at once. Work that doesn't touch the
+	// filesystem, eg. parsing files already read, carries on in the meantime.
+	fn throttle(&self) {
+		let Some(ops_per_sec) = self.ops_per_sec else {
+			return;
+		};
+		let now = Instant::now();
+		let start = {
+			let mut next_start = self.next_start.lock().unwrap();
+			let start = next_start.map_or(now, |next| next.max(now));
+			*next_start = Some(start + Duration::from_secs(1) / ops_per_sec);
+			start
+		};
+		std::thread::sleep(start - now);
+	}
+
+	fn with_permit<T>(&self, op: impl FnOnce() -> T) -> T {
+		let mut in_use = self.in_use.lock().unwrap();
+		while *in_use >= self.max_open {
+			in_use = self.freed.wait(in_use).unwrap();
+		}
+		*in_use += 1;
+		// Don't hold the lock during the operation, or only one could happen at a time.
+		drop(in_use);
+		let result = op();
+		*self.in_use.lock().unwrap() -= 1;
+		self.freed.notify_one();
+		result
+	}
+}
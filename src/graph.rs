@@ -0,0 +1,312 @@
+use crate::json::{self, Value};
+use crate::resolve::{normalize, relative};
+use crate::scan::{ImportKind, Span};
+use crate::{closure_analyses, Cache, Deadline, Importers, Options, Root};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::path::{Component, Path, PathBuf};
+
+// The import graph of a set of roots: every file in their deps, and the files each one imports.
+// Leaf deps are included with no imports of their own.
+#[derive(Default)]
+pub struct Graph {
+	pub edges: BTreeMap<PathBuf, BTreeSet<PathBuf>>,
+	// Each import expression behind the edges, in order of the importing file then where it is in that file.
+	pub imports: Vec<ImportSite>,
+}
+
+// Where a file imports another.
+pub struct ImportSite {
+	pub from: PathBuf,
+	pub to: PathBuf,
+	pub kind: ImportKind,
+	// As written in the import.
+	pub path: PathBuf,
+	pub span: Span,
+}
+
+pub fn build(options: &Options, cache: &Cache, roots: &[Root]) -> Result<Graph, String> {
+	let mut graph = Graph::default();
+	// A file may be in the deps of more than one root. Its imports are only recorded again if one of them
+	// resolves differently with that root's jpaths.
+	let mut seen = HashSet::new();
+	for root in roots {
+		let analyses = closure_analyses(options, cache, Deadline::new(&root.path), &root.jpaths, &root.path)?;
+		// With --index, a file's analysis can come from the index, so this may be the first time it's parsed.
+		let importers = Importers::of_closure(options, &analyses);
+		for (file, analysis) in analyses {
+			let imports = cache.parsed(options, &file).map_err(|e| importers.chain(options, &file, e))?;
+			for (import, target) in imports.iter().zip(analysis.targets.iter()) {
+				if seen.insert((file.clone(), import.span.start, target.clone())) {
+					graph.imports.push(ImportSite {
+						from: file.clone(),
+						to: target.clone(),
+						kind: import.kind,
+						path: import.path.clone(),
+						span: import.span,
+					});
+				}
+			}
+			for dep in analysis.leaf_deps.iter().chain(analysis.deep_deps.iter()) {
+				graph.edges.entry(dep.clone()).or_default();
+			}
+			graph.edges.entry(file).or_default().extend(
+				analysis.leaf_deps.iter().chain(analysis.deep_deps.iter()).cloned()
+			);
+		}
+	}
+	for file in &options.str_files {
+		graph.edges.entry(file.clone()).or_default();
+	}
+	graph.imports.sort_by(|a, b| a.from.cmp(&b.from).then(a.span.start.cmp(&b.span.start)));
+	Ok(graph)
+}
+
+// What's at the top of a repository or workspace.
+const TOP_MARKERS: [&str; 4] = [".git", "WORKSPACE", "WORKSPACE.bazel", "MODULE.bazel"];
+
+// The directory that file IDs are made from paths relative to: --relative-to if it was given, or else the top of the
+// repository or workspace the current directory is in, so IDs don't depend on which directory in it we're run from.
+// Outside of any, it's the current directory. cwd must be absolute.
+pub fn id_base(relative_to: Option<&Path>, cwd: &Path) -> PathBuf {
+	if let Some(dir) = relative_to {
+		return dir.to_owned();
+	}
+	cwd.ancestors()
+		.find(|dir| TOP_MARKERS.iter().any(|marker| dir.join(marker).exists()))
+		.unwrap_or(cwd)
+		.to_owned()
+}
+
+// Replace each name in the path with the start of its SHA-256, keeping any extension, eg. "lib/foo.libsonnet"
+// becomes "76b5a3573912/2c26b46b68ff.libsonnet". The same name always gives the same hash, so the structure
+// of the graph is kept, including which imports are relative to which files.
+pub fn redact_path(path: &Path) -> PathBuf {
+	path.components().map(|component| match component {
+		Component::Normal(name) => {
+			let name = name.to_string_lossy();
+			let (stem, extension) = match name.rfind('.') {
+				Some(i) if i > 0 => name.split_at(i),
+				_ => (name.as_ref(), ""),
+			};
+			let hash = crate::sha256::hex_digest(stem.as_bytes());
+			format!("{}{}", &hash[..12], extension).into()
+		},
+		component => component.as_os_str().to_owned(),
+	}).collect()
+}
+
+impl Graph {
+	// Every file which can be reached from the given files by following edges forwards,
+	// or backwards if reverse is set, including the given files.
+	pub fn reachable(&self, files: &[&Path], reverse: bool) -> BTreeSet<PathBuf> {
+		let reversed;
+		let edges = if reverse {
+			let mut edges: BTreeMap<PathBuf, BTreeSet<PathBuf>> = BTreeMap::new();
+			for (from, deps) in &self.edges {
+				for dep in deps {
+					edges.entry(dep.clone()).or_default().insert(from.clone());
+				}
+			}
+			reversed = edges;
+			&reversed
+		} else {
+			&self.edges
+		};
+		let mut reached = BTreeSet::new();
+		let mut to_expand: Vec<PathBuf> = files.iter().map(|file| file.to_path_buf()).collect();
+		while let Some(file) = to_expand.pop() {
+			if reached.contains(&file) {
+				continue;
+			}
+			if let Some(deps) = edges.get(&file) {
+				to_expand.extend(deps.iter().cloned());
+			}
+			reached.insert(file);
+		}
+		reached
+	}
+
+	// The same graph, with every path redacted as by redact_path.
+	pub fn redact(self) -> Graph {
+		Graph {
+			edges: self.edges.into_iter().map(|(from, deps)| {
+				(redact_path(&from), deps.iter().map(|dep| redact_path(dep)).collect())
+			}).collect(),
+			imports: self.imports.into_iter().map(|import| ImportSite {
+				from: redact_path(&import.from),
+				to: redact_path(&import.to),
+				path: redact_path(&import.path),
+				..import
+			}).collect(),
+		}
+	}
+
+	// The SHA-256 of the contents of each file in the graph. Files which don't exist are left out.
+	pub fn hashes(&self, fs: &crate::fs::Budget) -> Result<BTreeMap<PathBuf, String>, String> {
+		let mut hashes = BTreeMap::new();
+		for file in self.edges.keys() {
+			match fs.read(file) {
+				Ok(contents) => { hashes.insert(file.clone(), crate::sha256::hex_digest(&contents)); },
+				Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+				Err(e) => return Err(format!("Failed to read {}: {}", file.display(), e)),
+			}
+		}
+		Ok(hashes)
+	}
+
+	// An ID for each file which is the same wherever the tree is checked out, so snapshots taken in different places
+	// can be joined on it. It's the start of the SHA-256 of the file's path relative to base (see id_base), along with
+	// the hash of its contents if it has one, so it also changes when the file does.
+	pub fn ids(&self, hashes: &BTreeMap<PathBuf, String>, cwd: &Path, base: &Path) -> BTreeMap<PathBuf, String> {
+		self.edges.keys().map(|file| {
+			// Both are absolute, so there's always a way from one to the other.
+			let relative = relative(base, &normalize(&cwd.join(file))).expect("base and file are absolute");
+			let key = format!("{}\0{}", relative.to_string_lossy(), hashes.get(file).map_or("", |hash| hash.as_str()));
+			(file.clone(), crate::sha256::hex_digest(key.as_bytes())[..16].to_string())
+		}).collect()
+	}
+
+	// The edges where the file is only ever imported with importstr. Graphs loaded from snapshots
+	// that predate recording imports have none.
+	#[cfg(feature = "visualization")]
+	pub fn leaf_edges(&self) -> BTreeSet<(&Path, &Path)> {
+		let mut leaf = BTreeSet::new();
+		let mut code = BTreeSet::new();
+		for import in &self.imports {
+			let edge = (import.from.as_path(), import.to.as_path());
+			match import.kind {
+				ImportKind::ImportStr => leaf.insert(edge),
+				ImportKind::Import => code.insert(edge),
+			};
+		}
+		leaf.retain(|edge| !code.contains(edge));
+		leaf
+	}
+
+	// Restrict the graph to the files which the given file depends on, or which depend on it.
+	pub fn scope(self, file: &Path) -> Result<Graph, String> {
+		if !self.edges.contains_key(file) {
+			return Err(format!("--scope file {} is not in the deps of any root", file.display()));
+		}
+		let mut keep = self.reachable(&[file], false);
+		keep.extend(self.reachable(&[file], true));
+		let edges = self.edges.into_iter()
+			.filter(|(from, _)| keep.contains(from))
+			.map(|(from, deps)| {
+				let deps = deps.into_iter().filter(|dep| keep.contains(dep)).collect();
+				(from, deps)
+			})
+			.collect();
+		let imports = self.imports.into_iter()
+			.filter(|import| keep.contains(&import.from) && keep.contains(&import.to))
+			.collect();
+		Ok(Graph { edges, imports })
+	}
+}
+
+// A graph previously output with --format json, along with the hashes of its files.
+pub struct Snapshot {
+	pub graph: Graph,
+	pub hashes: BTreeMap<PathBuf, String>,
+}
+
+pub fn load(path: &Path) -> Result<Snapshot, String> {
+	let contents = std::fs::read_to_string(path).map_err(|e|
+		format!("Failed to read {}: {}", path.display(), e)
+	)?;
+	let bad = |e: &str| format!("Bad graph {}: {}", path.display(), e);
+	let value = json::parse(&contents).map_err(|e| bad(&e))?;
+	let paths = |value: Option<&Value>| -> Result<Vec<PathBuf>, String> {
+		value.and_then(|v| v.as_array())
+			.ok_or_else(|| bad("expected an array"))?
+			.iter()
+			.map(|path| path.as_str().map(PathBuf::from).ok_or_else(|| bad("expected a string")))
+			.collect()
+	};
+	let mut graph = Graph::default();
+	for node in paths(value.get("nodes"))? {
+		graph.edges.entry(node).or_default();
+	}
+	for edge in value.get("edges").and_then(|v| v.as_array()).ok_or_else(|| bad("missing edges"))? {
+		match paths(Some(edge))?.as_slice() {
+			[from, to] => { graph.edges.entry(from.clone()).or_default().insert(to.clone()); },
+			_ => return Err(bad("expected each edge to be a pair of paths")),
+		}
+	}
+	// Older snapshots don't have imports.
+	for import in value.get("imports").and_then(|v| v.as_array()).unwrap_or_default() {
+		let path = |key| import.get(key).and_then(|v| v.as_str()).map(PathBuf::from).ok_or_else(|| bad(&format!("missing or bad import {}", key)));
+		let number = |key| match import.get(key) {
+			Some(Value::Number(n)) if *n >= 0.0 => Ok(*n as usize),
+			_ => Err(bad(&format!("missing or bad import {}", key))),
+		};
+		let kind = import.get("kind").and_then(|v| v.as_str()).unwrap_or_default();
+		graph.imports.push(ImportSite {
+			from: path("from")?,
+			to: path("to")?,
+			kind: ImportKind::from_name(kind).ok_or_else(|| bad(&format!("unknown import kind {:?}", kind)))?,
+			path: path("path")?,
+			span: Span { start: number("start")?, end: number("end")?, line: number("line")? },
+		});
+	}
+	let mut hashes = BTreeMap::new();
+	match value.get("hashes") {
+		Some(Value::Object(items)) => for (file, hash) in items {
+			let hash = hash.as_str().ok_or_else(|| bad("expected hashes to be strings"))?;
+			hashes.insert(PathBuf::from(file), hash.to_string());
+		},
+		_ => return Err(bad("missing hashes")),
+	}
+	Ok(Snapshot { graph, hashes })
+}
+
+// The differences between two graphs.
+pub struct Diff {
+	pub added: Vec<PathBuf>,
+	pub removed: Vec<PathBuf>,
+	// Files that were removed and replaced with a file with the same contents, as (old, new).
+	pub moved: Vec<(PathBuf, PathBuf)>,
+	pub added_edges: Vec<(PathBuf, PathBuf)>,
+	pub removed_edges: Vec<(PathBuf, PathBuf)>,
+}
+
+// Compare two graphs. If follow_renames is set, files that were removed are matched up with added files
+// that have the same contents, and reported as moved instead. Edges to and from moved files are compared
+// as if they had always been at their new path, so they only show up if they actually changed.
+pub fn diff(old: &Snapshot, new: &Snapshot, follow_renames: bool) -> Diff {
+	let mut removed: Vec<PathBuf> = old.graph.edges.keys().filter(|f| !new.graph.edges.contains_key(*f)).cloned().collect();
+	let mut added: Vec<PathBuf> = new.graph.edges.keys().filter(|f| !old.graph.edges.contains_key(*f)).cloned().collect();
+	let mut moved = Vec::new();
+	if follow_renames {
+		// If several files have the same contents, pair them up in order.
+		let mut added_by_hash: BTreeMap<&str, Vec<&PathBuf>> = BTreeMap::new();
+		for file in added.iter().rev() {
+			if let Some(hash) = new.hashes.get(file) {
+				added_by_hash.entry(hash).or_default().push(file);
+			}
+		}
+		for file in &removed {
+			let to = old.hashes.get(file).and_then(|hash| added_by_hash.get_mut(hash.as_str())).and_then(|files| files.pop());
+			if let Some(to) = to {
+				moved.push((file.clone(), to.clone()));
+			}
+		}
+		removed.retain(|file| !moved.iter().any(|(from, _)| from == file));
+		added.retain(|file| !moved.iter().any(|(_, to)| to == file));
+	}
+	let renames: BTreeMap<&PathBuf, &PathBuf> = moved.iter().map(|(from, to)| (from, to)).collect();
+	let rename = |file: &PathBuf| renames.get(file).map_or_else(|| file.clone(), |to| (*to).clone());
+	let old_edges: BTreeSet<(PathBuf, PathBuf)> = old.graph.edges.iter()
+		.flat_map(|(from, deps)| deps.iter().map(move |dep| (rename(from), rename(dep))))
+		.collect();
+	let new_edges: BTreeSet<(PathBuf, PathBuf)> = new.graph.edges.iter()
+		.flat_map(|(from, deps)| deps.iter().map(move |dep| (from.clone(), dep.clone())))
+		.collect();
+	Diff {
+		added,
+		removed,
+		moved,
+		added_edges: new_edges.difference(&old_edges).cloned().collect(),
+		removed_edges: old_edges.difference(&new_edges).cloned().collect(),
+	}
+}
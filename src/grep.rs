@@ -0,0 +1,83 @@
+// Searching only the files a root depends on, to answer questions like "where in this environment's inputs
+// is this value set" without wading through matches in files it never loads.
+
+use crate::scan::{self, Span};
+use crate::{closure_analyses, Cache, Deadline, Options, Root};
+use std::collections::BTreeSet;
+use std::path::Path;
+
+#[derive(Clone, Copy)]
+pub enum Only {
+	// Inside string literals, including their quotes.
+	Strings,
+	// In the names of object fields.
+	Fields,
+}
+
+impl Only {
+	pub fn parse(name: &str) -> Result<Only, String> {
+		match name {
+			"strings" => Ok(Only::Strings),
+			"fields" => Ok(Only::Fields),
+			_ => Err(format!("Unknown --only {:?}, expected one of: strings, fields", name)),
+		}
+	}
+}
+
+pub struct Settings {
+	// Matched literally, not as a regex.
+	pub pattern: String,
+	pub only: Option<Only>,
+}
+
+// The lines of the file which match, as (line number, line).
+fn search<'a>(file: &Path, contents: &'a str, settings: &Settings) -> Result<Vec<(usize, &'a str)>, String> {
+	// Where matches have to be to count. None means anywhere.
+	let regions: Option<Vec<Span>> = match settings.only {
+		None => None,
+		Some(Only::Strings) => Some(scan::parse_string_spans(file, contents)?),
+		Some(Only::Fields) => Some(scan::parse_field_name_spans(file, contents)?),
+	};
+	let mut lines = BTreeSet::new();
+	for (start, _) in contents.match_indices(settings.pattern.as_str()) {
+		let end = start + settings.pattern.len();
+		if let Some(regions) = &regions {
+			if !regions.iter().any(|region| region.start <= start && end <= region.end) {
+				continue;
+			}
+		}
+		lines.insert(contents[..start].bytes().filter(|&b| b == b'\n').count());
+	}
+	let all: Vec<&str> = contents.lines().collect();
+	Ok(lines.into_iter().map(|i| (i + 1, all.get(i).copied().unwrap_or(""))).collect())
+}
+
+// Print each matching line in the deps of the roots, like grep -n. Returns how many lines matched.
+pub fn run(options: &Options, cache: &Cache, roots: &[Root], settings: &Settings) -> Result<usize, String> {
+	// Jsonnet files, and the files they importstr. The jsonnet-aware filters only apply to jsonnet files,
+	// so with either of them the others are skipped.
+	let mut code = BTreeSet::new();
+	let mut leaf = BTreeSet::new();
+	for root in roots {
+		for (file, analysis) in closure_analyses(options, cache, Deadline::new(&root.path), &root.jpaths, &root.path)? {
+			leaf.extend(analysis.leaf_deps.iter().filter(|dep| !analysis.unresolved.contains(dep)).cloned());
+			code.insert(file);
+		}
+	}
+	leaf.extend(options.str_files.iter().cloned());
+	leaf.retain(|file| !code.contains(file) && file.exists());
+	if settings.only.is_some() {
+		leaf.clear();
+	}
+
+	let mut matched = 0;
+	for file in code.iter().chain(leaf.iter()) {
+		let contents = options.fs.read(file).map_err(|e| format!("Failed to read {}: {}", file.display(), e))?;
+		let contents = String::from_utf8_lossy(&contents);
+		for (line, text) in search(file, &contents, settings)? {
+			println!("{}:{}:{}", file.display(), line, text);
+			matched += 1;
+		}
+	}
+	Ok(matched)
+}
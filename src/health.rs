@@ -0,0 +1,180 @@
+// A health report on the import graph of the roots, with a single score to track on a dashboard as the
+// jsonnet estate grows. The score starts at 100, and loses points for each of:
+// - import cycles: 10 each, up to 30
+// - roots over a budget (--max-deps or --max-depth): 5 each, up to 20
+// - orphans, ie. libraries under the --orphans-in directory which no root depends on: 1 each, up to 20
+// - the deepest chain of imports beyond 8: 2 for each level, up to 15
+// - the average number of files importing each file beyond 3: 5 for each one, up to 15
+// The weights are arbitrary, so it's the trend that matters more than the number.
+
+use crate::graph;
+use crate::json::{self, Value};
+use crate::resolve::normalize;
+use crate::{closure_analyses, index, output, resolve_deps, Cache, Deadline, Options, Root};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::path::{Path, PathBuf};
+
+pub struct Settings {
+	// The most deps any root should have.
+	pub max_deps: Option<usize>,
+	// The longest chain of imports any root should need.
+	pub max_depth: Option<usize>,
+	// Where to look for orphaned libraries.
+	pub orphans_in: PathBuf,
+	// A previous report to compare to.
+	pub against: Option<PathBuf>,
+}
+
+impl Settings {
+	pub fn new() -> Settings {
+		Settings { max_deps: None, max_depth: None, orphans_in: ".".into(), against: None }
+	}
+}
+
+// A root over one of its budgets.
+pub struct Violation {
+	pub root: PathBuf,
+	pub budget: &'static str,
+	pub value: usize,
+	pub limit: usize,
+}
+
+pub struct Report {
+	pub score: f64,
+	pub roots: usize,
+	pub files: usize,
+	pub cycles: Vec<Vec<PathBuf>>,
+	pub max_depth: usize,
+	pub average_fan_in: f64,
+	pub orphans: Vec<PathBuf>,
+	pub violations: Vec<Violation>,
+}
+
+// The strongly connected components of the graph given by each file's deps, with more than one file in them, or a file
+// which imports itself, found with Tarjan's algorithm. A stack is kept by hand rather than recursing, as chains of
+// imports can be long.
+pub fn cycles(graph: &BTreeMap<PathBuf, BTreeSet<PathBuf>>) -> Vec<Vec<PathBuf>> {
+	let files: Vec<&Path> = graph.keys().map(|file| file.as_path()).collect();
+	let ids: BTreeMap<&Path, usize> = files.iter().enumerate().map(|(id, file)| (*file, id)).collect();
+	let edges: Vec<Vec<usize>> = graph.values().map(|deps| deps.iter().filter_map(|dep| ids.get(dep.as_path()).copied()).collect()).collect();
+	let mut index = vec![usize::MAX; files.len()];
+	let mut low = vec![0; files.len()];
+	let mut on_stack = vec![false; files.len()];
+	let mut stack = Vec::new();
+	let mut next_index = 0;
+	let mut cycles = Vec::new();
+	for start in 0..files.len() {
+		if index[start] != usize::MAX {
+			continue;
+		}
+		// Each file being visited, with how many of its edges have been followed so far.
+		let mut visiting = vec![(start, 0)];
+		while let Some(&mut (file, ref mut edge)) = visiting.last_mut() {
+			if *edge == 0 && index[file] == usize::MAX {
+				index[file] = next_index;
+				low[file] = next_index;
+				next_index += 1;
+				stack.push(file);
+				on_stack[file] = true;
+			}
+			if let Some(&dep) = edges[file].get(*edge) {
+				*edge += 1;
+				if index[dep] == usize::MAX {
+					visiting.push((dep, 0));
+				} else if on_stack[dep] {
+					low[file] = low[file].min(index[dep]);
+				}
+				continue;
+			}
+			visiting.pop();
+			if let Some(&(parent, _)) = visiting.last() {
+				low[parent] = low[parent].min(low[file]);
+			}
+			if low[file] == index[file] {
+				let mut component = Vec::new();
+				while let Some(member) = stack.pop() {
+					on_stack[member] = false;
+					component.push(member);
+					if member == file {
+						break;
+					}
+				}
+				if component.len() > 1 || edges[file].contains(&file) {
+					let mut component: Vec<PathBuf> = component.into_iter().map(|member| files[member].to_path_buf()).collect();
+					component.sort();
+					cycles.push(component);
+				}
+			}
+		}
+	}
+	cycles.sort();
+	cycles
+}
+
+fn penalty(count: f64, per: f64, max: f64) -> f64 {
+	(count * per).clamp(0.0, max)
+}
+
+pub fn run(options: &Options, cache: &Cache, roots: &[Root], settings: &Settings) -> Result<Report, String> {
+	let graph = graph::build(options, cache, roots)?;
+	let mut max_depth = 0;
+	let mut violations = Vec::new();
+	let mut deps_of_roots = HashSet::new();
+	for root in roots {
+		let deadline = Deadline::new(&root.path);
+		let deps = resolve_deps(options, cache, deadline, &root.jpaths, &root.path)?;
+		let depth = output::depth(options, root, &closure_analyses(options, cache, deadline, &root.jpaths, &root.path)?);
+		max_depth = max_depth.max(depth);
+		for (budget, value, limit) in [("max_deps", deps.len(), settings.max_deps), ("max_depth", depth, settings.max_depth)] {
+			if let Some(limit) = limit.filter(|limit| value > *limit) {
+				violations.push(Violation { root: root.path.clone(), budget, value, limit });
+			}
+		}
+		deps_of_roots.extend(deps.iter().map(|dep| normalize(dep)));
+	}
+	let mut libraries = Vec::new();
+	index::find_files(&settings.orphans_in, &mut libraries)?;
+	let orphans: Vec<PathBuf> = libraries.into_iter()
+		.filter(|file| file.extension().is_some_and(|ext| ext == "libsonnet") && !deps_of_roots.contains(&normalize(file)))
+		.collect();
+	let imports: usize = graph.edges.values().map(|deps| deps.len()).sum();
+	let average_fan_in = match graph.edges.len() {
+		0 => 0.0,
+		files => imports as f64 / files as f64,
+	};
+	let cycles = cycles(&graph.edges);
+	let score = 100.0
+		- penalty(cycles.len() as f64, 10.0, 30.0)
+		- penalty(violations.len() as f64, 5.0, 20.0)
+		- penalty(orphans.len() as f64, 1.0, 20.0)
+		- penalty(max_depth as f64 - 8.0, 2.0, 15.0)
+		- penalty(average_fan_in - 3.0, 5.0, 15.0);
+	Ok(Report {
+		// To one decimal place, so it doesn't seem more precise than it is.
+		score: (score * 10.0).round() / 10.0,
+		roots: roots.len(),
+		files: graph.edges.len(),
+		cycles,
+		max_depth,
+		average_fan_in: (average_fan_in * 100.0).round() / 100.0,
+		orphans,
+		violations,
+	})
+}
+
+// The change in each number since a previous report, as output by us. Numbers missing from it are left out.
+pub fn trend(report: &Value, against: &Path) -> Result<Value, String> {
+	let contents = std::fs::read_to_string(against).map_err(|e| format!("Failed to read {}: {}", against.display(), e))?;
+	let previous = json::parse(&contents).map_err(|e| format!("Failed to parse {}: {}", against.display(), e))?;
+	let Value::Object(items) = report else {
+		unreachable!("reports are objects");
+	};
+	let mut changes = Vec::new();
+	for (key, value) in items {
+		if let (Value::Number(now), Some(Value::Number(before))) = (value, previous.get(key)) {
+			changes.push((key.clone(), Value::Number(((now - before) * 100.0).round() / 100.0)));
+		}
+	}
+	Ok(Value::Object(changes))
+}
+
@@ -0,0 +1,96 @@
+// Replaying the history of a root, to see when each of its deps was added or removed and by which commit,
+// eg. to audit how an environment came to need everything it does. Each commit is checked out in turn in a
+// temporary worktree, and the root analyzed there from the same directory relative to the top of the repository
+// as we were run from, so that paths come out the same as they would today. Between commits, only the files
+// the commit changed need parsing again.
+
+use crate::hooks::git;
+use crate::resolve::normalize;
+use crate::{resolve_deps, Cache, Deadline, Options, Root};
+use std::collections::{BTreeSet, HashSet};
+use std::path::{Path, PathBuf};
+
+// How the root's deps changed in one commit.
+pub struct Change {
+	pub commit: String,
+	pub subject: String,
+	pub added: BTreeSet<PathBuf>,
+	pub removed: BTreeSet<PathBuf>,
+}
+
+// The commits to look at: since itself, then each one after it up to HEAD, oldest first, as (hash, subject).
+// Only the first parent of merges is followed, as that's the history of what was actually deployed.
+fn commits(since: &str) -> Result<Vec<(String, String)>, String> {
+	let base = git(&["rev-parse", "--verify", "--quiet", &format!("{}^{{commit}}", since)])
+		.map_err(|_| format!("{:?} is not a commit", since))?;
+	let log = git(&["log", "--reverse", "--first-parent", "--format=%H %s", &format!("{}..HEAD", base)])?;
+	let subject = git(&["log", "-1", "--format=%s", &base])?;
+	let mut commits = vec![(base, subject)];
+	for line in log.lines() {
+		let (hash, subject) = line.split_once(' ').unwrap_or((line, ""));
+		commits.push((hash.into(), subject.into()));
+	}
+	Ok(commits)
+}
+
+// The files changed between two commits, relative to the current directory (given as its path from the top of the repository).
+fn changed(from: &str, to: &str, prefix: &Path) -> Result<HashSet<PathBuf>, String> {
+	let names = git(&["diff", "--name-only", "--no-renames", from, to])?;
+	let up: PathBuf = prefix.components().map(|_| "..").collect();
+	Ok(names.lines().map(|name| {
+		let name = Path::new(name);
+		match name.strip_prefix(prefix) {
+			Ok(rest) => rest.to_owned(),
+			Err(_) => normalize(&up.join(name)),
+		}
+	}).collect())
+}
+
+// Analyze the root at each commit from since to HEAD, returning the changes to its deps. The root is taken as
+// having no deps before since, so the first change is everything it depended on then. Commits where it
+// can't be analyzed, eg. because it didn't exist yet, are skipped with a warning.
+pub fn run(options: &Options, cache: &Cache, root: &Root, since: &str) -> Result<Vec<Change>, String> {
+	if root.path.is_absolute() {
+		return Err(format!("history needs a relative path to {}, so that it can be found in each commit", root.path.display()));
+	}
+	let commits = commits(since)?;
+	let prefix = PathBuf::from(git(&["rev-parse", "--show-prefix"])?);
+	let cwd = std::env::current_dir().map_err(|e| format!("Failed to get current directory: {}", e))?;
+	let worktree = std::env::temp_dir().join(format!("jsonnet_dep_graph-history-{}", std::process::id()));
+	let worktree_str = worktree.to_string_lossy().into_owned();
+	git(&["worktree", "add", "--detach", "--quiet", &worktree_str, &commits[0].0])?;
+
+	let replay = || -> Result<Vec<Change>, String> {
+		let dir = worktree.join(&prefix);
+		std::env::set_current_dir(&dir).map_err(|e| format!("Failed to change to {}: {}", dir.display(), e))?;
+		let mut changes = Vec::new();
+		let mut deps = HashSet::new();
+		let mut previous: Option<&str> = None;
+		for (commit, subject) in &commits {
+			if let Some(previous) = previous {
+				git(&["checkout", "--detach", "--quiet", commit])?;
+				cache.invalidate_normalized(&changed(previous, commit, &prefix)?);
+			}
+			previous = Some(commit);
+			let new = match resolve_deps(options, cache, Deadline::new(&root.path), &root.jpaths, &root.path) {
+				Ok(new) => new,
+				Err(e) => {
+					eprintln!("Skipping {} {}: {}", commit, subject, e);
+					continue;
+				},
+			};
+			let added: BTreeSet<_> = new.difference(&deps).cloned().collect();
+			let removed: BTreeSet<_> = deps.difference(&new).cloned().collect();
+			if !added.is_empty() || !removed.is_empty() {
+				changes.push(Change { commit: commit.clone(), subject: subject.clone(), added, removed });
+			}
+			deps = new;
+		}
+		Ok(changes)
+	};
+	let result = replay();
+	// Clean up whether or not that worked.
+	std::env::set_current_dir(&cwd).map_err(|e| format!("Failed to change back to {}: {}", cwd.display(), e))?;
+	git(&["worktree", "remove", "--force", &worktree_str])?;
+	result
+}
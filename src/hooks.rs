@@ -0,0 +1,66 @@
+// Install git hooks which run one of our check modes, so a repo can enforce it on every commit or push
+// without each team maintaining their own hook scripts.
+
+use crate::watch::shell_quote;
+use std::path::PathBuf;
+use std::process::Command;
+
+// The hooks we know how to install. Both are run from the top of the worktree, and fail the commit or push
+// if they exit non-zero.
+pub const HOOKS: &[&str] = &["pre-commit", "pre-push"];
+
+// The subcommands that make sense to run from a hook.
+pub const COMMANDS: &[&str] = &["check", "lint"];
+
+pub fn git(args: &[&str]) -> Result<String, String> {
+	let output = Command::new("git")
+		.args(args)
+		.output()
+		.map_err(|e| format!("Failed to run git: {}", e))?;
+	if !output.status.success() {
+		return Err(format!(
+			"git {} failed: {}", args.join(" "), String::from_utf8_lossy(&output.stderr).trim(),
+		));
+	}
+	Ok(String::from_utf8_lossy(&output.stdout).trim_end_matches('\n').to_string())
+}
+
+// Write the hook, which runs this program with the given args. Returns the path it was written to.
+// Fails if the hook already exists, unless force is set, as it may be someone else's.
+pub fn install(hook: &str, args: &[String], force: bool) -> Result<PathBuf, String> {
+	// This respects core.hooksPath, and works from within a linked worktree.
+	let path = PathBuf::from(git(&["rev-parse", "--git-path", "hooks"])?).join(hook);
+	// Hooks run from the top of the worktree, so go back to where we are now for relative paths in args to work.
+	let prefix = git(&["rev-parse", "--show-prefix"])?;
+	let exe = std::env::current_exe().map_err(|e| format!("Failed to find path to this program: {}", e))?;
+
+	let mut script = String::from("#!/bin/sh\n# Installed by jsonnet_dep_graph install-hooks.\nset -e\n");
+	if !prefix.is_empty() {
+		script.push_str(&format!("cd {}\n", shell_quote(&prefix)));
+	}
+	let mut command = vec![shell_quote(&exe.to_string_lossy())];
+	command.extend(args.iter().map(|arg| shell_quote(arg)));
+	script.push_str(&format!("exec {}\n", command.join(" ")));
+
+	if !force && path.exists() {
+		return Err(format!("{} already exists. Use --force to replace it.", path.display()));
+	}
+	if let Some(dir) = path.parent() {
+		std::fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+	}
+	std::fs::write(&path, script).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+	set_executable(&path)?;
+	Ok(path)
+}
+
+#[cfg(unix)]
+fn set_executable(path: &std::path::Path) -> Result<(), String> {
+	use std::os::unix::fs::PermissionsExt;
+	std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755))
+		.map_err(|e| format!("Failed to make {} executable: {}", path.display(), e))
+}
+
+#[cfg(not(unix))]
+fn set_executable(_path: &std::path::Path) -> Result<(), String> {
+	Ok(())
+}
@@ -0,0 +1,328 @@
+// An index records the imports of every jsonnet file in a directory tree, so that
+// later queries don't need to parse them again.
+//
+// It is stored as one JSON object per line. The first line is a header {"version": 1, "dir": DIR},
+// then one line per file:
+// {"path": PATH, "size": BYTES, "mtime": SECS, "hash": SHA256, "imports": [IMPORT, ...]}
+// where each IMPORT is:
+// {"kind": KIND, "path": PATH, "origin": ORIGIN, "conditional": BOOL, "start": OFFSET, "end": OFFSET, "line": LINE}
+// If the file couldn't be parsed, "imports" is replaced by "error": MESSAGE.
+
+use crate::json::{self, Value};
+use crate::scan::{self, DataFormat, Import, ImportKind, Origin, Span};
+use crate::graph::Graph;
+use crate::{Cache, Metadata, Options};
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+const VERSION: f64 = 1.0;
+
+pub struct Entry {
+	pub path: PathBuf,
+	pub size: u64,
+	pub mtime: u64,
+	pub hash: String,
+	pub imports: Result<Vec<Import>, String>,
+}
+
+pub struct Index {
+	// The directory that was indexed.
+	pub dir: PathBuf,
+	pub entries: Vec<Entry>,
+}
+
+fn is_jsonnet(path: &Path) -> bool {
+	matches!(path.extension().and_then(|ext| ext.to_str()), Some("jsonnet" | "libsonnet"))
+}
+
+// Find all jsonnet files under dir, in a consistent order.
+// Hidden files and directories (eg. .git) are skipped, and symlinks to directories are not followed
+// to avoid loops.
+pub fn find_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), String> {
+	let mut entries = std::fs::read_dir(dir)
+		.and_then(|entries| entries.collect::<Result<Vec<_>, _>>())
+		.map_err(|e| format!("Failed to list {}: {}", dir.display(), e))?;
+	entries.sort_by_key(|entry| entry.file_name());
+	for entry in entries {
+		if entry.file_name().to_string_lossy().starts_with('.') {
+			continue;
+		}
+		// Joining onto "." would give "./foo", which won't match how other paths are written.
+		let path = if dir == Path::new(".") { PathBuf::from(entry.file_name()) } else { entry.path() };
+		let file_type = entry.file_type().map_err(|e| format!("Failed to stat {}: {}", path.display(), e))?;
+		if file_type.is_dir() {
+			find_files(&path, files)?;
+		} else if is_jsonnet(&path) && path.is_file() {
+			files.push(path);
+		}
+	}
+	Ok(())
+}
+
+pub fn mtime_of(metadata: &std::fs::Metadata) -> u64 {
+	metadata.modified().ok()
+		.and_then(|mtime| mtime.duration_since(UNIX_EPOCH).ok())
+		.map_or(0, |mtime| mtime.as_secs())
+}
+
+fn index_file(path: &Path, fs: &crate::fs::Budget) -> Result<Entry, String> {
+	let contents = fs.read_to_string(path).map_err(|e|
+		format!("Failed to read {}: {}", path.display(), e)
+	)?;
+	let metadata = fs.metadata(path).map_err(|e| format!("Failed to stat {}: {}", path.display(), e))?;
+	Ok(Entry {
+		path: path.to_owned(),
+		size: contents.len() as u64,
+		mtime: mtime_of(&metadata),
+		hash: crate::sha256::hex_digest(contents.as_bytes()),
+		imports: scan::parse_contents(path, &contents, None),
+	})
+}
+
+fn entry_to_json(entry: &Entry) -> Value {
+	let mut items = vec![
+		("path".into(), Value::String(entry.path.to_string_lossy().into_owned())),
+		("size".into(), Value::Number(entry.size as f64)),
+		("mtime".into(), Value::Number(entry.mtime as f64)),
+		("hash".into(), Value::String(entry.hash.clone())),
+	];
+	match &entry.imports {
+		Ok(imports) => items.push(("imports".into(), Value::Array(imports.iter().map(|import| Value::Object(vec![
+			("kind".into(), Value::String(import.kind.name().into())),
+			("path".into(), Value::String(import.path.to_string_lossy().into_owned())),
+			("origin".into(), Value::String(import.origin.name().into())),
+			("conditional".into(), Value::Bool(import.conditional)),
+			("start".into(), Value::Number(import.span.start as f64)),
+			("end".into(), Value::Number(import.span.end as f64)),
+			("line".into(), Value::Number(import.span.line as f64)),
+		].into_iter().chain(import.data_format.map(|format| ("data_format".into(), Value::String(format.name().into())))).collect())).collect()))),
+		Err(e) => items.push(("error".into(), Value::String(e.clone()))),
+	}
+	Value::Object(items)
+}
+
+// Index every jsonnet file under dir.
+pub fn build(dir: &Path, fs: &crate::fs::Budget) -> Result<Index, String> {
+	let mut files = Vec::new();
+	find_files(dir, &mut files)?;
+	let entries = files.iter().map(|file| index_file(file, fs)).collect::<Result<_, _>>()?;
+	Ok(Index { dir: dir.to_owned(), entries })
+}
+
+fn number(value: &Value, key: &str) -> Result<u64, String> {
+	match value.get(key) {
+		Some(Value::Number(n)) if *n >= 0.0 => Ok(*n as u64),
+		_ => Err(format!("missing or bad {}", key)),
+	}
+}
+
+fn string<'a>(value: &'a Value, key: &str) -> Result<&'a str, String> {
+	value.get(key).and_then(|v| v.as_str()).ok_or_else(|| format!("missing or bad {}", key))
+}
+
+fn import_from_json(value: &Value) -> Result<Import, String> {
+	let kind = string(value, "kind")?;
+	let origin = string(value, "origin")?;
+	Ok(Import {
+		kind: ImportKind::from_name(kind).ok_or_else(|| format!("unknown import kind {:?}", kind))?,
+		path: string(value, "path")?.into(),
+		conditional: matches!(value.get("conditional"), Some(Value::Bool(true))),
+		origin: Origin::from_name(origin).ok_or_else(|| format!("unknown origin {:?}", origin))?,
+		span: Span {
+			start: number(value, "start")? as usize,
+			end: number(value, "end")? as usize,
+			line: number(value, "line")? as usize,
+		},
+		data_format: match value.get("data_format") {
+			Some(format) => Some(format.as_str().and_then(DataFormat::from_name).ok_or("bad data_format")?),
+			None => None,
+		},
+	})
+}
+
+fn entry_from_json(value: &Value) -> Result<Entry, String> {
+	let imports = match value.get("imports") {
+		Some(imports) => Ok(imports.as_array().ok_or("bad imports")?
+			.iter()
+			.map(import_from_json)
+			.collect::<Result<_, _>>()?),
+		None => Err(string(value, "error")?.to_string()),
+	};
+	Ok(Entry {
+		path: string(value, "path")?.into(),
+		size: number(value, "size")?,
+		mtime: number(value, "mtime")?,
+		hash: string(value, "hash")?.into(),
+		imports,
+	})
+}
+
+pub fn load(path: &Path) -> Result<Index, String> {
+	let contents = std::fs::read_to_string(path).map_err(|e|
+		format!("Failed to read {}: {}", path.display(), e)
+	)?;
+	let mut lines = contents.lines().enumerate().filter(|(_, line)| !line.trim().is_empty());
+	let bad_line = |index: usize, e: String| format!("Bad index {} at line {}: {}", path.display(), index + 1, e);
+	let (index, header) = lines.next().ok_or_else(|| format!("Index {} is empty", path.display()))?;
+	let header = json::parse(header).map_err(|e| bad_line(index, e))?;
+	if header.get("version") != Some(&Value::Number(VERSION)) {
+		return Err(format!("Index {} has an unsupported version, re-create it", path.display()));
+	}
+	let dir = string(&header, "dir").map_err(|e| bad_line(index, e))?.into();
+	let mut entries = Vec::new();
+	for (index, line) in lines {
+		let entry = json::parse(line).and_then(|value| entry_from_json(&value)).map_err(|e| bad_line(index, e))?;
+		entries.push(entry);
+	}
+	Ok(Index { dir, entries })
+}
+
+impl Index {
+	pub fn save(&self, path: &Path) -> Result<(), String> {
+		let header = Value::Object(vec![
+			("version".into(), Value::Number(VERSION)),
+			("dir".into(), Value::String(self.dir.to_string_lossy().into_owned())),
+		]);
+		let mut lines = vec![header.to_string()];
+		lines.extend(self.entries.iter().map(|entry| entry_to_json(entry).to_string()));
+		lines.push(String::new());
+		std::fs::write(path, lines.join("\n")).map_err(|e|
+			format!("Failed to write {}: {}", path.display(), e)
+		)
+	}
+
+	// Bring the index up to date with the files on disk. Files whose size or mtime have changed are
+	// re-indexed if their contents have actually changed, removed files are dropped and new files are added.
+	// Returns whether anything in the index changed. If frozen, instead of refreshing we fail
+	// if any file is out of date.
+	pub fn refresh(&mut self, frozen: bool, fs: &crate::fs::Budget) -> Result<bool, String> {
+		let mut files = Vec::new();
+		find_files(&self.dir, &mut files)?;
+		let mut existing: HashMap<PathBuf, Entry> = self.entries.drain(..).map(|entry| (entry.path.clone(), entry)).collect();
+		let mut stale = Vec::new();
+		let mut changed = false;
+		for file in files {
+			let entry = match existing.remove(&file) {
+				None => {
+					stale.push(file.clone());
+					index_file(&file, fs)?
+				},
+				Some(entry) => {
+					let metadata = fs.metadata(&file).map_err(|e| format!("Failed to stat {}: {}", file.display(), e))?;
+					if metadata.len() == entry.size && mtime_of(&metadata) == entry.mtime {
+						entry
+					} else {
+						let new_entry = index_file(&file, fs)?;
+						changed = true;
+						if new_entry.hash == entry.hash {
+							// If only the mtime changed, we can keep the old entry but we
+							// still update it so we don't need to re-hash it next time.
+							Entry { mtime: new_entry.mtime, ..entry }
+						} else {
+							// The contents changed, so the new entry is already what re-indexing it would give.
+							stale.push(file.clone());
+							new_entry
+						}
+					}
+				},
+			};
+			self.entries.push(entry);
+		}
+		// Anything left over no longer exists.
+		stale.extend(existing.into_keys());
+		if frozen && !stale.is_empty() {
+			return Err(format!(
+				"Index is out of date and --frozen was given: {} files changed, including {}",
+				stale.len(), stale[0].display(),
+			));
+		}
+		Ok(changed || !stale.is_empty())
+	}
+
+	// Add the imports of every indexed file to the cache, so they don't need to be parsed again.
+	// Files which failed to parse are left out, so that the error is reported if anything needs them.
+	pub fn preload(&self, cache: &Cache) {
+		for entry in &self.entries {
+			if let Ok(imports) = &entry.imports {
+				cache.insert_parsed(&entry.path, imports.clone());
+				cache.insert_digest(&entry.path, entry.hash.clone());
+				cache.insert_metadata(&entry.path, Metadata { size: entry.size, mtime: entry.mtime });
+			}
+		}
+	}
+
+	// Work out which files import each file, directly.
+	// Files which failed to parse are skipped with a warning, as we can't know what they import.
+	pub fn importers(&self, options: &Options, cache: &Cache, jpaths: &[PathBuf]) -> Result<Importers, String> {
+		let mut importers: HashMap<PathBuf, Vec<PathBuf>> = HashMap::new();
+		for entry in &self.entries {
+			if let Err(e) = &entry.imports {
+				eprintln!("Warning: skipping {}: {}", entry.path.display(), e);
+				continue;
+			}
+			let analysis = cache.analysis(options, jpaths, &entry.path)?;
+			for dep in analysis.leaf_deps.iter().chain(analysis.deep_deps.iter()) {
+				importers.entry(dep.clone()).or_default().push(entry.path.clone());
+			}
+		}
+		Ok(Importers(importers))
+	}
+}
+
+// The reverse of the import graph: for each file, the indexed files which directly import it.
+pub struct Importers(HashMap<PathBuf, Vec<PathBuf>>);
+
+impl Importers {
+	pub fn from_graph(graph: &Graph) -> Importers {
+		let mut importers: HashMap<PathBuf, Vec<PathBuf>> = HashMap::new();
+		for (from, deps) in &graph.edges {
+			for dep in deps {
+				importers.entry(dep.clone()).or_default().push(from.clone());
+			}
+		}
+		Importers(importers)
+	}
+
+	// Find every file which depends on any of the targets, including the targets themselves,
+	// along with the shortest chain of imports from each file to a target.
+	// Each file maps to the file it imports on that chain, or None for the targets themselves.
+	pub fn rdep_tree(&self, targets: &[&Path]) -> HashMap<PathBuf, Option<PathBuf>> {
+		let mut tree = HashMap::new();
+		let mut to_expand = VecDeque::new();
+		for target in targets {
+			if !tree.contains_key(*target) {
+				tree.insert(target.to_path_buf(), None);
+				to_expand.push_back(target.to_path_buf());
+			}
+		}
+		// Breadth-first, so the first chain we find to each file is the shortest.
+		while let Some(path) = to_expand.pop_front() {
+			for importer in self.0.get(&path).into_iter().flatten() {
+				if !tree.contains_key(importer) {
+					tree.insert(importer.clone(), Some(path.clone()));
+					to_expand.push_back(importer.clone());
+				}
+			}
+		}
+		tree
+	}
+}
+
+// Pick up to n chains of imports from the tree returned by rdep_tree, each going from a file
+// that depends on a target down to that target. Finding every chain could take exponential time,
+// so we only consider the shortest chain for each file, and prefer the longest of those
+// as they show the most context.
+pub fn sample_chains(tree: &HashMap<PathBuf, Option<PathBuf>>, n: usize) -> Vec<Vec<PathBuf>> {
+	// The targets themselves would only give chains of one file, which tell us nothing.
+	let mut chains: Vec<Vec<PathBuf>> = tree.iter().filter(|(_, next)| next.is_some()).map(|(path, _)| {
+		let mut chain = vec![path.clone()];
+		while let Some(Some(next)) = tree.get(chain.last().unwrap()) {
+			chain.push(next.clone());
+		}
+		chain
+	}).collect();
+	chains.sort_by(|a, b| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+	chains.truncate(n);
+	chains
+}
@@ -0,0 +1,114 @@
+// A client for GNU make's jobserver, so that when we're run from a recipe under `make -jN`, work done in parallel
+// (--batch jobs and --format jsonl roots) takes job slots from make's pool rather than adding to what make is
+// already running. See https://www.gnu.org/software/make/manual/html_node/Job-Slots.html.
+// We start with one implicit slot, as every job make runs does. Any more work running at once needs a token,
+// a byte read from the jobserver, which must be written back once that work is done.
+
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+pub struct Jobserver {
+	read: File,
+	write: Arc<File>,
+}
+
+// A job slot taken from the jobserver, which is given back when dropped.
+pub struct Token {
+	write: Arc<File>,
+	byte: u8,
+}
+
+impl Drop for Token {
+	fn drop(&mut self) {
+		// If this fails, make is gone or broken, and there's nothing useful to be done about it here.
+		let _ = self.write.as_ref().write_all(&[self.byte]);
+	}
+}
+
+// Tokens read from the jobserver in the background, for workers to take as they need them.
+pub struct Tokens {
+	receiver: Mutex<Receiver<Token>>,
+}
+
+impl Jobserver {
+	// Start reading tokens in the background. Reading blocks until make has a free slot, which could be long after
+	// we run out of work, so it's done on a thread of its own that we don't wait for. Each token is handed straight
+	// to a waiting worker, and once the Tokens are dropped, any token read after that is given back.
+	pub fn tokens(self) -> Tokens {
+		let (sender, receiver) = mpsc::sync_channel(0);
+		std::thread::spawn(move || loop {
+			let mut byte = [0];
+			if let Err(e) = (&self.read).read_exact(&mut byte) {
+				eprintln!("Failed to get a job slot from make's jobserver: {}", e);
+				return;
+			}
+			if sender.send(Token { write: self.write.clone(), byte: byte[0] }).is_err() {
+				return;
+			}
+		});
+		Tokens { receiver: Mutex::new(receiver) }
+	}
+}
+
+// What a worker needs before taking its next piece of work: nothing for the first worker, which has our implicit slot,
+// or for the rest, a token if there's a jobserver. Returns None if the worker should stop instead, because there's no
+// more work or no more tokens, leaving anything left to the first worker.
+pub fn slot(tokens: Option<&Tokens>, worker: usize, more_work: impl Fn() -> bool) -> Option<Option<Token>> {
+	let tokens = match tokens {
+		Some(tokens) if worker > 0 => tokens,
+		_ => return Some(None),
+	};
+	// Check for more work every so often, rather than waiting for a token that's only needed if there is some.
+	while more_work() {
+		match tokens.receiver.lock().unwrap().recv_timeout(Duration::from_millis(50)) {
+			Ok(token) => return Some(Some(token)),
+			Err(RecvTimeoutError::Timeout) => (),
+			Err(RecvTimeoutError::Disconnected) => return None,
+		}
+	}
+	None
+}
+
+// The paths to open for the jobserver's read and write ends, from the value of make's --jobserver-auth option.
+// This is either fifo:PATH, used by default since make 4.4, or R,W, a pair of inherited pipe file descriptors.
+// Descriptors are opened by their /dev/fd path, which gives us our own handles to the same pipe.
+fn paths(auth: &str) -> Option<(PathBuf, PathBuf)> {
+	if let Some(path) = auth.strip_prefix("fifo:") {
+		return Some((path.into(), path.into()));
+	}
+	let (read, write) = auth.split_once(',')?;
+	let (read, write): (u32, u32) = (read.parse().ok()?, write.parse().ok()?);
+	Some((format!("/dev/fd/{}", read).into(), format!("/dev/fd/{}", write).into()))
+}
+
+// The jobserver of the make we're running under, if any. A jobserver which can't be opened, eg. because the recipe
+// running us wasn't marked with + so make closed its descriptors, is skipped with a warning, and we run as if there
+// were no jobserver.
+pub fn from_env() -> Option<Jobserver> {
+	let makeflags = std::env::var("MAKEFLAGS").ok()?;
+	// If given more than once, make uses the last one. Older versions of make call it --jobserver-fds.
+	let auth = makeflags.split_whitespace()
+		.filter_map(|flag| flag.strip_prefix("--jobserver-auth=").or_else(|| flag.strip_prefix("--jobserver-fds=")))
+		.next_back()?;
+	let Some((read, write)) = paths(auth) else {
+		eprintln!("Ignoring make's jobserver, as --jobserver-auth={} isn't understood", auth);
+		return None;
+	};
+	let open = || -> std::io::Result<Jobserver> {
+		Ok(Jobserver {
+			read: File::open(&read)?,
+			write: Arc::new(File::options().write(true).open(&write)?),
+		})
+	};
+	match open() {
+		Ok(jobserver) => Some(jobserver),
+		Err(e) => {
+			eprintln!("Ignoring make's jobserver, as it couldn't be opened ({}). Is the recipe marked with +?", e);
+			None
+		},
+	}
+}
@@ -0,0 +1,315 @@
+// A minimal JSON parser and serializer, sufficient for our input and output files.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+	Null,
+	Bool(bool),
+	Number(f64),
+	String(String),
+	Array(Vec<Value>),
+	// Keys are kept in the order they appear.
+	Object(Vec<(String, Value)>),
+}
+
+impl Value {
+	pub fn get(&self, key: &str) -> Option<&Value> {
+		match self {
+			Value::Object(items) => items.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+			_ => None,
+		}
+	}
+
+	pub fn as_str(&self) -> Option<&str> {
+		match self {
+			Value::String(s) => Some(s),
+			_ => None,
+		}
+	}
+
+	pub fn as_array(&self) -> Option<&[Value]> {
+		match self {
+			Value::Array(items) => Some(items),
+			_ => None,
+		}
+	}
+}
+
+// Serializes as compact JSON, all on one line.
+impl std::fmt::Display for Value {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Value::Null => write!(f, "null"),
+			Value::Bool(b) => write!(f, "{}", b),
+			// Print whole numbers without a trailing ".0". All the numbers we output are integers
+			// well within the range that f64 represents exactly.
+			Value::Number(n) if n.fract() == 0.0 && n.abs() < 1e15 => write!(f, "{}", *n as i64),
+			Value::Number(n) => write!(f, "{}", n),
+			Value::String(s) => write_string(f, s),
+			Value::Array(items) => {
+				write!(f, "[")?;
+				for (i, item) in items.iter().enumerate() {
+					if i > 0 {
+						write!(f, ",")?;
+					}
+					write!(f, "{}", item)?;
+				}
+				write!(f, "]")
+			},
+			Value::Object(items) => {
+				write!(f, "{{")?;
+				for (i, (key, value)) in items.iter().enumerate() {
+					if i > 0 {
+						write!(f, ",")?;
+					}
+					write_string(f, key)?;
+					write!(f, ":{}", value)?;
+				}
+				write!(f, "}}")
+			},
+		}
+	}
+}
+
+fn write_string(f: &mut std::fmt::Formatter<'_>, s: &str) -> std::fmt::Result {
+	write!(f, "\"")?;
+	for c in s.chars() {
+		match c {
+			'"' => write!(f, "\\\"")?,
+			'\\' => write!(f, "\\\\")?,
+			'\n' => write!(f, "\\n")?,
+			'\r' => write!(f, "\\r")?,
+			'\t' => write!(f, "\\t")?,
+			c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+			c => write!(f, "{}", c)?,
+		}
+	}
+	write!(f, "\"")
+}
+
+pub fn parse(text: &str) -> Result<Value, String> {
+	let mut parser = Parser { text: text.as_bytes(), pos: 0 };
+	let value = parser.value()?;
+	parser.whitespace();
+	if parser.pos < parser.text.len() {
+		return Err(parser.error("trailing characters"));
+	}
+	Ok(value)
+}
+
+struct Parser<'a> {
+	text: &'a [u8],
+	pos: usize,
+}
+
+impl<'a> Parser<'a> {
+	fn error(&self, msg: &str) -> String {
+		format!("{} at offset {}", msg, self.pos)
+	}
+
+	fn whitespace(&mut self) {
+		while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.text.get(self.pos) {
+			self.pos += 1;
+		}
+	}
+
+	fn peek(&mut self) -> Option<u8> {
+		self.whitespace();
+		self.text.get(self.pos).copied()
+	}
+
+	fn expect(&mut self, byte: u8) -> Result<(), String> {
+		if self.peek() != Some(byte) {
+			return Err(self.error(&format!("expected {:?}", byte as char)));
+		}
+		self.pos += 1;
+		Ok(())
+	}
+
+	fn literal(&mut self, word: &str, value: Value) -> Result<Value, String> {
+		if !self.text[self.pos..].starts_with(word.as_bytes()) {
+			return Err(self.error("unexpected character"));
+		}
+		self.pos += word.len();
+		Ok(value)
+	}
+
+	fn value(&mut self) -> Result<Value, String> {
+		match self.peek() {
+			None => Err(self.error("unexpected end of input")),
+			Some(b'n') => self.literal("null", Value::Null),
+			Some(b't') => self.literal("true", Value::Bool(true)),
+			Some(b'f') => self.literal("false", Value::Bool(false)),
+			Some(b'"') => Ok(Value::String(self.string()?)),
+			Some(b'[') => {
+				self.pos += 1;
+				let mut items = Vec::new();
+				if self.peek() == Some(b']') {
+					self.pos += 1;
+					return Ok(Value::Array(items));
+				}
+				loop {
+					items.push(self.value()?);
+					match self.peek() {
+						Some(b',') => self.pos += 1,
+						Some(b']') => {
+							self.pos += 1;
+							return Ok(Value::Array(items));
+						},
+						_ => return Err(self.error("expected ',' or ']'")),
+					}
+				}
+			},
+			Some(b'{') => {
+				self.pos += 1;
+				let mut items = Vec::new();
+				if self.peek() == Some(b'}') {
+					self.pos += 1;
+					return Ok(Value::Object(items));
+				}
+				loop {
+					if self.peek() != Some(b'"') {
+						return Err(self.error("expected string key"));
+					}
+					let key = self.string()?;
+					self.expect(b':')?;
+					items.push((key, self.value()?));
+					match self.peek() {
+						Some(b',') => self.pos += 1,
+						Some(b'}') => {
+							self.pos += 1;
+							return Ok(Value::Object(items));
+						},
+						_ => return Err(self.error("expected ',' or '}'")),
+					}
+				}
+			},
+			Some(b'-' | b'0'..=b'9') => self.number(),
+			Some(_) => Err(self.error("unexpected character")),
+		}
+	}
+
+	fn number(&mut self) -> Result<Value, String> {
+		let start = self.pos;
+		while let Some(b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9') = self.text.get(self.pos) {
+			self.pos += 1;
+		}
+		// This can't fail to be utf-8 as we only consumed ascii characters.
+		let text = std::str::from_utf8(&self.text[start..self.pos]).unwrap();
+		text.parse().map(Value::Number).map_err(|_| {
+			self.pos = start;
+			self.error("bad number")
+		})
+	}
+
+	fn hex4(&mut self) -> Result<u32, String> {
+		let digits = self.text.get(self.pos..self.pos + 4)
+			.and_then(|digits| std::str::from_utf8(digits).ok())
+			.and_then(|digits| u32::from_str_radix(digits, 16).ok())
+			.ok_or_else(|| self.error("bad unicode escape"))?;
+		self.pos += 4;
+		Ok(digits)
+	}
+
+	// Called with pos at the opening quote.
+	fn string(&mut self) -> Result<String, String> {
+		self.pos += 1;
+		let mut result = Vec::new();
+		loop {
+			let byte = *self.text.get(self.pos).ok_or_else(|| self.error("unterminated string"))?;
+			self.pos += 1;
+			match byte {
+				b'"' => break,
+				b'\\' => {
+					let escape = *self.text.get(self.pos).ok_or_else(|| self.error("unterminated string"))?;
+					self.pos += 1;
+					let c = match escape {
+						b'"' => '"',
+						b'\\' => '\\',
+						b'/' => '/',
+						b'b' => '\u{8}',
+						b'f' => '\u{c}',
+						b'n' => '\n',
+						b'r' => '\r',
+						b't' => '\t',
+						b'u' => {
+							let mut code = self.hex4()?;
+							// Characters outside the BMP are encoded as a surrogate pair.
+							if (0xd800..0xdc00).contains(&code) && self.text[self.pos..].starts_with(b"\\u") {
+								self.pos += 2;
+								let low = self.hex4()?;
+								if !(0xdc00..0xe000).contains(&low) {
+									return Err(self.error("bad unicode escape"));
+								}
+								code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+							}
+							char::from_u32(code).ok_or_else(|| self.error("bad unicode escape"))?
+						},
+						_ => return Err(self.error("bad escape")),
+					};
+					let mut buf = [0; 4];
+					result.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+				},
+				byte => result.push(byte),
+			}
+		}
+		// The input was a str and we only split it at ascii characters, so this is still valid utf-8.
+		Ok(String::from_utf8(result).unwrap())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn round_trips_nested_values() {
+		let text = r#"{"a":[1,[2,{"b":null}],{}],"c":{"d":[true,false,[]],"e":"f"},"":-0.5}"#;
+		let value = parse(text).unwrap();
+		assert_eq!(value.to_string(), text);
+		assert_eq!(parse(&value.to_string()).unwrap(), value);
+		// Whitespace anywhere between tokens is allowed, and not kept.
+		assert_eq!(parse(" {\n\t\"a\" : [ 1 , 2 ] }\r\n").unwrap().to_string(), r#"{"a":[1,2]}"#);
+	}
+
+	#[test]
+	fn string_escapes() {
+		assert_eq!(parse(r#""\"\\\/\b\f\n\r\t""#).unwrap(), Value::String("\"\\/\u{8}\u{c}\n\r\t".into()));
+		assert_eq!(parse(r#""\u0041\u00e9\u20AC""#).unwrap(), Value::String("Aé€".into()));
+		// Outside the BMP, as a surrogate pair.
+		assert_eq!(parse(r#""\ud83d\ude00""#).unwrap(), Value::String("😀".into()));
+		// Unescaped non-ASCII is passed through.
+		assert_eq!(parse("\"é😀\"").unwrap(), Value::String("é😀".into()));
+		for bad in [r#""\ud83d""#, r#""\ud83dA""#, r#""\ud83d\u0041""#, r#""\ude00""#, r#""\u12""#, r#""\uzzzz""#, r#""\x""#] {
+			assert!(parse(bad).is_err(), "{} should fail", bad);
+		}
+		// Control characters are escaped when written, and read back the same.
+		let value = Value::String("a\"b\\c\nd\u{1}é".into());
+		assert_eq!(value.to_string(), r#""a\"b\\c\nd\u0001é""#);
+		assert_eq!(parse(&value.to_string()).unwrap(), value);
+	}
+
+	#[test]
+	fn numbers() {
+		for (text, n) in [("0", 0.0), ("-0", 0.0), ("42", 42.0), ("-7", -7.0), ("1.5", 1.5), ("1e3", 1000.0), ("2.5E-1", 0.25), ("1e+2", 100.0)] {
+			assert_eq!(parse(text).unwrap(), Value::Number(n), "{}", text);
+		}
+		for bad in ["-", "1.2.3", "1e", "--1", "+1", ".5"] {
+			assert!(parse(bad).is_err(), "{} should fail", bad);
+		}
+		// Whole numbers are written without a fraction, and others as they are.
+		assert_eq!(Value::Number(3.0).to_string(), "3");
+		assert_eq!(Value::Number(-12.0).to_string(), "-12");
+		assert_eq!(Value::Number(0.25).to_string(), "0.25");
+		assert_eq!(Value::Number(1e20).to_string(), "100000000000000000000");
+	}
+
+	#[test]
+	fn errors_on_truncated_or_trailing_input() {
+		for bad in ["", " ", "[", "[1,", "[1", "{", r#"{"a""#, r#"{"a":"#, r#"{"a":1,"#, r#""abc"#, r#""a\"#, "tru", "nul", "[1,]", r#"{"a":1,}"#, "{1:2}"] {
+			assert!(parse(bad).is_err(), "{:?} should fail", bad);
+		}
+		for bad in ["1 2", "{}x", "[]]", "null null", r#""a""b""#] {
+			assert_eq!(parse(bad).unwrap_err().split(" at ").next(), Some("trailing characters"), "{:?}", bad);
+		}
+	}
+}
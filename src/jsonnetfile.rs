@@ -0,0 +1,44 @@
+// Reading jsonnet-bundler's jsonnetfile.json, so that a vendored package which declares local
+// dependencies of its own can have its imports resolved the way jb would.
+
+use crate::json;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+pub const FILENAME: &str = "jsonnetfile.json";
+// The lock file jb writes alongside jsonnetfile.json, which marks the root of a jsonnet project.
+pub const LOCK_FILENAME: &str = "jsonnetfile.lock.json";
+
+// The local directories declared as dependencies in the jsonnetfile.json in dir, relative to the
+// current directory, or None if dir doesn't have one. Other kinds of dependency (eg. git) are ignored,
+// as they are fetched into vendor/ rather than resolved from where they're declared.
+pub fn local_paths(dir: &Path, fs: &crate::fs::Budget) -> Result<Option<Vec<PathBuf>>, String> {
+	let path = dir.join(FILENAME);
+	let contents = match fs.read_to_string(&path) {
+		Ok(contents) => contents,
+		Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+		Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+	};
+	let value = json::parse(&contents).map_err(|e| format!("Failed to parse {}: {}", path.display(), e))?;
+	let dependencies = match value.get("dependencies") {
+		None => return Ok(Some(Vec::new())),
+		Some(dependencies) => dependencies.as_array()
+			.ok_or_else(|| format!("{}: \"dependencies\" must be a list", path.display()))?,
+	};
+	let mut paths = Vec::new();
+	for dependency in dependencies {
+		let local = dependency.get("source").and_then(|source| source.get("local"));
+		if let Some(local) = local {
+			let directory = local.get("directory").and_then(|directory| directory.as_str())
+				.ok_or_else(|| format!("{}: local dependency is missing \"directory\"", path.display()))?;
+			paths.push(dir.join(directory));
+		}
+	}
+	Ok(Some(paths))
+}
+
+// Whether dir has a jsonnetfile.lock.json.
+pub fn has_lock(dir: &Path, fs: &crate::fs::Budget) -> Result<bool, String> {
+	let path = dir.join(LOCK_FILENAME);
+	fs.exists(&path).map_err(|e| format!("Could not check path {}: {}", path.display(), e))
+}
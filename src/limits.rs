@@ -0,0 +1,51 @@
+// Limits on how much work a run may do, to stop a traversal that has exploded
+// (eg. because something imports a huge generated tree) from tying up shared machines.
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+// The exit code used when a limit is exceeded, so callers can tell this apart from other failures.
+pub const EXIT_CODE: u8 = 3;
+
+// Set once any limit is exceeded. Errors are only strings, so this is how main knows to use EXIT_CODE.
+static EXCEEDED: AtomicBool = AtomicBool::new(false);
+
+pub fn exceeded() -> bool {
+	EXCEEDED.load(Ordering::Relaxed)
+}
+
+pub struct Limits {
+	// Fail once more than this many files have been analyzed.
+	pub max_files: Option<usize>,
+	// Fail once the run has taken longer than this.
+	pub max_time: Option<Duration>,
+	started: Instant,
+	files: AtomicUsize,
+}
+
+impl Limits {
+	pub fn new() -> Limits {
+		Limits { max_files: None, max_time: None, started: Instant::now(), files: AtomicUsize::new(0) }
+	}
+
+	// Called before analyzing each file.
+	pub fn file_analyzed(&self) -> Result<(), String> {
+		let files = self.files.fetch_add(1, Ordering::Relaxed) + 1;
+		if let Some(max_files) = self.max_files {
+			if files > max_files {
+				EXCEEDED.store(true, Ordering::Relaxed);
+				return Err(format!("Aborting as more than {} files were analyzed (--max-files)", max_files));
+			}
+		}
+		if let Some(max_time) = self.max_time {
+			if self.started.elapsed() > max_time {
+				EXCEEDED.store(true, Ordering::Relaxed);
+				return Err(format!(
+					"Aborting as the run took longer than {}s, after analyzing {} files (--max-seconds)",
+					max_time.as_secs(), files,
+				));
+			}
+		}
+		Ok(())
+	}
+}
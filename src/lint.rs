@@ -0,0 +1,290 @@
+// Checks for imports which work, but are written in a way that makes them harder
+// to read or search for, or aren't needed at all.
+
+use crate::fix::Edit;
+use crate::health;
+use crate::json::Value;
+use crate::resolve::{normalize, relative, Normalization, Resolver};
+use crate::scan::{self, Import, ImportKind, ImportLocal};
+use crate::{closure_analyses, Cache, Deadline, Importers, Options, Root};
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+
+// Each rule, with a short description of what it finds.
+pub const RULES: [(&str, &str); 6] = [
+	("non-normalized", "Import path contains components that aren't needed"),
+	("mixed-resolution", "Import is relative to the file, but the same file is imported from a library path elsewhere"),
+	("local-shadows-jpath", "Import found relative to the file shadows a file of the same name in a library path"),
+	("unused-import", "Imported local is never used"),
+	("unresolved-import", "Import isn't found relative to the file or in any library path"),
+	("import-cycle", "Import is part of a cycle of files which import each other"),
+];
+
+pub struct Problem {
+	pub file: PathBuf,
+	pub line: usize,
+	pub rule: &'static str,
+	pub message: String,
+	// How to fix it, if we know a safe way to.
+	pub fix: Option<Edit>,
+}
+
+// An import along with what we worked out about it.
+struct Resolved<'a> {
+	file: &'a Path,
+	import: &'a Import,
+	// The normalized path of the file it refers to.
+	target: PathBuf,
+	// Whether it was found in a library path rather than relative to the file.
+	via_jpath: bool,
+	// If it was found relative to the file, the first file in a library path it would otherwise have been.
+	shadowed: Option<PathBuf>,
+	jpaths: &'a [PathBuf],
+}
+
+// An edit replacing the import with one of the new path, if the new path refers to the same file.
+fn fix_import(options: &Options, cache: &Cache, resolved: &Resolved, path: &Path) -> Result<Option<Edit>, String> {
+	let jpaths = cache.jpaths_for(options, resolved.jpaths, resolved.file)?;
+	let jpaths: Vec<&Path> = jpaths.iter().map(|path| path.as_path()).collect();
+	let base_dir = resolved.file.parent().unwrap();
+	let resolver = Resolver { base_dir, jpaths: &jpaths, fs_checks: options.fs_checks, fs: &options.fs, normalization: Normalization::Verbatim };
+	if normalize(&resolver.resolve(path)?) != resolved.target {
+		return Ok(None);
+	}
+	let path = Value::String(path.to_string_lossy().into_owned());
+	Ok(Some(Edit {
+		start: resolved.import.span.start,
+		end: resolved.import.span.end,
+		replacement: format!("{} {}", resolved.import.kind.name(), path),
+	}))
+}
+
+fn is_ident_char(c: char) -> bool {
+	c.is_ascii_alphanumeric() || c == '_'
+}
+
+// Whether the local's name appears anywhere in the file besides where it's bound. This is purely textual,
+// so a mention in a comment or string counts as a use. That's fine as it only means we miss a problem,
+// rather than removing an import that's needed.
+fn is_used(contents: &str, local: &ImportLocal) -> bool {
+	contents.match_indices(local.name.as_str()).any(|(start, _)| {
+		let end = start + local.name.len();
+		let before = contents[..start].chars().next_back();
+		let after = contents[end..].chars().next();
+		let whole_word = !before.is_some_and(is_ident_char) && !after.is_some_and(is_ident_char);
+		whole_word && !(local.start..local.import.end).contains(&start)
+	})
+}
+
+// An edit removing the local, along with its line if nothing else is on it.
+// None if there's anything but whitespace between the import and the semicolon, eg. a comment.
+fn remove_local(contents: &str, local: &ImportLocal) -> Option<Edit> {
+	let after = &contents[local.import.end..];
+	let semicolon = after.find(|c: char| !c.is_whitespace()).filter(|&i| after[i..].starts_with(';'))?;
+	let mut start = local.start;
+	let mut end = local.import.end + semicolon + 1;
+	let line_start = contents[..start].rfind('\n').map_or(0, |i| i + 1);
+	let line_end = contents[end..].find('\n').map_or(contents.len(), |i| end + i + 1);
+	if contents[line_start..start].trim().is_empty() && contents[end..line_end].trim().is_empty() {
+		start = line_start;
+		end = line_end;
+	} else {
+		end += contents[end..].len() - contents[end..].trim_start_matches([' ', '\t']).len();
+	}
+	Some(Edit { start, end, replacement: String::new() })
+}
+
+// Check every import in the deps of the roots, each file only once.
+pub fn lint(options: &Options, cache: &Cache, roots: &[Root]) -> Result<Vec<Problem>, String> {
+	// Gather everything first, as whether a target is imported in different ways depends on all of them.
+	let mut files = Vec::new();
+	let mut seen = HashSet::new();
+	let mut importers = Importers::default();
+	for root in roots {
+		let analyses = closure_analyses(options, cache, Deadline::new(&root.path), &root.jpaths, &root.path)?;
+		// Each file is only checked for the first root it's found with, so it's that root's chain that counts.
+		for (dep, importer) in Importers::of_closure(options, &analyses).0 {
+			if !seen.contains(&dep) {
+				importers.add(&importer, &dep);
+			}
+		}
+		for (file, analysis) in analyses {
+			if seen.insert(file.clone()) {
+				let imports = cache.parsed(options, &file).map_err(|e| importers.chain(options, &file, e))?;
+				files.push((file, &root.jpaths, imports, analysis));
+			}
+		}
+	}
+	let mut all = Vec::new();
+	for (file, jpaths, imports, _) in &files {
+		let file_jpaths = cache.jpaths_for(options, jpaths, file)?;
+		let jpath_refs: Vec<&Path> = file_jpaths.iter().map(|path| path.as_path()).collect();
+		let base_dir = file.parent().unwrap();
+		// Verbatim, so that we can tell how it was found. The target is normalized separately.
+		let resolver = Resolver { base_dir, jpaths: &jpath_refs, fs_checks: options.fs_checks, fs: &options.fs, normalization: Normalization::Verbatim };
+		for import in imports.iter() {
+			// Absolute imports can only be written one way, near enough.
+			if import.path.is_absolute() {
+				continue;
+			}
+			let resolved = resolver.resolve(&import.path)?;
+			let via_jpath = resolved != base_dir.join(&import.path);
+			let shadowed = if via_jpath || !options.fs_checks {
+				None
+			} else {
+				let mut shadowed = None;
+				for jpath in &jpath_refs {
+					let candidate = jpath.join(&import.path);
+					if options.fs.exists(&candidate).map_err(|e| format!("Could not check path {}: {}", candidate.display(), e))? {
+						// A file in a library path importing its sibling finds the same file either way.
+						if normalize(&candidate) != normalize(&resolved) {
+							shadowed = Some(candidate);
+						}
+						break;
+					}
+				}
+				shadowed
+			};
+			all.push(Resolved {
+				file,
+				import,
+				target: normalize(&resolved),
+				via_jpath,
+				shadowed,
+				jpaths,
+			});
+		}
+	}
+
+	// For each target that is imported via a library path, how it was written.
+	let mut jpath_imports: BTreeMap<&Path, &Path> = BTreeMap::new();
+	for resolved in all.iter().filter(|resolved| resolved.via_jpath) {
+		jpath_imports.entry(&resolved.target).or_insert(&resolved.import.path);
+	}
+
+	let mut problems = Vec::new();
+	for (file, _, imports, analysis) in &files {
+		for (import, target) in imports.iter().zip(&analysis.targets) {
+			if analysis.unresolved.contains(target) {
+				problems.push(Problem {
+					file: file.clone(),
+					line: import.span.line,
+					rule: "unresolved-import",
+					message: format!("{:?} isn't found relative to this file or in any library path", import.path),
+					fix: None,
+				});
+			}
+		}
+	}
+	// Each cycle is reported once, at the first import in the first file of it that leads to another file in it.
+	let edges = files.iter().map(|(file, _, _, analysis)| (file.clone(), analysis.deep_deps.iter().cloned().collect())).collect();
+	for cycle in health::cycles(&edges) {
+		let (file, _, imports, analysis) = files.iter().find(|(file, _, _, _)| *file == cycle[0]).expect("cycles are of linted files");
+		let Some(import) = imports.iter().zip(&analysis.targets).find_map(|(import, target)| {
+			(import.kind == ImportKind::Import && cycle.contains(target)).then_some(import)
+		}) else {
+			continue;
+		};
+		let as_str: Vec<_> = cycle.iter().map(|p| p.to_string_lossy()).collect();
+		problems.push(Problem {
+			file: file.clone(),
+			line: import.span.line,
+			rule: "import-cycle",
+			message: format!("{:?} is part of an import cycle between {}", import.path, as_str.join(", ")),
+			fix: None,
+		});
+	}
+	// Imports which would be removed entirely, so there's no point complaining about how they're written.
+	let mut removed = HashSet::new();
+	for (file, _, _, _) in &files {
+		let contents = options.fs.read_to_string(file).map_err(|e| importers.chain(options, file, format!("Failed to read {}: {}", file.display(), e)))?;
+		for local in scan::parse_import_locals(file, &contents).map_err(|e| importers.chain(options, file, e))? {
+			if is_used(&contents, &local) {
+				continue;
+			}
+			removed.insert((file.as_path(), local.import.start));
+			problems.push(Problem {
+				file: file.clone(),
+				line: local.import.line,
+				rule: "unused-import",
+				message: format!("{} is imported but never used", local.name),
+				fix: remove_local(&contents, &local),
+			});
+		}
+	}
+	for resolved in &all {
+		if removed.contains(&(resolved.file, resolved.import.span.start)) {
+			continue;
+		}
+		let written = &resolved.import.path;
+		let problem = |rule, message, fix| Problem { file: resolved.file.to_owned(), line: resolved.import.span.line, rule, message, fix };
+		// This is how a stale local copy of a library silently takes precedence over the real one.
+		if let Some(shadowed) = &resolved.shadowed {
+			problems.push(problem("local-shadows-jpath", format!(
+				"{:?} is found relative to this file as {}, shadowing {} from a library path",
+				written, resolved.target.display(), shadowed.display(),
+			), None));
+		}
+		// Prefer library paths, as those don't change when the importing file moves.
+		if let (false, Some(jpath_import)) = (resolved.via_jpath, jpath_imports.get(resolved.target.as_path())) {
+			let canonical = normalize(jpath_import);
+			problems.push(problem("mixed-resolution", format!(
+				"{:?} is relative to this file, but {} is imported from a library path elsewhere as {:?}",
+				written, resolved.target.display(), canonical,
+			), fix_import(options, cache, resolved, &canonical)?));
+			continue;
+		}
+		let canonical = if resolved.via_jpath {
+			Some(normalize(written))
+		} else {
+			relative(&normalize(resolved.file.parent().unwrap()), &resolved.target)
+		};
+		if let Some(canonical) = canonical {
+			if canonical.as_os_str() != written.as_os_str() && !canonical.as_os_str().is_empty() {
+				problems.push(problem("non-normalized", format!(
+					"{:?} should be written {:?}", written, canonical,
+				), fix_import(options, cache, resolved, &canonical)?));
+			}
+		}
+	}
+	problems.sort_by(|a, b| a.file.cmp(&b.file).then(a.line.cmp(&b.line)));
+	Ok(problems)
+}
+
+// Fix the problems that can be, or with dry_run, print a diff of the fixes instead.
+// Returns whether each problem was (or would be) fixed.
+pub fn fix(problems: &[Problem], dry_run: bool) -> Result<Vec<bool>, String> {
+	let mut by_file: BTreeMap<&Path, Vec<(usize, &Edit)>> = BTreeMap::new();
+	for (i, problem) in problems.iter().enumerate() {
+		if let Some(fix) = &problem.fix {
+			by_file.entry(&problem.file).or_default().push((i, fix));
+		}
+	}
+	let mut fixed = vec![false; problems.len()];
+	for (file, edits) in by_file {
+		let applied = crate::fix::apply_file(file, &edits.iter().map(|(_, edit)| *edit).collect::<Vec<_>>(), dry_run)?;
+		for ((i, _), applied) in edits.iter().zip(applied) {
+			fixed[*i] = applied;
+		}
+	}
+	Ok(fixed)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn sibling_in_jpath_does_not_shadow_itself() {
+		let dir = std::env::temp_dir().join(format!("jsonnet_dep_graph_lint_{}", std::process::id()));
+		std::fs::create_dir_all(dir.join("lib")).unwrap();
+		std::fs::write(dir.join("main.jsonnet"), "import 'a.libsonnet'\n").unwrap();
+		std::fs::write(dir.join("lib/a.libsonnet"), "import 'b.libsonnet'\n").unwrap();
+		std::fs::write(dir.join("lib/b.libsonnet"), "{}\n").unwrap();
+		let root = Root { path: dir.join("main.jsonnet"), jpaths: vec![dir.join("lib")], meta: Vec::new(), target: None };
+		let problems = lint(&Options::new(), &Cache::default(), &[root]);
+		std::fs::remove_dir_all(&dir).unwrap();
+		let rules: Vec<_> = problems.unwrap().iter().map(|problem| problem.rule).collect();
+		assert!(!rules.contains(&"local-shadows-jpath"), "unexpected problems: {:?}", rules);
+	}
+}
@@ -1,42 +1,56 @@
-use jrsonnet_parser::*;
+mod age;
+mod annotate;
+mod batch;
+mod bazel;
+mod check;
+mod codeowners;
+mod config;
+mod conformance;
+mod depfile;
+mod diagram;
+mod duplicates;
+mod fix;
+mod fs;
+mod graph;
+mod grep;
+mod health;
+#[cfg(feature = "git")]
+mod history;
+#[cfg(feature = "git")]
+mod hooks;
+mod index;
+mod jobserver;
+mod json;
+mod jsonnetfile;
+mod limits;
+mod lint;
+mod nix;
+mod output;
+mod partition;
+mod plan;
+mod reduce;
+mod repro;
+mod resolve;
+mod resources;
+mod sarif;
+mod scan;
+mod self_check;
+mod sha256;
+mod stats;
+mod stream;
+mod tar;
+mod template;
+mod watch;
+mod worker;
+mod yaml;
+
+use resolve::{Normalization, Resolver};
+use scan::{DataFormat, Import, ImportKind, Origin};
 use std::path::{Path, PathBuf};
-use std::collections::{HashMap, HashSet};
-use std::collections::hash_map::Entry;
-
-struct Resolver<'a> {
-	base_dir: &'a Path,
-	jpaths: &'a [&'a Path],
-}
-
-impl<'a> Resolver<'a> {
-	fn resolve(&self, path: &Path) -> Result<PathBuf, String> {
-		// If path is absolute, no need to check anything either as the prefix doesn't matter.
-		if path.is_absolute() {
-			return Ok(path.to_owned());
-		}
-		// If no jpaths set, this is a no-op and doesn't need to check for existence.
-		if self.jpaths.is_empty() {
-			return Ok(self.base_dir.join(path));
-		}
-		// Find the first extant match.
-		// Fail if we can't determine existence for any candidate.
-		for prefix in std::iter::once(self.base_dir).chain(self.jpaths.iter().copied()) {
-			let candidate = prefix.join(path);
-			let exists = candidate.try_exists().map_err(|e|
-				format!("Could not check path {}: {}", path.display(), e)
-			)?;
-			if exists {
-				return Ok(candidate);
-			}
-		}
-		// None existed, fall back to the local case.
-		// This seems more useful than erroring.
-		// It will likely error later anyway, when we try to parse that file.
-		// However, this behaviour is useful if the subject is a leaf dep
-		// that is a generated file.
-		return Ok(self.base_dir.join(path));
-	}
-}
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 #[derive(Default, Debug)]
 struct Analysis {
@@ -46,233 +60,1985 @@ struct Analysis {
 	// Deep deps are jsonnet files where a change in that file *or any of its dependences*
 	// can affect the analysed file.
 	deep_deps: Vec<PathBuf>,
+	// Deps (of either kind) which are only imported in places that may not be evaluated.
+	conditional: Vec<PathBuf>,
+	// Each distinct combination of dep and where in the file it was imported.
+	origins: Vec<(PathBuf, Origin)>,
+	// Deps (of either kind) which weren't found in any library path, so were assumed to be relative to the file.
+	unresolved: Vec<PathBuf>,
+	// What each of the file's imports resolved to, in the same order as its parsed imports.
+	targets: Vec<PathBuf>,
+	// Leaf deps which are parsed as data, and what format. See scan::DataFormat.
+	data_inputs: Vec<(PathBuf, DataFormat)>,
 }
 
-fn analyze_file(jpaths: &[&Path], filepath: &Path) -> Result<Analysis, String> {
-	let contents = std::fs::read_to_string(filepath).map_err(|e|
-		format!("Failed to read {}: {}", filepath.display(), e)
-	)?;
+// Which of a root's deps to output.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum DepsKind {
+	All,
+	// Jsonnet files evaluated as code, ie. the root and anything reached through import.
+	Code,
+	// Files read as data, ie. with importstr or importbin, or passed with --ext-str-file and the like.
+	Data,
+}
 
-	let settings = ParserSettings {
-		loc_data: false,
-		file_name: filepath.to_owned().into(),
-	};
+impl DepsKind {
+	fn name(&self) -> &'static str {
+		match self {
+			DepsKind::All => "all",
+			DepsKind::Code => "code",
+			DepsKind::Data => "data",
+		}
+	}
+
+	fn parse(name: &str) -> Result<DepsKind, String> {
+		[DepsKind::All, DepsKind::Code, DepsKind::Data].into_iter()
+			.find(|kind| kind.name() == name)
+			.ok_or_else(|| format!("Unknown kind of deps {:?}, expected one of: all, code, data", name))
+	}
+}
+
+// Settings which apply to the whole run.
+struct Options {
+	fs_checks: bool,
+	format: output::Format,
+	// Files passed to jsonnet with --ext-code-file or --tla-code-file. These are evaluated
+	// alongside every root, so they and their deps are deps of every root.
+	code_files: Vec<PathBuf>,
+	// Files passed to jsonnet with --ext-str-file or --tla-str-file, which are leaf deps of every root.
+	str_files: Vec<PathBuf>,
+	// Retries and concurrency limits for filesystem operations.
+	fs: fs::Budget,
+	// Give up on finding a root's deps if it takes longer than this.
+	per_root_timeout: Option<Duration>,
+	// Limits on the run as a whole.
+	limits: limits::Limits,
+	// Also resolve imports using the local dependencies declared in the nearest jsonnetfile.json.
+	jsonnetfile_jpaths: bool,
+	// Settings from --config.
+	config: config::Config,
+	// Include the full lists of deps in --format table.
+	verbose: bool,
+	// Include the files parsed as data in --format json and yaml.
+	structured_data: bool,
+	// Include where each dep is imported, by each file that imports it, in --format json and yaml.
+	occurrences: bool,
+	// Follow each make rule with an empty rule for each dep.
+	phony: bool,
+	// With --fail-fast, say which chain of imports led to a file which couldn't be analyzed.
+	fail_fast: bool,
+	// With --provenance, list how each dep was imported, and by which files.
+	provenance: bool,
+	// With --hash, include the SHA-256 of each dep in json or yaml output.
+	hash: bool,
+	// With --with-metadata, include the size and mtime of each dep in json or yaml output.
+	with_metadata: bool,
+	// With --target, what to write as the target of each make rule instead of the root.
+	target: Option<String>,
+	// Fail on paths that would need escaping in make rules and depfiles, rather than escaping them.
+	strict_paths: bool,
+	// The version of the output asked for with --output-version, if any.
+	output_version: Option<u32>,
+	// The kind of rule to output with --format bazel.
+	bazel_rule: String,
+	// The command and output of each rule with --format tup.
+	tup_command: String,
+	tup_output: String,
+	// The directory paths are relative to with --format nix.
+	nix_root: PathBuf,
+	// Start each root's list of deps in --format print0 with the root, and end it with an empty entry.
+	print0_roots: bool,
+	// With --template, what to render for each root.
+	template: Option<template::Template>,
+	// Which deps to output, with --deps-kind.
+	deps_kind: DepsKind,
+	// With --skip-disabled-imports, the values of --ext-str to work out if conditions with.
+	skip_disabled: Option<HashMap<String, String>>,
+	// How resolved paths (and roots) are written, and so which of them are the same file, with --normalize-paths.
+	normalization: Normalization,
+	// With --relative-to, the directory to output paths relative to, and the current directory.
+	// Both are absolute and normalized.
+	relative_to: Option<(PathBuf, PathBuf)>,
+	// Include each root's jsonnetfile.lock.json in --format json and yaml, and group roots by it with --union.
+	group_by_lock: bool,
+	// The path given to the --exec-expr root, which isn't a real file and so isn't one of its deps.
+	exec_root: Option<PathBuf>,
+}
+
+impl Options {
+	// The defaults, before any arguments are applied.
+	fn new() -> Options {
+		Options {
+			fs_checks: true,
+			format: output::Format::Make,
+			code_files: Vec::new(),
+			str_files: Vec::new(),
+			fs: fs::Budget::new(3),
+			per_root_timeout: None,
+			limits: limits::Limits::new(),
+			jsonnetfile_jpaths: false,
+			config: config::Config::default(),
+			exec_root: None,
+			verbose: false,
+			structured_data: false,
+			occurrences: false,
+			group_by_lock: false,
+			phony: false,
+			strict_paths: false,
+			target: None,
+			hash: false,
+			provenance: false,
+			fail_fast: false,
+			with_metadata: false,
+			output_version: None,
+			bazel_rule: "filegroup".into(),
+			tup_command: "jsonnet %f -o %o".into(),
+			tup_output: "%B.json".into(),
+			nix_root: PathBuf::new(),
+			print0_roots: false,
+			template: None,
+			deps_kind: DepsKind::All,
+			skip_disabled: None,
+			relative_to: None,
+			normalization: Normalization::Verbatim,
+		}
+	}
+
+	// The path of a file given to us, eg. a root, written the same way as any import of it would be.
+	fn normalized(&self, path: PathBuf) -> Result<PathBuf, String> {
+		self.normalization.apply(path, &self.fs)
+	}
+}
+
+// The size of a file in bytes, and when it was last modified, in seconds since the epoch.
+#[derive(Clone, Copy)]
+struct Metadata {
+	size: u64,
+	mtime: u64,
+}
+
+// Analysis of each file, for one set of jpaths.
+type Analyses = HashMap<PathBuf, Arc<Analysis>>;
+
+#[derive(Default)]
+struct Cache {
+	// The imports in each file, as written. These don't depend on resolution settings,
+	// so they are shared between all roots.
+	parsed: Mutex<HashMap<PathBuf, Arc<[Import]>>>,
+	// The analysis of each file, which depends on the jpaths used to resolve its imports.
+	// So we keep a separate map per distinct jpath list.
+	analyses: Mutex<HashMap<Vec<PathBuf>, Analyses>>,
+	// The local dependencies declared by the jsonnetfile.json in each directory, or None if there isn't one.
+	jsonnetfiles: Mutex<HashMap<PathBuf, Option<Arc<[PathBuf]>>>>,
+	// Whether each directory has a jsonnetfile.lock.json.
+	locks: Mutex<HashMap<PathBuf, bool>>,
+	// With --hash, the SHA-256 of each file's contents, kept from when it was read to be parsed where possible.
+	digests: Mutex<HashMap<PathBuf, String>>,
+	// With --with-metadata, the size and mtime of each file, likewise.
+	metadata: Mutex<HashMap<PathBuf, Metadata>>,
+	// How many files were parsed, and how many analyses were found already done or not, for --resource-summary.
+	parses: AtomicUsize,
+	hits: AtomicUsize,
+	misses: AtomicUsize,
+}
+
+// The cache may be shared between threads. We don't hold the locks while doing any actual work,
+// so that threads can work on different files at once. This means two threads may occasionally
+// do the same work twice, but they'll get the same result so it doesn't matter which one is kept.
+impl Cache {
+	fn parsed(&self, options: &Options, filepath: &Path) -> Result<Arc<[Import]>, String> {
+		if let Some(imports) = self.parsed.lock().unwrap().get(filepath) {
+			return Ok(imports.clone());
+		}
+		let imports: Arc<[Import]> = match options.hash || options.with_metadata {
+			false => scan::parse_file(filepath, &options.fs, options.skip_disabled.as_ref())?.into(),
+			// Keep what --hash and --with-metadata need while we have the contents, so they match what was parsed.
+			true => {
+				let contents = options.fs.read_to_string(filepath).map_err(|e| format!("Failed to read {}: {}", filepath.display(), e))?;
+				if options.hash {
+					self.insert_digest(filepath, sha256::hex_digest(contents.as_bytes()));
+				}
+				if options.with_metadata {
+					let metadata = options.fs.metadata(filepath).map_err(|e| format!("Failed to stat {}: {}", filepath.display(), e))?;
+					self.insert_metadata(filepath, Metadata { size: contents.len() as u64, mtime: index::mtime_of(&metadata) });
+				}
+				scan::parse_contents(filepath, &contents, options.skip_disabled.as_ref())?.into()
+			},
+		};
+		self.parses.fetch_add(1, Ordering::Relaxed);
+		self.parsed.lock().unwrap().insert(filepath.to_owned(), imports.clone());
+		Ok(imports)
+	}
+
+	fn analysis(&self, options: &Options, jpaths: &[PathBuf], filepath: &Path) -> Result<Arc<Analysis>, String> {
+		let cached = self.analyses.lock().unwrap().get(jpaths).and_then(|analyses| analyses.get(filepath)).cloned();
+		if let Some(analysis) = cached {
+			self.hits.fetch_add(1, Ordering::Relaxed);
+			return Ok(analysis);
+		}
+		self.misses.fetch_add(1, Ordering::Relaxed);
+		options.limits.file_analyzed()?;
+		let analysis = Arc::new(analyze_file(options, self, jpaths, filepath)?);
+		self.analyses.lock().unwrap()
+			.entry(jpaths.to_vec()).or_default()
+			.insert(filepath.to_owned(), analysis.clone());
+		Ok(analysis)
+	}
+
+	// The jpaths to resolve the file's imports with, given the jpaths for the root.
+	fn jpaths_for(&self, options: &Options, jpaths: &[PathBuf], filepath: &Path) -> Result<Vec<PathBuf>, String> {
+		let jpaths = options.config.jpaths_for(filepath).unwrap_or(jpaths);
+		if !options.jsonnetfile_jpaths {
+			return Ok(jpaths.to_vec());
+		}
+		// The nearest jsonnetfile.json applies to everything below it, and its local dependencies take precedence.
+		let mut scoped = Vec::new();
+		for dir in filepath.parent().unwrap().ancestors() {
+			let cached = self.jsonnetfiles.lock().unwrap().get(dir).cloned();
+			let local_paths = match cached {
+				Some(local_paths) => local_paths,
+				None => {
+					let local_paths: Option<Arc<[PathBuf]>> = jsonnetfile::local_paths(dir, &options.fs)?.map(Into::into);
+					self.jsonnetfiles.lock().unwrap().insert(dir.to_owned(), local_paths.clone());
+					local_paths
+				},
+			};
+			if let Some(local_paths) = local_paths {
+				scoped.extend(local_paths.iter().cloned());
+				break;
+			}
+		}
+		scoped.extend(jpaths.iter().cloned());
+		Ok(scoped)
+	}
+
+	// Each directory found so far with a jsonnetfile.json, with the local dependencies declared in it.
+	fn jsonnetfiles(&self) -> Vec<(PathBuf, Arc<[PathBuf]>)> {
+		let jsonnetfiles = self.jsonnetfiles.lock().unwrap();
+		jsonnetfiles.iter().filter_map(|(dir, local_paths)| Some((dir.clone(), local_paths.clone()?))).collect()
+	}
+
+	// The jsonnetfile.lock.json of the project the file belongs to, ie. the one in the nearest directory above it
+	// which has one, if any. This is found lexically, so a file reached through .. after a symlink may be misplaced.
+	fn lock_for(&self, options: &Options, filepath: &Path) -> Result<Option<PathBuf>, String> {
+		let filepath = resolve::normalize(filepath);
+		for dir in filepath.parent().unwrap_or(Path::new("")).ancestors() {
+			let cached = self.locks.lock().unwrap().get(dir).copied();
+			let has_lock = match cached {
+				Some(has_lock) => has_lock,
+				None => {
+					let has_lock = jsonnetfile::has_lock(dir, &options.fs)?;
+					self.locks.lock().unwrap().insert(dir.to_owned(), has_lock);
+					has_lock
+				},
+			};
+			if has_lock {
+				return Ok(Some(dir.join(jsonnetfile::LOCK_FILENAME)));
+			}
+		}
+		Ok(None)
+	}
+
+	fn insert_parsed(&self, filepath: &Path, imports: Vec<Import>) {
+		self.parsed.lock().unwrap().insert(filepath.to_owned(), imports.into());
+	}
+
+	fn insert_digest(&self, filepath: &Path, digest: String) {
+		self.digests.lock().unwrap().insert(filepath.to_owned(), digest);
+	}
+
+	// The SHA-256 of the file's contents, reading it only if it wasn't already read to be parsed,
+	// eg. because it's a leaf dep. None if the file doesn't exist, as unresolved deps may not.
+	fn digest(&self, options: &Options, filepath: &Path) -> Result<Option<String>, String> {
+		if let Some(digest) = self.digests.lock().unwrap().get(filepath) {
+			return Ok(Some(digest.clone()));
+		}
+		let digest = match options.fs.read(filepath) {
+			Ok(contents) => sha256::hex_digest(&contents),
+			Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+			Err(e) => return Err(format!("Failed to read {}: {}", filepath.display(), e)),
+		};
+		self.insert_digest(filepath, digest.clone());
+		Ok(Some(digest))
+	}
+
+	fn insert_metadata(&self, filepath: &Path, metadata: Metadata) {
+		self.metadata.lock().unwrap().insert(filepath.to_owned(), metadata);
+	}
+
+	// As digest, but the file's size and mtime, which only needs a stat if it wasn't already read to be parsed.
+	fn file_metadata(&self, options: &Options, filepath: &Path) -> Result<Option<Metadata>, String> {
+		if let Some(metadata) = self.metadata.lock().unwrap().get(filepath) {
+			return Ok(Some(*metadata));
+		}
+		let metadata = match options.fs.metadata(filepath) {
+			Ok(metadata) => Metadata { size: metadata.len(), mtime: index::mtime_of(&metadata) },
+			Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+			Err(e) => return Err(format!("Failed to stat {}: {}", filepath.display(), e)),
+		};
+		self.insert_metadata(filepath, metadata);
+		Ok(Some(metadata))
+	}
 
-	let ast = parse(&contents, &settings).map_err(|e|
-		format!("Failed to parse {}: {}", filepath.display(), e)
-	)?;
+	// Forget anything we know that may be affected by changes to the given files.
+	fn invalidate(&self, changed: &[PathBuf]) {
+		let mut parsed = self.parsed.lock().unwrap();
+		let mut digests = self.digests.lock().unwrap();
+		let mut metadata = self.metadata.lock().unwrap();
+		for path in changed {
+			parsed.remove(path);
+			digests.remove(path);
+			metadata.remove(path);
+		}
+		// A file being created or deleted can change how any import is resolved,
+		// so we can't know which analyses are still valid. Since resolution
+		// is cheap compared to parsing, just throw them all away.
+		self.analyses.lock().unwrap().clear();
+		self.jsonnetfiles.lock().unwrap().clear();
+		self.locks.lock().unwrap().clear();
+	}
+
+	// Forget everything, as if nothing had been analyzed yet.
+	fn clear(&self) {
+		self.parsed.lock().unwrap().clear();
+		self.digests.lock().unwrap().clear();
+		self.metadata.lock().unwrap().clear();
+		self.invalidate(&[]);
+	}
+
+	// As invalidate, but given normalized paths, so that files are forgotten however they were reached.
+	#[cfg(feature = "git")]
+	fn invalidate_normalized(&self, changed: &HashSet<PathBuf>) {
+		let stale: Vec<PathBuf> = self.parsed.lock().unwrap().keys()
+			.filter(|path| changed.contains(&resolve::normalize(path)))
+			.cloned()
+			.collect();
+		self.invalidate(&stale);
+	}
+}
+
+fn analyze_file(options: &Options, cache: &Cache, jpaths: &[PathBuf], filepath: &Path) -> Result<Analysis, String> {
+	let imports = cache.parsed(options, filepath)?;
 
 	// Path should always have a parent given we managed to open it as a file earlier, so it
 	// can't be a directory or "".
 	let base_dir = filepath.parent().unwrap();
-	let resolver = Resolver { base_dir, jpaths };
+	let jpaths = cache.jpaths_for(options, jpaths, filepath)?;
+	let jpaths: Vec<&Path> = jpaths.iter().map(|path| path.as_path()).collect();
+	let resolver = Resolver { base_dir, jpaths: &jpaths, fs_checks: options.fs_checks, fs: &options.fs, normalization: options.normalization };
 
 	let mut analysis = Analysis::default();
-	scan_ast(&resolver, &mut analysis, &ast)?;
-
+	let mut unconditional = HashSet::new();
+	for import in imports.iter() {
+		let paths = match import.kind {
+			ImportKind::Import => &mut analysis.deep_deps,
+			ImportKind::ImportStr => &mut analysis.leaf_deps,
+		};
+		let (path, unresolved) = add_path(&resolver, paths, &import.path)?;
+		if unresolved && !analysis.unresolved.contains(&path) {
+			analysis.unresolved.push(path.clone());
+		}
+		analysis.targets.push(path.clone());
+		if let Some(format) = import.data_format {
+			if !analysis.data_inputs.contains(&(path.clone(), format)) {
+				analysis.data_inputs.push((path.clone(), format));
+			}
+		}
+		let origin = (path.clone(), import.origin);
+		if !analysis.origins.contains(&origin) {
+			analysis.origins.push(origin);
+		}
+		if !import.conditional {
+			unconditional.insert(path);
+		}
+	}
+	analysis.conditional = analysis.leaf_deps.iter()
+		.chain(analysis.deep_deps.iter())
+		.filter(|path| !unconditional.contains(*path))
+		.cloned()
+		.collect();
 	Ok(analysis)
 }
 
-fn add_path(resolver: &Resolver, paths: &mut Vec<PathBuf>, path: &Path) -> Result<(), String> {
-	let path = resolver.resolve(path)?;
+// Resolve the path and add it to the list if it isn't already present. Returns the resolved path,
+// and whether it wasn't found anywhere.
+fn add_path(resolver: &Resolver, paths: &mut Vec<PathBuf>, path: &Path) -> Result<(PathBuf, bool), String> {
+	let (path, unresolved) = resolver.resolve_checked(path)?;
 	if !paths.contains(&path) {
-		paths.push(path);
+		paths.push(path.clone());
 	}
-	Ok(())
+	Ok((path, unresolved))
 }
 
-fn scan_ast(resolver: &Resolver, analysis: &mut Analysis, expr: &LocExpr) -> Result<(), String> {
-	match &*expr.0 {
-		// Base cases: We found actual imports!
-		Expr::Import(path) => add_path(resolver, &mut analysis.deep_deps, path)?,
-		Expr::ImportStr(path) => add_path(resolver, &mut analysis.leaf_deps, &path)?,
-		// Otherwise, recurse if needed
-		Expr::Arr(exprs) => for expr in exprs { scan_ast(resolver, analysis, expr)? },
-		Expr::ArrComp(expr, compspecs) => {
-			scan_ast(resolver, analysis, expr)?;
-			scan_compspecs(resolver, analysis, compspecs)?;
-		},
-		Expr::Obj(obj) => scan_obj(resolver, analysis, obj)?,
-		Expr::ObjExtend(expr, obj) => {
-			scan_ast(resolver, analysis, expr)?;
-			scan_obj(resolver, analysis, obj)?;
-		},
-		Expr::Parened(expr) => scan_ast(resolver, analysis, expr)?,
-		Expr::UnaryOp(_, expr) => scan_ast(resolver, analysis, expr)?,
-		Expr::BinaryOp(expr_a, _, expr_b) => {
-			scan_ast(resolver, analysis, expr_a)?;
-			scan_ast(resolver, analysis, expr_b)?;
-		},
-		Expr::AssertExpr(AssertStmt(expr_a, maybe_expr_b), expr_c) => {
-			scan_ast(resolver, analysis, expr_a)?;
-			if let Some(expr) = maybe_expr_b {
-				scan_ast(resolver, analysis, expr)?;
-			}
-			scan_ast(resolver, analysis, expr_c)?;
-		},
-		Expr::LocalExpr(bindspecs, expr) => {
-			for bindspec in bindspecs {
-				scan_bindspec(resolver, analysis, bindspec)?;
-			}
-			scan_ast(resolver, analysis, expr)?;
-		},
-		Expr::ErrorStmt(expr) => scan_ast(resolver, analysis, expr)?,
-		Expr::Apply(expr, args, _) => {
-			scan_ast(resolver, analysis, expr)?;
-			for Arg(_, expr) in &args.0 {
-				scan_ast(resolver, analysis, expr)?;
-			}
-		},
-		Expr::Index(expr_a, expr_b) => {
-			scan_ast(resolver, analysis, expr_a)?;
-			scan_ast(resolver, analysis, expr_b)?;
-		},
-		Expr::Function(params, expr) => {
-			for Param(_, maybe_expr) in &*params.0 {
-				if let Some(expr) = maybe_expr {
-					scan_ast(resolver, analysis, expr)?;
-				}
-			}
-			scan_ast(resolver, analysis, expr)?;
-		},
-		Expr::IfElse{cond, cond_then, cond_else} => {
-			scan_ast(resolver, analysis, &cond.0)?;
-			scan_ast(resolver, analysis, cond_then)?;
-			if let Some(expr) = cond_else {
-				scan_ast(resolver, analysis, expr)?;
-			}
-		},
-		Expr::Slice(expr, SliceDesc{start, end, step}) => {
-			scan_ast(resolver, analysis, expr)?;
-			for maybe_expr in [start, end, step] {
-				if let Some(expr) = maybe_expr {
-					scan_ast(resolver, analysis, expr)?;
-				}
-			}
-		},
-		// Remaining cases are leaf nodes like literals that we don't care about.
-		_ => (),
-	}
-	Ok(())
+// The jsonnet files evaluated for the root: the root itself and any code files.
+fn root_files(options: &Options, filename: &Path) -> Vec<PathBuf> {
+	let mut files = vec![filename.to_owned()];
+	files.extend(options.code_files.iter().cloned());
+	files
 }
 
-fn scan_compspecs(resolver: &Resolver, analysis: &mut Analysis, compspecs: &[CompSpec]) -> Result<(), String> {
-	for compspec in compspecs {
-		match compspec {
-			CompSpec::IfSpec(data) => scan_ast(resolver, analysis, &data.0)?,
-			CompSpec::ForSpec(data) => scan_ast(resolver, analysis, &data.1)?,
+// When --per-root-timeout runs out for a root. This is made once for each root, before its deps are first walked,
+// and passed to every walk of them, so the timeout is for finding everything about the root rather than for each walk.
+#[derive(Clone, Copy)]
+struct Deadline<'a> {
+	root: &'a Path,
+	started: Instant,
+}
+
+impl<'a> Deadline<'a> {
+	fn new(root: &'a Path) -> Deadline<'a> {
+		Deadline { root, started: Instant::now() }
+	}
+
+	// Fail if we've been working on the root for longer than --per-root-timeout.
+	// Called while walking its deps, with how many files we've seen so far.
+	fn check(&self, options: &Options, seen: usize) -> Result<(), String> {
+		match options.per_root_timeout {
+			Some(timeout) if self.started.elapsed() > timeout => Err(format!(
+				"Timed out after {}s finding deps of {}, having found {} so far",
+				timeout.as_secs(), self.root.display(), seen,
+			)),
+			_ => Ok(()),
 		}
 	}
-	Ok(())
 }
 
-fn scan_bindspec(resolver: &Resolver, analysis: &mut Analysis, bindspec: &BindSpec) -> Result<(), String> {
-	let BindSpec{params, value, ..} = bindspec;
-	if let Some(params) = params {
-		for Param(_, maybe_expr) in &*params.0 {
-			if let Some(expr) = maybe_expr {
-				scan_ast(resolver, analysis, expr)?;
-			}
-		}
+fn resolve_deps(options: &Options, cache: &Cache, deadline: Deadline, jpaths: &[PathBuf], filename: &Path) -> Result<HashSet<PathBuf>, String> {
+	let mut deps = closure(options, cache, deadline, jpaths, root_files(options, filename), None)?;
+	deps.extend(options.str_files.iter().cloned());
+	if let Some(exec_root) = &options.exec_root {
+		deps.remove(exec_root);
 	}
-	scan_ast(resolver, analysis, value)?;
-	Ok(())
+	// A file can be both, if it's imported as code in one place and as data in another.
+	if options.deps_kind != DepsKind::All {
+		let analyses = closure_analyses(options, cache, deadline, jpaths, filename)?;
+		let kept: HashSet<&PathBuf> = match options.deps_kind {
+			DepsKind::Code => analyses.iter().map(|(file, _)| file).collect(),
+			_ => analyses.iter().flat_map(|(_, analysis)| analysis.leaf_deps.iter()).chain(options.str_files.iter()).collect(),
+		};
+		deps.retain(|dep| kept.contains(dep));
+	}
+	Ok(deps)
 }
 
-fn scan_obj(resolver: &Resolver, analysis: &mut Analysis, obj: &ObjBody) -> Result<(), String> {
-	match obj {
-		ObjBody::MemberList(members) => {
-			for member in members {
-				match member {
-					Member::Field(FieldMember{name, params, value, ..}) => {
-						match name {
-							FieldName::Fixed(_) => (),
-							FieldName::Dyn(expr) => scan_ast(resolver, analysis, expr)?,
-						}
-						if let Some(params) = params {
-							for Param(_, maybe_expr) in &*params.0 {
-								if let Some(expr) = maybe_expr {
-									scan_ast(resolver, analysis, expr)?;
-								}
-							}
-						}
-						scan_ast(resolver, analysis, value)?;
-					},
-					Member::BindStmt(bindspec) => scan_bindspec(resolver, analysis, bindspec)?,
-					Member::AssertStmt(AssertStmt(expr, maybe_expr)) => {
-						scan_ast(resolver, analysis, expr)?;
-						if let Some(expr) = maybe_expr {
-							scan_ast(resolver, analysis, expr)?;
-						}
-					},
+// With --fail-fast, the file each file was first found imported by, so that an error from analyzing it can say
+// which chain of imports led to it. Files with no importer are where the chain starts.
+#[derive(Default)]
+struct Importers(HashMap<PathBuf, PathBuf>);
+
+impl Importers {
+	// Record that the file imports the dep, unless something else was found importing it first.
+	fn add(&mut self, file: &Path, dep: &Path) {
+		self.0.entry(dep.to_owned()).or_insert_with(|| file.to_owned());
+	}
+
+	// The importers of the files in a closure from closure_analyses, as they were found by it.
+	fn of_closure(options: &Options, analyses: &[(PathBuf, Arc<Analysis>)]) -> Importers {
+		let mut importers = Importers::default();
+		if options.fail_fast {
+			let mut seen = HashSet::new();
+			for (file, analysis) in analyses {
+				for deep_dep in &analysis.deep_deps {
+					if !seen.contains(deep_dep) && deep_dep != file {
+						importers.add(file, deep_dep);
+					}
 				}
+				seen.insert(file);
 			}
-		},
-		ObjBody::ObjComp(ObjComp{pre_locals, key, value, post_locals, compspecs}) => {
-			for bindspec in pre_locals { scan_bindspec(resolver, analysis, bindspec)?; }
-			scan_ast(resolver, analysis, key)?;
-			scan_ast(resolver, analysis, value)?;
-			for bindspec in post_locals { scan_bindspec(resolver, analysis, bindspec)?; }
-			scan_compspecs(resolver, analysis, compspecs)?;
-		},
+		}
+		importers
+	}
+
+	// With --fail-fast, add to an error from analyzing the file the chain of imports that led to it.
+	fn chain(&self, options: &Options, file: &Path, e: String) -> String {
+		if !options.fail_fast || !self.0.contains_key(file) {
+			return e;
+		}
+		let mut chain = vec![file];
+		while let Some(importer) = self.0.get(*chain.last().unwrap()) {
+			// Importers from different traversals, eg. for lint, could otherwise lead round in a loop.
+			if chain.contains(&importer.as_path()) {
+				break;
+			}
+			chain.push(importer);
+		}
+		let as_str: Vec<_> = chain.iter().rev().map(|p| p.to_string_lossy()).collect();
+		format!("{} (imported through {})", e, as_str.join(" -> "))
 	}
-	Ok(())
 }
 
-fn resolve_deps(cache: &mut HashMap<PathBuf, Analysis>, jpaths: &[&Path], filename: &Path) -> Result<HashSet<PathBuf>, String> {
+// Get the given jsonnet files and everything they depend on. The importer is the file which imported them, if any,
+// for --fail-fast to start import chains from.
+fn closure(options: &Options, cache: &Cache, deadline: Deadline, jpaths: &[PathBuf], files: Vec<PathBuf>, importer: Option<&Path>) -> Result<HashSet<PathBuf>, String> {
 	let mut deps: HashSet<PathBuf> = HashSet::new();
-	let mut to_expand = vec![filename.to_owned()];
+	let mut importers = Importers::default();
+	if let (true, Some(importer)) = (options.fail_fast, importer) {
+		for file in &files {
+			importers.add(importer, file);
+		}
+	}
+	let mut to_expand = files;
 	while let Some(filename) = to_expand.pop() {
 		// It's possible to have already seen this dep, if the dependency graph contains loops.
 		// In that case, don't expand to avoid infinite looping.
 		if deps.contains(&filename) {
 			continue;
 		}
+		deadline.check(options, deps.len())?;
+		let analysis = cache.analysis(options, jpaths, &filename).map_err(|e| importers.chain(options, &filename, e))?;
 		deps.insert(filename.clone());
-		// We can't just use or_insert_with() because analyse_file may error,
-		// so we need to do it the long way.
-		let analysis = match cache.entry(filename) {
-			Entry::Occupied(entry) => entry.into_mut(),
-			Entry::Vacant(entry) => {
-				let analysis = analyze_file(jpaths, entry.key())?;
-				entry.insert(analysis)
-			}
-		};
 		// leaf deps can be added immediately to the full set, and don't need to be expanded.
 		for leaf_dep in &analysis.leaf_deps {
 			deps.insert(leaf_dep.clone());
 		}
 		// deep deps go into the expand list.
 		for deep_dep in &analysis.deep_deps {
+			if !deps.contains(deep_dep) && options.fail_fast {
+				importers.add(&filename, deep_dep);
+			}
 			to_expand.push(deep_dep.clone());
 		}
 	}
 	Ok(deps)
 }
 
+// Get the analysis of every jsonnet file in the file's deps, including the file itself.
+fn closure_analyses(options: &Options, cache: &Cache, deadline: Deadline, jpaths: &[PathBuf], filename: &Path) -> Result<Vec<(PathBuf, Arc<Analysis>)>, String> {
+	let mut seen: HashSet<PathBuf> = HashSet::new();
+	let mut importers = Importers::default();
+	let mut analyses = Vec::new();
+	let mut to_expand = root_files(options, filename);
+	while let Some(filename) = to_expand.pop() {
+		if seen.contains(&filename) {
+			continue;
+		}
+		deadline.check(options, seen.len())?;
+		let analysis = cache.analysis(options, jpaths, &filename).map_err(|e| importers.chain(options, &filename, e))?;
+		for deep_dep in &analysis.deep_deps {
+			if !seen.contains(deep_dep) && *deep_dep != filename && options.fail_fast {
+				importers.add(&filename, deep_dep);
+			}
+		}
+		to_expand.extend(analysis.deep_deps.iter().cloned());
+		seen.insert(filename.clone());
+		analyses.push((filename, analysis));
+	}
+	Ok(analyses)
+}
+
+// Get the subset of the file's deps that can be reached without going through any conditional imports.
+// These are the ones that aren't conditional, not ones that are sure to be loaded, as an unused import isn't. Like resolve_deps, this includes the file itself.
+fn unconditional_deps(options: &Options, cache: &Cache, deadline: Deadline, jpaths: &[PathBuf], filename: &Path) -> Result<HashSet<PathBuf>, String> {
+	let mut deps: HashSet<PathBuf> = options.str_files.iter().cloned().collect();
+	let mut importers = Importers::default();
+	let mut to_expand = root_files(options, filename);
+	while let Some(filename) = to_expand.pop() {
+		if deps.contains(&filename) {
+			continue;
+		}
+		deadline.check(options, deps.len())?;
+		let analysis = cache.analysis(options, jpaths, &filename).map_err(|e| importers.chain(options, &filename, e))?;
+		deps.insert(filename.clone());
+		for leaf_dep in &analysis.leaf_deps {
+			if !analysis.conditional.contains(leaf_dep) {
+				deps.insert(leaf_dep.clone());
+			}
+		}
+		for deep_dep in &analysis.deep_deps {
+			if !analysis.conditional.contains(deep_dep) {
+				if !deps.contains(deep_dep) && options.fail_fast {
+					importers.add(&filename, deep_dep);
+				}
+				to_expand.push(deep_dep.clone());
+			}
+		}
+	}
+	Ok(deps)
+}
+
+// What to output for each root.
+enum Mode {
+	// The full set of files the root depends on.
+	Deps,
+	// The root's own imports, as written.
+	RawImports,
+	// The root's deps along with when they last changed.
+	Age,
+	// How many files and bytes the root's deps add up to, and which imports they come from.
+	Stats,
+	// Distinct files in the root's deps which have the same contents.
+	Duplicates,
+	// The root's source, with what each import resolves to and costs.
+	Annotate,
+	// Every indexed file which depends on the root.
+	RDeps,
+	// Every indexed file which depends on any of the roots, all together.
+	Affected,
+	// The imports of every file in the deps of the roots, all together.
+	Graph,
+	// Analyze files as requested on stdin, instead of any roots.
+	Worker,
+	// Compare the root's deps to a golden list.
+	Check,
+	// Look for imports which could be written better, in every file in the deps of the roots.
+	Lint,
+	// Bundle up the files needed to reproduce the analysis of the roots.
+	Repro,
+	// Make a minimal copy of the root which still fails a check.
+	Reduce,
+	// Search the files in the deps of the roots.
+	Grep,
+	// Report what --patch-depfiles would write and which roots would need rebuilding, without writing anything.
+	Plan,
+	// Split the roots into batches which share no dirty deps.
+	Partition,
+	// Score the import graph of the roots on cycles, depth, fan-in, orphans and budgets.
+	Health,
+	// Replay the root's history, reporting when each dep was added or removed.
+	#[cfg(feature = "git")]
+	History,
+}
+
+impl Mode {
+	fn name(&self) -> &'static str {
+		match self {
+			Mode::Deps => "deps",
+			Mode::RawImports => "raw-imports",
+			Mode::Age => "age-report",
+			Mode::Stats => "stats",
+			Mode::Duplicates => "duplicates",
+			Mode::Annotate => "annotate",
+			Mode::RDeps => "rdeps",
+			Mode::Affected => "affected",
+			Mode::Graph => "graph",
+			Mode::Worker => "worker",
+			Mode::Check => "check",
+			Mode::Lint => "lint",
+			Mode::Repro => "repro",
+			Mode::Reduce => "reduce",
+			Mode::Grep => "grep",
+			Mode::Plan => "plan",
+			Mode::Partition => "partition",
+			Mode::Health => "health",
+			#[cfg(feature = "git")]
+			Mode::History => "history",
+		}
+	}
+}
+
+// A file to analyze, along with the resolution settings to analyze it with.
+struct Root {
+	path: PathBuf,
+	jpaths: Vec<PathBuf>,
+	// Arbitrary KEY=VALUE pairs which are included in json output for this root.
+	meta: Vec<(String, String)>,
+	// What to write as the target of its make rule instead of the root, overriding --target.
+	target: Option<String>,
+}
+
+enum RootArg {
+	File(PathBuf),
+	Spec(String),
+}
+
+// Split a --root argument into its path and each KEY=VALUE option. Options start at a : followed by a KEY of
+// letters, digits, - or _ and then =, so a : anywhere else, eg. in a path or target, is kept as part of it.
+fn split_root_spec(spec: &str) -> (&str, Vec<&str>) {
+	let is_option = |rest: &str| rest.split_once('=').is_some_and(|(key, _)| {
+		!key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+	});
+	let starts: Vec<usize> = spec.match_indices(':').map(|(i, _)| i).filter(|i| is_option(&spec[i + 1..])).collect();
+	let path = &spec[..starts.first().copied().unwrap_or(spec.len())];
+	let options = starts.iter().enumerate().map(|(n, &start)| {
+		&spec[start + 1..starts.get(n + 1).copied().unwrap_or(spec.len())]
+	}).collect();
+	(path, options)
+}
+
+// Parse a --root argument of the form PATH[:KEY=VALUE...].
+// Roots without an explicit jpath option use the global --jpath list.
+fn parse_root(spec: &str, default_jpaths: &[PathBuf]) -> Result<Root, String> {
+	let (path, options) = split_root_spec(spec);
+	if path.is_empty() {
+		return Err(format!("Missing path in --root {}", spec));
+	}
+	let mut root = Root { path: path.into(), jpaths: default_jpaths.to_vec(), meta: Vec::new(), target: None };
+	for option in options {
+		let (key, value) = option.split_once('=').expect("options have a KEY=");
+		match key {
+			"jpath" => root.jpaths = value.split(',').filter(|p| !p.is_empty()).map(PathBuf::from).collect(),
+			"target" => {
+				output::render_target(value, None)?;
+				root.target = Some(value.into());
+			},
+			_ => return Err(format!("Unknown option {:?} in --root {}", key, spec)),
+		}
+	}
+	Ok(root)
+}
+
+// Handle the "index DIR -o FILE" subcommand.
+fn index_main(progname: &str, mut args: impl Iterator<Item = String>) -> Result<(), String> {
+	let mut dir: Option<PathBuf> = None;
+	let mut output: Option<PathBuf> = None;
+	while let Some(arg) = args.next() {
+		match arg.as_str() {
+			"--help" => return Err(format!("Usage: {} index DIR -o FILE", progname)),
+			"-o" | "--output" => {
+				let path = args.next().ok_or("Missing argument to --output")?;
+				output = Some(path.into());
+			},
+			_ if dir.is_none() => dir = Some(arg.into()),
+			_ => return Err(format!("Unexpected argument {:?}", arg)),
+		}
+	}
+	let dir = dir.ok_or("Missing directory to index")?;
+	let output = output.ok_or("Missing --output file for index")?;
+	index::build(&dir, &Options::new().fs)?.save(&output)
+}
+
+// Handle the "graph diff OLD NEW" subcommand.
+fn graph_diff_main(progname: &str, mut args: impl Iterator<Item = String>) -> Result<(), String> {
+	let mut files: Vec<PathBuf> = Vec::new();
+	let mut format = output::Format::Make;
+	let mut follow_renames = false;
+	while let Some(arg) = args.next() {
+		match arg.as_str() {
+			"--help" => return Err(format!("Usage: {} graph diff [--follow-renames] [--format FORMAT] OLD NEW", progname)),
+			"--follow-renames" => follow_renames = true,
+			"--format" => {
+				let name = args.next().ok_or("Missing argument to --format")?;
+				format = output::Format::parse(&name)?;
+				if !matches!(format, output::Format::Make | output::Format::Json | output::Format::Yaml) {
+					return Err(format!("graph diff doesn't support --format {}", format.name()));
+				}
+			},
+			_ => files.push(arg.into()),
+		}
+	}
+	let [old, new] = files.as_slice() else {
+		return Err("graph diff takes exactly two files, OLD and NEW".into());
+	};
+	let diff = graph::diff(&graph::load(old)?, &graph::load(new)?, follow_renames);
+	let diff = output::format_diff(format, &diff);
+	if !diff.is_empty() {
+		println!("{}", diff);
+	}
+	Ok(())
+}
+
+// Handle the "worker merge SHARD..." subcommand.
+fn worker_merge_main(progname: &str, mut args: impl Iterator<Item = String>) -> Result<(), String> {
+	let mut shards: Vec<PathBuf> = Vec::new();
+	let mut options = Options::new();
+	while let Some(arg) = args.next() {
+		match arg.as_str() {
+			"--help" => return Err(format!("Usage: {} worker merge [--format FORMAT] SHARD...", progname)),
+			"--format" => {
+				let name = args.next().ok_or("Missing argument to --format")?;
+				options.format = output::Format::parse(&name)?;
+				if !matches!(options.format, output::Format::Make | output::Format::Json | output::Format::Yaml) {
+					return Err(format!("worker merge doesn't support --format {}", options.format.name()));
+				}
+			},
+			_ => shards.push(arg.into()),
+		}
+	}
+	if shards.is_empty() {
+		return Err("worker merge takes at least one SHARD".into());
+	}
+	let graph = worker::merge(&shards)?;
+	let hashes = graph.hashes(&options.fs)?;
+	let cwd = std::env::current_dir().map_err(|e| format!("Failed to get current directory: {}", e))?;
+	let ids = graph.ids(&hashes, &cwd, &graph::id_base(None, &cwd));
+	println!("{}", output::format_graph(&options, &graph, &hashes, &ids));
+	Ok(())
+}
+
+// Handle the "conformance run DIR [--jsonnet BINARY]" subcommand.
+fn conformance_main(progname: &str, mut args: impl Iterator<Item = String>) -> Result<(), String> {
+	let usage = format!("Usage: {} conformance run [--jsonnet BINARY] DIR", progname);
+	if args.next().as_deref() != Some("run") {
+		return Err(usage);
+	}
+	let mut dir: Option<PathBuf> = None;
+	let mut jsonnet: Option<PathBuf> = None;
+	while let Some(arg) = args.next() {
+		match arg.as_str() {
+			"--help" => return Err(usage),
+			"--jsonnet" => {
+				let path = args.next().ok_or("Missing argument to --jsonnet")?;
+				jsonnet = Some(path.into());
+			},
+			_ if dir.is_none() => dir = Some(arg.into()),
+			_ => return Err(format!("Unexpected argument {:?}", arg)),
+		}
+	}
+	let dir = dir.ok_or("Missing directory of cases to run")?;
+	conformance::run(&Options::new(), &dir, jsonnet.as_deref())
+}
+
+// The error for trying to use something that was left out of this build.
+fn missing_feature(what: &str, feature: &str) -> String {
+	format!("{} is not available, as this was built without the {:?} feature", what, feature)
+}
+
+// Handle the "install-hooks [--hook HOOK] [--force] SUBCOMMAND ARGS..." subcommand.
+#[cfg(feature = "git")]
+fn install_hooks_main(progname: &str, mut args: impl Iterator<Item = String>) -> Result<(), String> {
+	let mut hook = "pre-commit".to_string();
+	let mut force = false;
+	let mut command: Vec<String> = Vec::new();
+	while let Some(arg) = args.next() {
+		match arg.as_str() {
+			"--help" => return Err(format!(
+				"Usage: {} install-hooks [--hook {}] [--force] {{{}}} [OPTIONS] FILENAME...",
+				progname, hooks::HOOKS.join(" | "), hooks::COMMANDS.join(" | "),
+			)),
+			"--hook" => {
+				hook = args.next().ok_or("Missing argument to --hook")?;
+				if !hooks::HOOKS.contains(&hook.as_str()) {
+					return Err(format!("Unknown hook {:?}, expected one of: {}", hook, hooks::HOOKS.join(", ")));
+				}
+			},
+			"--force" => force = true,
+			// Everything from the subcommand on is passed to the hook as is.
+			_ => {
+				command.push(arg);
+				command.extend(args.by_ref());
+			},
+		}
+	}
+	match command.first() {
+		Some(subcommand) if hooks::COMMANDS.contains(&subcommand.as_str()) => (),
+		Some(subcommand) => return Err(format!(
+			"Can't install a hook for {:?}, expected one of: {}", subcommand, hooks::COMMANDS.join(", "),
+		)),
+		None => return Err("Missing subcommand for the hook to run".into()),
+	}
+	let path = hooks::install(&hook, &command, force)?;
+	eprintln!("Installed {}", path.display());
+	Ok(())
+}
+
 fn inner_main() -> Result<(), String> {
 	// Argument parsing
-	let mut files: Vec<PathBuf> = Vec::new();
+	// Roots are either plain filenames or --root specs. We can't interpret them until
+	// all args are read, as --jpath may come later.
+	let mut root_args: Vec<RootArg> = Vec::new();
 	let mut jpaths: Vec<PathBuf> = Vec::new();
-	let mut args = std::env::args();
+	let mut mode = Mode::Deps;
+	let mut age_settings = age::Settings { git: false, older_than: None, newer_than: None };
+	let mut options = Options::new();
+	let mut batch_file: Option<PathBuf> = None;
+	let mut watch = false;
+	let mut watch_settings = watch::Settings::new();
+	// Default to one worker per CPU. If we can't tell how many there are, don't parallelize.
+	let mut jobs = std::thread::available_parallelism().map_or(1, |n| n.get());
+	let mut index_file: Option<PathBuf> = None;
+	let mut frozen = false;
+	let mut scope: Option<PathBuf> = None;
+	let mut redact = false;
+	let mut output_path: Option<PathBuf> = None;
+	let mut shrink = true;
+	let mut reduce_check: Option<String> = None;
+	let mut grep_pattern = String::new();
+	let mut grep_only: Option<grep::Only> = None;
+	#[cfg(feature = "git")]
+	let mut history_since: Option<String> = None;
+	let mut sample_chains: Option<usize> = None;
+	let mut notify = false;
+	let mut codeowners_file: Option<PathBuf> = None;
+	let mut golden: Option<PathBuf> = None;
+	let mut update = false;
+	let mut root_meta: Vec<(PathBuf, String, String)> = Vec::new();
+	let mut union = false;
+	let mut fix = false;
+	let mut fix_dry_run = false;
+	let mut self_check = false;
+	let mut resource_summary = false;
+	let mut exec_expr: Option<String> = None;
+	let mut exec_dir = PathBuf::new();
+	let mut from_graph: Option<PathBuf> = None;
+	let mut print_effective_config = false;
+	let mut patch_depfiles: Option<depfile::Location> = None;
+	let mut plan_changed: Option<Vec<PathBuf>> = None;
+	let mut health_settings = health::Settings::new();
+	let mut depfile: Option<PathBuf> = None;
+	let mut skip_disabled = false;
+	let mut relative_to: Option<PathBuf> = None;
+	let mut ext_strs: HashMap<String, String> = HashMap::new();
+	let raw_args: Vec<String> = std::env::args().collect();
+	let mut args = std::env::args().peekable();
 	let progname = args.next().ok_or("Missing arg 0")?;
+	// An optional subcommand must come first. Otherwise, we default to deps.
+	match args.peek().map(|arg| arg.as_str()) {
+		Some("index") => {
+			args.next();
+			return index_main(&progname, args);
+		},
+		Some("conformance") => {
+			args.next();
+			return conformance_main(&progname, args);
+		},
+		Some("install-hooks") => {
+			args.next();
+			#[cfg(feature = "git")]
+			return install_hooks_main(&progname, args);
+			#[cfg(not(feature = "git"))]
+			return Err(missing_feature("install-hooks", "git"));
+		},
+		Some("deps") => { args.next(); },
+		Some("rdeps") => {
+			args.next();
+			mode = Mode::RDeps;
+		},
+		Some("affected") => {
+			args.next();
+			mode = Mode::Affected;
+		},
+		Some("check") => {
+			args.next();
+			mode = Mode::Check;
+		},
+		Some("lint") => {
+			args.next();
+			mode = Mode::Lint;
+		},
+		Some("worker") => {
+			args.next();
+			if args.peek().map(|arg| arg.as_str()) == Some("merge") {
+				args.next();
+				return worker_merge_main(&progname, args);
+			}
+			mode = Mode::Worker;
+		},
+		Some("repro") => {
+			args.next();
+			mode = Mode::Repro;
+		},
+		Some("reduce") => {
+			args.next();
+			mode = Mode::Reduce;
+		},
+		// The pattern comes straight after, so it can't be mistaken for a root.
+		Some("grep") => {
+			args.next();
+			grep_pattern = args.next().ok_or("grep requires a PATTERN")?;
+			mode = Mode::Grep;
+		},
+		Some("plan") => {
+			args.next();
+			mode = Mode::Plan;
+		},
+		Some("partition") => {
+			args.next();
+			mode = Mode::Partition;
+		},
+		Some("health") => {
+			args.next();
+			mode = Mode::Health;
+		},
+		Some("annotate") => {
+			args.next();
+			mode = Mode::Annotate;
+		},
+		Some("history") => {
+			args.next();
+			#[cfg(not(feature = "git"))]
+			return Err(missing_feature("history", "git"));
+			#[cfg(feature = "git")]
+			{ mode = Mode::History; }
+		},
+		Some("graph") => {
+			args.next();
+			if args.peek().map(|arg| arg.as_str()) == Some("diff") {
+				args.next();
+				return graph_diff_main(&progname, args);
+			}
+			mode = Mode::Graph;
+		},
+		_ => (),
+	}
 	while let Some(arg) = args.next() {
 		match arg.as_str() {
-			"--help" => return Err(format!("Usage: {} {{FILENAME | --jpath PATH}}", progname)),
+			"--help" => return Err(format!(concat!(
+				"Usage: {} [deps | rdeps | affected | graph | check | lint | worker | repro | reduce | annotate] [OPTIONS] {{FILENAME | --root PATH[:jpath=PATH,...][:target=TARGET] | --jpath PATH}}\n",
+				"       {} grep PATTERN [--only strings | fields] [OPTIONS] --in-closure FILENAME...\n",
+				"       {} history FILENAME --since REV [OPTIONS]\n",
+				"       {} plan {{--patch-depfiles DIR | -MD}} [--changed FILE...] [OPTIONS] FILENAME...\n",
+				"       {} partition [--changed FILE...] [OPTIONS] FILENAME...\n",
+				"       {} index DIR -o FILE\n",
+				"       {} graph diff [--follow-renames] OLD NEW\n",
+				"       {} worker merge [--format FORMAT] SHARD...\n",
+				"       {} conformance run [--jsonnet BINARY] DIR\n",
+				"       {} install-hooks [--hook pre-commit | pre-push] [--force] {{check | lint}} [OPTIONS] FILENAME...\n",
+				"Subcommands:\n",
+				"  deps                Output the deps of each FILENAME (the default)\n",
+				"  rdeps               Output every file in --index which depends on each FILENAME\n",
+				"  affected            Output every file in --index which depends on any FILENAME\n",
+				"  graph               Output the imports of every file in the deps of any FILENAME\n",
+				"  graph diff          Compare two outputs of graph --format json\n",
+				"  check               Compare the deps of FILENAME to the list in --against\n",
+				"  lint                Check how imports are written in every file in the deps of any FILENAME\n",
+				"  repro               Bundle the files needed to reproduce the analysis of each FILENAME into -o FILE\n",
+				"  reduce              Copy FILENAME and its deps to -o DIR, and remove all they can while --check CMD still fails\n",
+				"  grep                Output each line containing PATTERN in any file in the deps of any FILENAME\n",
+				"  plan                Output what --patch-depfiles would write and which FILENAMEs need rebuilding, as JSON\n",
+				"  partition           Output batches of FILENAMEs which share no deps, or with --changed, no changed deps\n",
+				"  annotate            Output each FILENAME as a diff adding a comment to each import with what it brings in\n",
+				"  health              Output a score for the import graph of the FILENAMEs, and what went into it, as JSON\n",
+				"  history             Output when each dep of FILENAME was added or removed, for each commit since --since REV\n",
+				"  worker              Analyze files as requested on stdin, one JSON object per line\n",
+				"  worker merge        Merge the results of workers, saved in each SHARD, into one graph\n",
+				"  index               Record the imports of every jsonnet file in DIR\n",
+				"  conformance run     Check how imports are resolved against the cases in DIR, and optionally jsonnet\n",
+				"  install-hooks       Install a git hook which runs check or lint with the given options\n",
+				"Options:\n",
+				"  --exec-expr EXPR    Also output the deps of jsonnet code EXPR, like jsonnet -e\n",
+				"  --exec-dir DIR      Resolve imports in --exec-expr relative to DIR (default current directory)\n",
+				"  --root-meta PATH=KEY=VALUE\n",
+				"                      With --format json or yaml, include KEY=VALUE in the output for root PATH\n",
+				"  --index FILE        Use the imports recorded in FILE instead of parsing files again\n",
+				"  --from-graph FILE   With rdeps, affected or graph, use a graph saved with graph --format json.\n",
+				"                      Can't be used with other subcommands, --union, --watch or --batch\n",
+				"  --frozen            With --index, fail if the index is out of date instead of updating it\n",
+				"  --notify-format     With affected, output a JSON notification for each owner of affected files, from CODEOWNERS\n",
+				"  --codeowners FILE   With --notify-format, the CODEOWNERS file to use, instead of looking for one\n",
+				"  --sample-chains N   With rdeps or affected and --format json or yaml, include N example import chains\n",
+				"  --against FILE      With check, the golden list of deps to compare against.\n",
+				"                      With health, a previous report to output the change since\n",
+				"  --update            With check, write the current deps to the --against file instead\n",
+				"  --fix               With lint, rewrite imports to fix any problems found where possible\n",
+				"  --fix-dry-run       With lint, output a diff of what --fix would change instead\n",
+				"  --scope FILE        With graph, only output files which FILE depends on or which depend on it\n",
+				"  --redact            With graph or repro, replace each name in every path with a hash of it\n",
+				"  -o, --output FILE   Write deps to FILE (or stdout if -), replacing it only once they've all been found\n",
+				"                      With repro, the archive to write, as a .tar.gz (or .tar if it ends in .tar)\n",
+				"                      With reduce, the directory to put the reduced copy in\n",
+				"  --check CMD         With reduce, a shell command which fails while the problem is present\n",
+				"  --in-closure FILE   With grep, search the deps of FILE. The same as giving FILE as a FILENAME\n",
+				"  --since REV         With history, the commit to start from\n",
+				"  --only WHERE        With grep, only match within string literals (strings) or field names (fields)\n",
+				"  --no-shrink         With repro, include files in full rather than only their imports\n",
+				"  --format FORMAT     Output format: make (default), json, jsonl, yaml, json-tree, tree, table, ninja-depfile, bazel, tup, nix or print0, for graph, csv, dot, mermaid, graphml, plantuml or d2, or for lint, sarif\n",
+				"  -v, --verbose       With --format table, also list every dep\n",
+				"  --structured-data   With --format json or yaml, list deps that are parsed as JSON or YAML data\n",
+				"  --occurrences       With --format json or yaml, list every import of each dep, by each file importing it\n",
+				"  --provenance        With --format json or yaml, list how each dep was imported, and by which files\n",
+				"  --hash sha256       With --format json or yaml, include a hash of the contents of each dep\n",
+				"  --with-metadata     With --format json or yaml, include the size and mtime of each dep\n",
+				"  --group-by-lock     With --format json or yaml, group by jsonnetfile.lock.json and fail on deps under another one\n",
+				"  --phony, -MP        Follow each make rule with an empty rule for each dep, so make doesn't fail if one is deleted\n",
+				"  --target TARGET, -MT TARGET\n",
+				"                      The target of each make rule instead of FILENAME, with {{root}}, {{dir}}, {{name}} and {{stem}}\n",
+				"                      replaced by FILENAME, its directory, its file name and its file name without extension\n",
+				"  --strict-paths      Fail on paths with whitespace, $ or # in make rules and depfiles, instead of escaping them\n",
+				"  --output-version N  Which version of the output to write (default 1): 2 escapes paths in make rules,\n",
+				"                      3 also groups import cycles in make rules so make doesn't report them as circular,\n",
+				"                      4 also leaves deps which weren't found out of make rules, except in a comment\n",
+				"  --bazel-rule RULE   With --format bazel, the kind of rule to output (default filegroup), eg. jsonnet_library\n",
+				"  --tup-command CMD   With --format tup, the command each rule runs (default \"jsonnet %f -o %o\")\n",
+				"  --tup-output OUTPUT\n",
+				"                      With --format tup, the output of each rule (default %B.json)\n",
+				"  --normalize-paths POLICY\n",
+				"                      How to write paths found, and so which are the same file: verbatim (default),\n",
+				"                      lexical-normalize to remove . and .., or canonicalize to also resolve symlinks\n",
+				"  --relative-to DIR   Write every path relative to DIR, eg. . for the current directory.\n",
+				"                      With graph, make the IDs of files from their paths relative to DIR instead\n",
+				"  --nix-root DIR      With --format nix, write paths relative to DIR, where the .nix file will be\n",
+				"  --print0            Same as --format print0: output each dep followed by a NUL, for xargs -0\n",
+				"  --template TEMPLATE Output TEMPLATE for each root, with {{root}}, {{deps}}, {{leaf_deps}} and the like filled in\n",
+				"  --template-file FILE\n",
+				"                      As --template, but read the template from FILE\n",
+				"  --print0-roots      With --print0, start each FILENAME's deps with FILENAME and end them with an empty entry\n",
+				"  --union             Output the deps of all FILENAMEs together, one per line\n",
+				"  --patch-depfiles DIR\n",
+				"                      Write the deps of each FILENAME to DIR/FILENAME.d, if they have changed\n",
+				"  -MD                 Write the deps of each FILENAME to FILENAME.d, if they have changed\n",
+				"  --dep-dir DIR       The same as --patch-depfiles DIR\n",
+				"  --depfile PATH, -MF PATH\n",
+				"                      Write the deps of the only FILENAME to PATH, if they have changed. With -MD, where it writes\n",
+				"  --changed FILE      With plan, rebuild roots depending on FILE, instead of on files newer than their depfile.\n",
+				"                      With partition, only roots depending on FILE are batched, by which FILEs they share\n",
+				"  --max-deps N        With health, count each FILENAME with more than N deps as over budget\n",
+				"  --max-depth N       With health, count each FILENAME needing a chain of more than N imports as over budget\n",
+				"  --orphans-in DIR    With health, look for libraries which no FILENAME depends on under DIR (default .)\n",
+				"  --fail-fast         Stop at the first file which can't be analyzed (the default), saying which imports led to it\n",
+				"  --self-check        After finishing, check for internal inconsistencies and fail if any are found\n",
+				"  --resource-summary  After finishing, print the time, memory, files read and cache hits used to stderr\n",
+				"  --raw-imports       Output imports as written instead of resolved deps\n",
+				"  --deps-kind KIND    Only output deps of this kind: code (reached with import), data (importstr and the like) or all\n",
+				"  --no-fs-checks      Don't check which library path an import exists in\n",
+				"  --config FILE       Read repo settings, eg. jpaths for particular directories, from FILE\n",
+				"  --print-effective-config\n",
+				"                      First write every setting in effect to stderr as a JSON object, for reproducibility\n",
+				"  --jsonnetfile-jpaths\n",
+				"                      Also search the local dependencies in the nearest jsonnetfile.json\n",
+				"  --per-root-timeout SECS\n",
+				"                      Fail a root if finding its deps takes longer than SECS\n",
+				"  --max-files N       Abort with exit code 3 if more than N files are analyzed\n",
+				"  --max-seconds SECS  Abort with exit code 3 if the run takes longer than SECS\n",
+				"  --fs-retries N      Retry filesystem errors that may be transient N times (default 3)\n",
+				"  --max-open-files N  Limit how many files are checked or read at once (default from ulimit -n)\n",
+				"  --io-throttle OPS_PER_SEC\n",
+				"                      Limit how many files are checked or read per second\n",
+				"  --ext-code-file VAR=PATH, --tla-code-file VAR=PATH\n",
+				"                      Treat PATH and its deps as deps of every root\n",
+				"  --ext-str-file VAR=PATH, --tla-str-file VAR=PATH\n",
+				"                      Treat PATH as a dep of every root\n",
+				"  --skip-disabled-imports\n",
+				"                      Leave out imports in branches of ifs that can never be taken\n",
+				"  --ext-str VAR[=VALUE]\n",
+				"                      With --skip-disabled-imports, the value of std.extVar(VAR)\n",
+				"  --age-report        Output when each dep last changed\n",
+				"  --stats             Output the number and size of deps brought in by each import\n",
+				"  --duplicates        Output deps with identical contents, and fail if there are any\n",
+				"  --git               With --age-report, use last commit time instead of mtime\n",
+				"  --older-than YEARS  With --age-report, flag deps unchanged for this long\n",
+				"  --newer-than HOURS  With --age-report, flag deps changed this recently\n",
+				"  --batch FILE        Also process jobs from FILE, one JSON object per line\n",
+				"  --jobs N            Number of jobs from --batch, or roots with --format jsonl, to process in parallel\n",
+				"  --watch             Keep running, and output deps again whenever they change\n",
+				"  --diff-output       With --watch, after the first output only output deps added or removed\n",
+				"  --debounce MS       With --watch, wait until files stop changing for MS milliseconds before acting (default 250)\n",
+				"  --on-change CMD     With --watch, run CMD after each change, replacing {{roots}}",
+			), progname, progname, progname, progname, progname, progname, progname, progname, progname, progname)),
 			"--jpath" => {
 				let path = args.next().ok_or("Missing argument to --jpath")?;
 				jpaths.push(path.into());
 			},
-			filepath => files.push(filepath.into()),
+			"--format" => {
+				let format = args.next().ok_or("Missing argument to --format")?;
+				options.format = output::Format::parse(&format)?;
+			},
+			"--raw-imports" => mode = Mode::RawImports,
+			"--deps-kind" => options.deps_kind = DepsKind::parse(&args.next().ok_or("Missing argument to --deps-kind")?)?,
+			"--union" => union = true,
+			"--self-check" => self_check = true,
+			"--resource-summary" => resource_summary = true,
+			"--jsonnetfile-jpaths" => options.jsonnetfile_jpaths = true,
+			"-v" | "--verbose" => options.verbose = true,
+			"--structured-data" => options.structured_data = true,
+			"--occurrences" => options.occurrences = true,
+			"--provenance" => options.provenance = true,
+			"--hash" => match args.next().as_deref() {
+				Some("sha256") => options.hash = true,
+				Some(algorithm) => return Err(format!("Unknown --hash algorithm {:?}, expected sha256", algorithm)),
+				None => return Err("Missing argument to --hash".into()),
+			},
+			"--strict-paths" => options.strict_paths = true,
+			"--fail-fast" => options.fail_fast = true,
+			"--with-metadata" => options.with_metadata = true,
+			"--target" | "-MT" => {
+				let pattern = args.next().ok_or_else(|| format!("Missing argument to {}", arg))?;
+				output::render_target(&pattern, None)?;
+				options.target = Some(pattern);
+			},
+			"--group-by-lock" => options.group_by_lock = true,
+			"--phony" | "-MP" => options.phony = true,
+			"--output-version" => {
+				let n = args.next().ok_or("Missing argument to --output-version")?;
+				let version = n.parse().ok().filter(|version| output::OUTPUT_VERSIONS.contains(version)).ok_or_else(|| format!(
+					"Bad argument to --output-version: {:?}, expected one of {:?}", n, output::OUTPUT_VERSIONS,
+				))?;
+				options.output_version = Some(version);
+			},
+			"--bazel-rule" => options.bazel_rule = args.next().ok_or("Missing argument to --bazel-rule")?,
+			"--tup-command" => options.tup_command = args.next().ok_or("Missing argument to --tup-command")?,
+			"--tup-output" => options.tup_output = args.next().ok_or("Missing argument to --tup-output")?,
+			"--normalize-paths" => {
+				options.normalization = Normalization::parse(&args.next().ok_or("Missing argument to --normalize-paths")?)?;
+			},
+			"--relative-to" => relative_to = Some(args.next().ok_or("Missing argument to --relative-to")?.into()),
+			"--nix-root" => options.nix_root = args.next().ok_or("Missing argument to --nix-root")?.into(),
+			"--print0" => options.format = output::Format::Print0,
+			"--template" | "--template-file" => {
+				let arg_value = args.next().ok_or_else(|| format!("Missing argument to {}", arg))?;
+				let text = match arg.as_str() {
+					"--template" => arg_value,
+					_ => std::fs::read_to_string(&arg_value).map_err(|e| format!("Failed to read {}: {}", arg_value, e))?,
+				};
+				options.template = Some(template::Template::parse(&text, &output::TEMPLATE_SCALARS, &output::TEMPLATE_LISTS)?);
+				options.format = output::Format::Template;
+			},
+			"--print0-roots" => options.print0_roots = true,
+			"--exec-expr" => exec_expr = Some(args.next().ok_or("Missing argument to --exec-expr")?),
+			"--exec-dir" => {
+				let path = args.next().ok_or("Missing argument to --exec-dir")?;
+				exec_dir = path.into();
+			},
+			"--print-effective-config" => print_effective_config = true,
+			"--max-deps" => {
+				let n = args.next().ok_or("Missing argument to --max-deps")?;
+				health_settings.max_deps = Some(n.parse().map_err(|e| format!("Bad argument to --max-deps: {}", e))?);
+			},
+			"--max-depth" => {
+				let n = args.next().ok_or("Missing argument to --max-depth")?;
+				health_settings.max_depth = Some(n.parse().map_err(|e| format!("Bad argument to --max-depth: {}", e))?);
+			},
+			"--orphans-in" => health_settings.orphans_in = args.next().ok_or("Missing argument to --orphans-in")?.into(),
+			"--changed" => {
+				let path = args.next().ok_or("Missing argument to --changed")?;
+				plan_changed.get_or_insert_with(Vec::new).push(path.into());
+			},
+			"--patch-depfiles" | "--dep-dir" => {
+				let path = args.next().ok_or_else(|| format!("Missing argument to {}", arg))?;
+				patch_depfiles = Some(depfile::Location::Under(path.into()));
+			},
+			// Like gcc, --dep-dir (or --patch-depfiles) decides where they go, whichever order they're given in.
+			"-MD" => if patch_depfiles.is_none() {
+				patch_depfiles = Some(depfile::Location::NextToRoot);
+			},
+			"--depfile" | "-MF" => {
+				let path = args.next().ok_or_else(|| format!("Missing argument to {}", arg))?;
+				depfile = Some(path.into());
+			},
+			"--from-graph" => {
+				let path = args.next().ok_or("Missing argument to --from-graph")?;
+				from_graph = Some(path.into());
+			},
+			"--config" => {
+				let path = args.next().ok_or("Missing argument to --config")?;
+				options.config = config::load(Path::new(&path))?;
+			},
+			"--age-report" => mode = Mode::Age,
+			"--stats" => mode = Mode::Stats,
+			"--duplicates" => mode = Mode::Duplicates,
+			#[cfg(feature = "git")]
+			"--git" => age_settings.git = true,
+			#[cfg(not(feature = "git"))]
+			"--git" => return Err(missing_feature("--git", "git")),
+			"--older-than" => {
+				let years = args.next().ok_or("Missing argument to --older-than")?;
+				let years: u64 = years.parse().map_err(|e| format!("Bad argument to --older-than: {}", e))?;
+				age_settings.older_than = Some(years.checked_mul(365 * 24 * 3600).ok_or_else(|| format!("Bad argument to --older-than: {} years is too long", years))?);
+			},
+			"--newer-than" => {
+				let hours = args.next().ok_or("Missing argument to --newer-than")?;
+				let hours: u64 = hours.parse().map_err(|e| format!("Bad argument to --newer-than: {}", e))?;
+				age_settings.newer_than = Some(hours.checked_mul(3600).ok_or_else(|| format!("Bad argument to --newer-than: {} hours is too long", hours))?);
+			},
+			"--no-fs-checks" => options.fs_checks = false,
+			"--per-root-timeout" => {
+				let secs = args.next().ok_or("Missing argument to --per-root-timeout")?;
+				let secs: u64 = secs.parse().map_err(|e| format!("Bad argument to --per-root-timeout: {}", e))?;
+				options.per_root_timeout = Some(Duration::from_secs(secs));
+			},
+			"--max-files" => {
+				let n = args.next().ok_or("Missing argument to --max-files")?;
+				options.limits.max_files = Some(n.parse().map_err(|e| format!("Bad argument to --max-files: {}", e))?);
+			},
+			"--max-seconds" => {
+				let secs = args.next().ok_or("Missing argument to --max-seconds")?;
+				let secs: u64 = secs.parse().map_err(|e| format!("Bad argument to --max-seconds: {}", e))?;
+				options.limits.max_time = Some(Duration::from_secs(secs));
+			},
+			"--fs-retries" => {
+				let n = args.next().ok_or("Missing argument to --fs-retries")?;
+				options.fs.retries = n.parse().map_err(|e| format!("Bad argument to --fs-retries: {}", e))?;
+			},
+			"--io-throttle" => {
+				let n = args.next().ok_or("Missing argument to --io-throttle")?;
+				let n = n.parse().map_err(|e| format!("Bad argument to --io-throttle: {}", e))?;
+				if n == 0 {
+					return Err("--io-throttle must be at least 1".into());
+				}
+				options.fs.ops_per_sec = Some(n);
+			},
+			"--max-open-files" => {
+				let n = args.next().ok_or("Missing argument to --max-open-files")?;
+				options.fs.max_open = n.parse().map_err(|e| format!("Bad argument to --max-open-files: {}", e))?;
+				if options.fs.max_open == 0 {
+					return Err("--max-open-files must be at least 1".into());
+				}
+			},
+			"--ext-code-file" | "--tla-code-file" | "--ext-str-file" | "--tla-str-file" => {
+				let var = args.next().ok_or_else(|| format!("Missing argument to {}", arg))?;
+				// Like jsonnet, these take VAR=PATH. We only care about the path.
+				let (_, path) = var.split_once('=').ok_or_else(||
+					format!("Bad argument to {}: expected VAR=PATH", arg)
+				)?;
+				if arg.ends_with("-code-file") {
+					options.code_files.push(path.into());
+				} else {
+					options.str_files.push(path.into());
+				}
+			},
+			"--ext-str" => {
+				let var = args.next().ok_or("Missing argument to --ext-str")?;
+				// Like jsonnet, a VAR without a value takes it from the environment variable of the same name.
+				let (name, value) = match var.split_once('=') {
+					Some((name, value)) => (name.to_string(), value.to_string()),
+					None => {
+						let value = std::env::var(&var).map_err(|_| format!("--ext-str {} given, but ${} isn't set", var, var))?;
+						(var, value)
+					},
+				};
+				ext_strs.insert(name, value);
+			},
+			"--skip-disabled-imports" => skip_disabled = true,
+			"--index" => {
+				let path = args.next().ok_or("Missing argument to --index")?;
+				index_file = Some(path.into());
+			},
+			"--frozen" => frozen = true,
+			"--sample-chains" => {
+				let n = args.next().ok_or("Missing argument to --sample-chains")?;
+				sample_chains = Some(n.parse().map_err(|e| format!("Bad argument to --sample-chains: {}", e))?);
+			},
+			"--against" => {
+				let path = args.next().ok_or("Missing argument to --against")?;
+				golden = Some(path.into());
+			},
+			"--update" => update = true,
+			"--fix" => fix = true,
+			"--fix-dry-run" => fix_dry_run = true,
+			"--redact" => redact = true,
+			"--no-shrink" => shrink = false,
+			"--check" => reduce_check = Some(args.next().ok_or("Missing argument to --check")?),
+			"-o" | "--output" => {
+				let path = args.next().ok_or_else(|| format!("Missing argument to {}", arg))?;
+				output_path = Some(path.into());
+			},
+			"--scope" => {
+				let path = args.next().ok_or("Missing argument to --scope")?;
+				scope = Some(path.into());
+			},
+			"--batch" => {
+				let path = args.next().ok_or("Missing argument to --batch")?;
+				batch_file = Some(path.into());
+			},
+			"--watch" => watch = true,
+			"--diff-output" => watch_settings.diff_output = true,
+			"--debounce" => {
+				let ms = args.next().ok_or("Missing argument to --debounce")?;
+				let ms = ms.parse().map_err(|e| format!("Bad argument to --debounce: {}", e))?;
+				watch_settings.debounce = Duration::from_millis(ms);
+			},
+			"--on-change" => {
+				let command = args.next().ok_or("Missing argument to --on-change")?;
+				watch_settings.on_change = Some(command);
+			},
+			"--jobs" => {
+				let n = args.next().ok_or("Missing argument to --jobs")?;
+				jobs = n.parse().map_err(|e| format!("Bad argument to --jobs: {}", e))?;
+				if jobs == 0 {
+					return Err("--jobs must be at least 1".into());
+				}
+			},
+			"--root-meta" => {
+				let spec = args.next().ok_or("Missing argument to --root-meta")?;
+				let (path, key, value) = spec.split_once('=')
+					.and_then(|(path, meta)| meta.split_once('=').map(|(key, value)| (path, key, value)))
+					.ok_or_else(|| format!("Bad argument to --root-meta {:?}: expected PATH=KEY=VALUE", spec))?;
+				root_meta.push((path.into(), key.into(), value.into()));
+			},
+			"--root" => {
+				let spec = args.next().ok_or("Missing argument to --root")?;
+				root_args.push(RootArg::Spec(spec));
+			},
+			// Reads better than a bare FILENAME after a grep PATTERN, but is otherwise the same.
+			"--in-closure" => {
+				let path = args.next().ok_or("Missing argument to --in-closure")?;
+				root_args.push(RootArg::File(path.into()));
+			},
+			"--notify-format" => notify = true,
+			"--codeowners" => {
+				let path = args.next().ok_or("Missing argument to --codeowners")?;
+				codeowners_file = Some(path.into());
+			},
+			#[cfg(feature = "git")]
+			"--since" => history_since = Some(args.next().ok_or("Missing argument to --since")?),
+			#[cfg(not(feature = "git"))]
+			"--since" => return Err(missing_feature("--since", "git")),
+			"--only" => {
+				let name = args.next().ok_or("Missing argument to --only")?;
+				grep_only = Some(grep::Only::parse(&name)?);
+			},
+			filepath => root_args.push(RootArg::File(filepath.into())),
 		}
 	}
 
-	let mut cache: HashMap<PathBuf, Analysis> = HashMap::new();
-	let jpaths: Vec<&Path> = jpaths.iter().map(|path| path.as_path()).collect();
-	for filepath in files {
-		let deps = resolve_deps(&mut cache, &jpaths, &filepath)?;
-		let as_str: Vec<_> = deps.iter().map(|p| p.to_string_lossy()).collect();
-		println!("{}: {}", filepath.display(), as_str.join(" "));
+	if skip_disabled {
+		// The index records every import, so it can't be used to leave some out.
+		if index_file.is_some() {
+			return Err("--skip-disabled-imports can't be combined with --index".into());
+		}
+		options.skip_disabled = Some(ext_strs);
+	} else if !ext_strs.is_empty() {
+		return Err("--ext-str can only be used with --skip-disabled-imports".into());
+	}
+
+	// Paths recorded in an index or graph are as they were found then.
+	if options.normalization == Normalization::Canonical && (index_file.is_some() || from_graph.is_some()) {
+		return Err("--normalize-paths canonicalize can't be combined with --index or --from-graph".into());
+	}
+	if let Some(dir) = relative_to {
+		let cwd = std::env::current_dir().map_err(|e| format!("Failed to get current directory: {}", e))?;
+		options.relative_to = Some((resolve::normalize(&cwd.join(dir)), cwd));
+	}
+
+	// Files we're given are written the same way as those we find, so that they're the same file wherever they appear.
+	let mut roots = root_args.into_iter().map(|arg| {
+		let root = match arg {
+			RootArg::File(path) => Root { path, jpaths: jpaths.clone(), meta: Vec::new(), target: None },
+			RootArg::Spec(spec) => parse_root(&spec, &jpaths)?,
+		};
+		Ok(Root { path: options.normalized(root.path)?, ..root })
+	}).collect::<Result<Vec<_>, String>>()?;
+	for files in [&mut options.code_files, &mut options.str_files] {
+		*files = std::mem::take(files).into_iter().map(|path| options.normalization.apply(path, &options.fs)).collect::<Result<_, _>>()?;
+	}
+	// Like jsonnet -e, the expression is treated as a file in exec_dir named <cmdline>.
+	let exec_expr = match exec_expr {
+		Some(expr) => {
+			if !matches!(mode, Mode::Deps | Mode::RawImports) {
+				return Err("--exec-expr can only be used to output deps or raw imports".into());
+			}
+			let path = options.normalized(exec_dir.join("<cmdline>"))?;
+			roots.push(Root { path: path.clone(), jpaths: jpaths.clone(), meta: Vec::new(), target: None });
+			options.exec_root = Some(path.clone());
+			Some((path, expr))
+		},
+		None => None,
+	};
+	for (path, key, value) in root_meta {
+		let path = options.normalized(path)?;
+		let mut found = false;
+		for root in roots.iter_mut().filter(|root| root.path == path) {
+			root.meta.push((key.clone(), value.clone()));
+			found = true;
+		}
+		if !found {
+			return Err(format!("--root-meta given for {}, which is not a root", path.display()));
+		}
+	}
+
+	if print_effective_config {
+		use json::Value;
+		let string = |s: &str| Value::String(s.into());
+		let path = |path: &Path| string(&path.to_string_lossy());
+		let paths = |paths: &[PathBuf]| Value::Array(paths.iter().map(|p| path(p)).collect());
+		let optional = |value: Option<Value>| value.unwrap_or(Value::Null);
+		let number = |n: usize| Value::Number(n as f64);
+		let cwd = std::env::current_dir().map_err(|e| format!("Failed to get current directory: {}", e))?;
+		let config = Value::Object(vec![
+			("version".into(), string(env!("CARGO_PKG_VERSION"))),
+			("args".into(), Value::Array(raw_args.iter().map(|arg| string(arg)).collect())),
+			// Relative paths everywhere else are relative to this.
+			("cwd".into(), path(&cwd)),
+			("mode".into(), string(mode.name())),
+			("format".into(), string(options.format.name())),
+			("output_version".into(), number(options.output_version.unwrap_or(output::DEFAULT_OUTPUT_VERSION) as usize)),
+			("roots".into(), Value::Array(roots.iter().map(|root| Value::Object(vec![
+				("path".into(), path(&root.path)),
+				("jpaths".into(), paths(&root.jpaths)),
+				("meta".into(), Value::Object(root.meta.iter().map(|(key, value)| (key.clone(), string(value))).collect())),
+				("target".into(), optional(root.target.as_deref().map(string))),
+			])).collect())),
+			("jpaths".into(), paths(&jpaths)),
+			("fs_checks".into(), Value::Bool(options.fs_checks)),
+			("jsonnetfile_jpaths".into(), Value::Bool(options.jsonnetfile_jpaths)),
+			("config".into(), options.config.to_json()),
+			("code_files".into(), paths(&options.code_files)),
+			("str_files".into(), paths(&options.str_files)),
+			("fs_retries".into(), number(options.fs.retries as usize)),
+			// No limit could be found if this is null.
+			("max_open_files".into(), if options.fs.max_open == usize::MAX { Value::Null } else { number(options.fs.max_open) }),
+			("io_throttle".into(), optional(options.fs.ops_per_sec.map(|n| number(n as usize)))),
+			("per_root_timeout".into(), optional(options.per_root_timeout.map(|t| number(t.as_secs() as usize)))),
+			("max_files".into(), optional(options.limits.max_files.map(number))),
+			("max_seconds".into(), optional(options.limits.max_time.map(|t| number(t.as_secs() as usize)))),
+			("index".into(), optional(index_file.as_deref().map(path))),
+			("frozen".into(), Value::Bool(frozen)),
+			("batch".into(), optional(batch_file.as_deref().map(path))),
+			("jobs".into(), number(jobs)),
+			("deps_kind".into(), string(options.deps_kind.name())),
+			("normalize_paths".into(), string(options.normalization.name())),
+			("relative_to".into(), optional(options.relative_to.as_ref().map(|(dir, _)| path(dir)))),
+			("target".into(), optional(options.target.as_deref().map(string))),
+			("union".into(), Value::Bool(union)),
+			("output".into(), optional(output_path.as_deref().map(path))),
+			("verbose".into(), Value::Bool(options.verbose)),
+			("structured_data".into(), Value::Bool(options.structured_data)),
+			("occurrences".into(), Value::Bool(options.occurrences)),
+			("group_by_lock".into(), Value::Bool(options.group_by_lock)),
+			("phony".into(), Value::Bool(options.phony)),
+			("strict_paths".into(), Value::Bool(options.strict_paths)),
+			("bazel_rule".into(), string(&options.bazel_rule)),
+			("tup_command".into(), string(&options.tup_command)),
+			("tup_output".into(), string(&options.tup_output)),
+			("nix_root".into(), path(&options.nix_root)),
+			("print0_roots".into(), Value::Bool(options.print0_roots)),
+			("template".into(), optional(options.template.as_ref().map(|template| string(&template.source)))),
+			("exec_root".into(), optional(options.exec_root.as_deref().map(path))),
+			("provenance".into(), Value::Bool(options.provenance)),
+			("hash".into(), optional(options.hash.then(|| string("sha256")))),
+			("fail_fast".into(), Value::Bool(options.fail_fast)),
+			("with_metadata".into(), Value::Bool(options.with_metadata)),
+			("skip_disabled_imports".into(), Value::Bool(options.skip_disabled.is_some())),
+			("ext_strs".into(), Value::Object(
+				options.skip_disabled.iter().flatten().collect::<BTreeMap<_, _>>().into_iter().map(|(name, value)| (name.clone(), string(value))).collect()
+			)),
+		]);
+		// This goes to stderr so it can't end up in the output, eg. of a make rule.
+		eprintln!("{}", Value::Object(vec![("effective_config".into(), config)]));
+	}
+
+	let cache = Cache::default();
+	let _summary = resource_summary.then(|| resources::Summary::new(&options, &cache));
+	if let Some((path, expr)) = exec_expr {
+		cache.insert_parsed(&path, scan::parse_contents(&path, &expr, options.skip_disabled.as_ref())?);
+	}
+	let index = match index_file {
+		Some(path) => {
+			let mut index = index::load(&path)?;
+			if index.refresh(frozen, &options.fs)? && !frozen {
+				index.save(&path)?;
+			}
+			index.preload(&cache);
+			Some(index)
+		},
+		None => None,
+	};
+	// A saved graph has everything rdeps, affected and graph need, but not the contents of files that other modes look at.
+	let from_graph = match from_graph {
+		Some(_) if !matches!(mode, Mode::RDeps | Mode::Affected | Mode::Graph) || union || watch || batch_file.is_some() => {
+			return Err("--from-graph can only be used with rdeps, affected or graph, and can't be combined with --union, --watch or --batch, as a saved graph has only imports and not the files themselves".into());
+		},
+		Some(path) => Some(graph::load(&path)?),
+		None => None,
+	};
+	if matches!(mode, Mode::Plan) && patch_depfiles.is_none() {
+		return Err("plan requires --patch-depfiles DIR or -MD, to know where the depfiles are".into());
+	}
+	let health_options = health_settings.max_deps.is_some() || health_settings.max_depth.is_some() || health_settings.orphans_in != Path::new(".");
+	if health_options && !matches!(mode, Mode::Health) {
+		return Err("--max-deps, --max-depth and --orphans-in can only be used with health".into());
+	}
+	if plan_changed.is_some() && !matches!(mode, Mode::Plan | Mode::Partition) {
+		return Err("--changed can only be used with plan or partition".into());
+	}
+	if patch_depfiles.is_some() && (union || watch || !matches!(mode, Mode::Deps | Mode::Plan) || !matches!(options.format, output::Format::Make | output::Format::NinjaDepfile)) {
+		return Err("--patch-depfiles can only be used to output deps in make or ninja-depfile format, and can't be combined with --union or --watch".into());
+	}
+	let single_root = !union && !watch && batch_file.is_none() && matches!(mode, Mode::Deps) && roots.len() == 1;
+	if options.format == output::Format::NinjaDepfile && !single_root && patch_depfiles.is_none() {
+		return Err("--format ninja-depfile can only be used to output the deps of a single root or with --patch-depfiles, as a ninja depfile has one target".into());
+	}
+	if options.format == output::Format::Nix && !single_root && !(union && matches!(mode, Mode::Deps)) {
+		return Err("--format nix can only be used to output the deps of a single root or with --union, as it outputs a single list".into());
+	}
+	// As with gcc, -MF says where -MD writes the depfile.
+	if depfile.is_some() && matches!(mode, Mode::Deps) && matches!(patch_depfiles, Some(depfile::Location::NextToRoot)) {
+		patch_depfiles = None;
+	}
+	if depfile.is_some() && (!single_root || patch_depfiles.is_some() || !matches!(options.format, output::Format::Make | output::Format::NinjaDepfile)) {
+		return Err("--depfile can only be used to output the deps of a single root, in make or ninja-depfile format".into());
+	}
+	if matches!(options.format, output::Format::Diagram(_) | output::Format::Csv) && !matches!(mode, Mode::Graph) {
+		return Err(format!("--format {} can only be used with graph", options.format.name()));
+	}
+	if options.format == output::Format::Sarif && (!matches!(mode, Mode::Lint) || fix_dry_run) {
+		return Err("--format sarif can only be used with lint, and can't be combined with --fix-dry-run".into());
+	}
+	if options.format == output::Format::Print0 && (watch || !matches!(mode, Mode::Deps | Mode::RDeps | Mode::Affected)) {
+		return Err("--print0 can only be used with deps, rdeps or affected, and can't be combined with --watch".into());
+	}
+	if options.relative_to.is_some() && (!matches!(mode, Mode::Deps | Mode::Plan | Mode::Graph) || matches!(options.format, output::Format::Bazel | output::Format::Nix)) {
+		return Err("--relative-to can only be used to output deps, with plan, or with graph, and not in bazel or nix format, which have their own ways of writing paths".into());
+	}
+	if options.print0_roots && (union || options.format != output::Format::Print0 || !matches!(mode, Mode::Deps | Mode::RDeps)) {
+		return Err("--print0-roots can only be used with --print0 to output deps or rdeps of each root, and can't be combined with --union".into());
+	}
+	if matches!(options.format, output::Format::Table | output::Format::Jsonl | output::Format::JsonTree | output::Format::Tree | output::Format::Bazel | output::Format::Tup | output::Format::Template) && (union || !matches!(mode, Mode::Deps)) {
+		let flag = match options.format {
+			output::Format::Template => "--template".to_string(),
+			format => format!("--format {}", format.name()),
+		};
+		return Err(format!("{} can only be used to output the deps of each root", flag));
+	}
+	if options.structured_data && (union || !matches!(mode, Mode::Deps) || !options.format.is_structured()) {
+		return Err("--structured-data can only be used to output the deps of each root in json or yaml format".into());
+	}
+	if options.occurrences && (union || !matches!(mode, Mode::Deps) || !options.format.is_structured()) {
+		return Err("--occurrences can only be used to output the deps of each root in json or yaml format".into());
+	}
+	if options.provenance && (union || !matches!(mode, Mode::Deps) || !options.format.is_structured()) {
+		return Err("--provenance can only be used to output the deps of each root in json or yaml format".into());
+	}
+	if options.hash && (union || !matches!(mode, Mode::Deps) || !options.format.is_structured()) {
+		return Err("--hash can only be used to output the deps of each root in json or yaml format".into());
+	}
+	if options.with_metadata && (union || !matches!(mode, Mode::Deps) || !options.format.is_structured()) {
+		return Err("--with-metadata can only be used to output the deps of each root in json or yaml format".into());
+	}
+	let root_target = roots.iter().any(|root| root.target.is_some());
+	if (options.target.is_some() || root_target) && (union || !matches!(mode, Mode::Deps | Mode::Plan) || !matches!(options.format, output::Format::Make | output::Format::NinjaDepfile)) {
+		let flag = if options.target.is_some() { "--target" } else { "--root PATH:target=TARGET" };
+		return Err(format!("{} can only be used to output deps in make or ninja-depfile format, and can't be combined with --union", flag));
+	}
+	if options.strict_paths && (union || !matches!(mode, Mode::Deps | Mode::Plan) || !matches!(options.format, output::Format::Make | output::Format::NinjaDepfile)) {
+		return Err("--strict-paths can only be used to output deps in make or ninja-depfile format, and can't be combined with --union".into());
+	}
+	if options.phony && (union || !matches!(mode, Mode::Deps | Mode::Plan) || options.format != output::Format::Make) {
+		return Err("--phony can only be used to output deps in make format, and can't be combined with --union".into());
+	}
+	if options.group_by_lock && (watch || !matches!(mode, Mode::Deps) || !options.format.is_structured()) {
+		return Err("--group-by-lock can only be used to output deps in json or yaml format, and can't be combined with --watch".into());
+	}
+	// The deps are written to the file all at once at the end, which for jsonl would defeat the point.
+	let output_file = match output_path.as_deref().map(Path::new) {
+		Some(path) if path == Path::new("-") => None,
+		Some(_) if matches!(mode, Mode::Repro | Mode::Reduce) => None,
+		Some(_) if watch || batch_file.is_some() || patch_depfiles.is_some() || depfile.is_some() || !matches!(mode, Mode::Deps) => {
+			return Err("-o can only be used with repro, reduce or to output deps, and can't be combined with --watch, --batch or depfiles".into());
+		},
+		Some(_) if options.format == output::Format::Jsonl => return Err("-o can't be used with --format jsonl, which outputs each root as soon as it's done".into()),
+		Some(path) => Some(path.to_path_buf()),
+		None => None,
+	};
+	// With -o, output is kept until everything has succeeded, and only then written to the file.
+	let mut output_buffer = Vec::new();
+	let mut emit = |text: &[u8]| match output_file {
+		Some(_) => {
+			output_buffer.extend_from_slice(text);
+			Ok(())
+		},
+		None => output::write_stdout(text),
+	};
+	if options.deps_kind != DepsKind::All && (self_check || !matches!(mode, Mode::Deps)) {
+		return Err("--deps-kind can only be used to output deps, and can't be combined with --self-check".into());
+	}
+	if self_check && (union || watch || !matches!(mode, Mode::Deps | Mode::RawImports | Mode::Age | Mode::Stats | Mode::Duplicates)) {
+		return Err("--self-check can only be used when outputting deps of each root, and can't be combined with --union or --watch".into());
+	}
+	if let Mode::Worker = mode {
+		if !roots.is_empty() {
+			return Err("worker takes its files from stdin, not as arguments".into());
+		}
+		return worker::run(&options, &cache, &jpaths);
+	}
+	if let Mode::Check = mode {
+		let golden = golden.ok_or("check requires --against FILE")?;
+		let [root] = roots.as_slice() else {
+			return Err("check takes exactly one root".into());
+		};
+		let deps = resolve_deps(&options, &cache, Deadline::new(&root.path), &root.jpaths, &root.path)?;
+		return if update {
+			check::update(&golden, &deps)
+		} else {
+			check::check(&golden, &root.path, &deps)
+		};
+	}
+	if let Mode::Lint = mode {
+		let problems = lint::lint(&options, &cache, &roots)?;
+		let fixed = if fix || fix_dry_run {
+			lint::fix(&problems, fix_dry_run)?
+		} else {
+			vec![false; problems.len()]
+		};
+		if options.format == output::Format::Sarif {
+			let unfixed: Vec<_> = problems.iter().zip(fixed).filter(|(_, fixed)| !fixed).map(|(problem, _)| problem).collect();
+			println!("{}", sarif::log(unfixed.iter().copied()));
+			return match unfixed.len() {
+				0 => Ok(()),
+				n => Err(format!("Found {} problems with imports", n)),
+			};
+		}
+		let mut unfixed = 0;
+		for (problem, fixed) in problems.iter().zip(fixed) {
+			// With a dry run nothing is actually fixed, and the diff goes to stdout instead.
+			if fix_dry_run {
+				unfixed += 1;
+				let note = if fixed { " (fixable)" } else { "" };
+				eprintln!("{}:{}: {}: {}{}", problem.file.display(), problem.line, problem.rule, problem.message, note);
+				continue;
+			}
+			if !fixed {
+				unfixed += 1;
+			}
+			let note = if fixed { " (fixed)" } else { "" };
+			println!("{}:{}: {}: {}{}", problem.file.display(), problem.line, problem.rule, problem.message, note);
+		}
+		return match unfixed {
+			0 => Ok(()),
+			n => Err(format!("Found {} problems with imports", n)),
+		};
+	}
+	if (notify || codeowners_file.is_some()) && !matches!(mode, Mode::Affected) {
+		return Err("--notify-format and --codeowners can only be used with affected".into());
+	}
+	if let Mode::RDeps | Mode::Affected = mode {
+		if sample_chains.is_some() && !options.format.is_structured() {
+			return Err("--sample-chains requires --format json or yaml".into());
+		}
+		let importers = match from_graph {
+			Some(snapshot) => index::Importers::from_graph(&snapshot.graph),
+			None => index.ok_or("rdeps and affected require --index or --from-graph")?.importers(&options, &cache, &jpaths)?,
+		};
+		if let Mode::RDeps = mode {
+			for root in roots {
+				let tree = importers.rdep_tree(&[&root.path]);
+				let chains = sample_chains.map(|n| index::sample_chains(&tree, n));
+				let rdeps = tree.into_keys().collect();
+				output::write_stdout(&[output::format_rdeps(&options, &root.path, &rdeps, chains.as_deref()), options.format.line_end().into()].concat())?;
+			}
+		} else {
+			let changed: Vec<&Path> = roots.iter().map(|root| root.path.as_path()).collect();
+			let tree = importers.rdep_tree(&changed);
+			let chains = sample_chains.map(|n| index::sample_chains(&tree, n));
+			let affected: BTreeSet<PathBuf> = tree.into_keys().collect();
+			if notify {
+				let owners = match &codeowners_file {
+					Some(path) => codeowners::load(path)?,
+					None => codeowners::find()?,
+				};
+				// A file with several owners is included for each of them.
+				let mut by_owner: BTreeMap<Option<&str>, Vec<&PathBuf>> = BTreeMap::new();
+				for file in &affected {
+					match owners.owners(file) {
+						[] => by_owner.entry(None).or_default().push(file),
+						file_owners => for owner in file_owners {
+							by_owner.entry(Some(owner)).or_default().push(file);
+						},
+					}
+				}
+				for (owner, files) in by_owner {
+					println!("{}", output::format_notification(&changed, owner, &files));
+				}
+				return Ok(());
+			}
+			let affected = output::format_affected(&options, &changed, &affected, chains.as_deref());
+			if !affected.is_empty() {
+				output::write_stdout(&[affected, options.format.line_end().into()].concat())?;
+			}
+		}
+		return Ok(());
+	}
+	if redact && !matches!(mode, Mode::Graph | Mode::Repro) {
+		return Err("--redact can only be used with graph or repro".into());
+	}
+	if let Mode::Repro = mode {
+		let output = output_path.ok_or("repro requires -o FILE")?;
+		if redact && !shrink {
+			return Err("--redact can't be combined with --no-shrink, as the files themselves would give names away".into());
+		}
+		let settings = repro::Settings { output, shrink, redact };
+		let files = repro::create(&options, &cache, &roots, &settings)?;
+		eprintln!("Wrote {} files to {}", files, settings.output.display());
+		return Ok(());
+	}
+	if let Mode::Reduce = mode {
+		let [root] = roots.as_slice() else {
+			return Err("reduce takes exactly one root".into());
+		};
+		let settings = reduce::Settings {
+			check: reduce_check.ok_or("reduce requires --check CMD")?,
+			output: output_path.ok_or("reduce requires -o DIR")?,
+		};
+		let (reduced, files) = reduce::run(&options, &cache, root, &settings)?;
+		eprintln!("Reduced {} to {} files", root.path.display(), files);
+		println!("{}", reduced.display());
+		return Ok(());
+	}
+	if let Mode::Grep = mode {
+		let settings = grep::Settings { pattern: grep_pattern, only: grep_only };
+		return match grep::run(&options, &cache, &roots, &settings)? {
+			0 => Err(format!("Found no matches for {:?}", settings.pattern)),
+			_ => Ok(()),
+		};
+	}
+	if grep_only.is_some() {
+		return Err("--only can only be used with grep".into());
+	}
+	if let Mode::Plan = mode {
+		let location = patch_depfiles.as_ref().unwrap();
+		let steps = plan::run(&options, &cache, &roots, location, plan_changed.as_deref())?;
+		for step in &steps {
+			println!("{}", output::format_plan(step));
+		}
+		let written = steps.iter().filter(|step| !matches!(step.action, plan::Action::Keep)).count();
+		eprintln!("Would update {} of {} depfiles {}, and rebuild {} roots", written, roots.len(), location, steps.len());
+		return Ok(());
+	}
+	if let Mode::Health = mode {
+		health_settings.against = golden;
+		let report = health::run(&options, &cache, &roots, &health_settings)?;
+		let mut value = output::format_health(&report);
+		if let Some(against) = &health_settings.against {
+			let change = health::trend(&value, against)?;
+			if let json::Value::Object(items) = &mut value {
+				items.push(("change".into(), change));
+			}
+		}
+		println!("{}", options.format.render(&value));
+		return Ok(());
+	}
+	if let Mode::Partition = mode {
+		for batch in partition::run(&options, &cache, &roots, plan_changed.as_deref())? {
+			println!("{}", output::format_batch(&options, &batch));
+		}
+		return Ok(());
+	}
+	#[cfg(feature = "git")]
+	if let Mode::History = mode {
+		let [root] = roots.as_slice() else {
+			return Err("history takes exactly one root".into());
+		};
+		let since = history_since.ok_or("history requires --since REV")?;
+		for change in history::run(&options, &cache, root, &since)? {
+			println!("{}", output::format_history(&options, &change));
+		}
+		return Ok(());
+	}
+	if let Mode::Graph = mode {
+		let (mut graph, mut hashes) = match from_graph {
+			Some(_) if !roots.is_empty() => return Err("graph --from-graph outputs the saved graph, so takes no FILENAMEs".into()),
+			Some(snapshot) => (snapshot.graph, snapshot.hashes),
+			None => {
+				let graph = graph::build(&options, &cache, &roots)?;
+				let hashes = graph.hashes(&options.fs)?;
+				(graph, hashes)
+			},
+		};
+		if let Some(scope) = scope {
+			graph = graph.scope(&scope)?;
+			hashes.retain(|file, _| graph.edges.contains_key(file));
+		}
+		// Hashes of known files, eg. a public library, could give away what's in the graph.
+		if redact {
+			graph = graph.redact();
+			hashes.clear();
+		}
+		let cwd = std::env::current_dir().map_err(|e| format!("Failed to get current directory: {}", e))?;
+		let ids = graph.ids(&hashes, &cwd, &graph::id_base(options.relative_to.as_ref().map(|(dir, _)| dir.as_path()), &cwd));
+		println!("{}", output::format_graph(&options, &graph, &hashes, &ids));
+		return Ok(());
+	}
+	if union {
+		if batch_file.is_some() || watch || !matches!(mode, Mode::Deps) {
+			return Err("--union can only be used to output deps, and can't be combined with --batch or --watch".into());
+		}
+		// Without --group-by-lock, every root is in the same group.
+		let mut groups: BTreeMap<Option<PathBuf>, Vec<&Root>> = BTreeMap::new();
+		for root in &roots {
+			let lock = if options.group_by_lock { cache.lock_for(&options, &root.path)? } else { None };
+			groups.entry(lock).or_default().push(root);
+		}
+		let mut cross_lock = 0;
+		for (lock, roots) in &groups {
+			let mut needed_by: BTreeMap<PathBuf, Vec<&Path>> = BTreeMap::new();
+			for root in roots {
+				for dep in resolve_deps(&options, &cache, Deadline::new(&root.path), &root.jpaths, &root.path)? {
+					needed_by.entry(dep).or_default().push(&root.path);
+				}
+			}
+			if options.group_by_lock {
+				cross_lock += output::cross_lock(&options, &cache, lock.as_deref(), needed_by.keys())?.len();
+			}
+			let union = output::format_union(&options, &cache, roots, &needed_by)?;
+			if !union.is_empty() {
+				emit(&[union, options.format.line_end().into()].concat())?;
+			}
+		}
+		if let Some(path) = &output_file {
+			depfile::write(path, &output_buffer)?;
+		}
+		return match cross_lock {
+			0 => Ok(()),
+			n => Err(format!("Found {} deps under a different jsonnetfile.lock.json to the roots needing them", n)),
+		};
+	}
+	if watch {
+		if batch_file.is_some() || !matches!(mode, Mode::Deps) {
+			return Err("--watch can't be combined with --batch, --raw-imports, --age-report, --stats or --duplicates".into());
+		}
+		watch::run(&options, &cache, &roots, &watch_settings);
+	}
+	let mut duplicates = 0;
+	let mut cross_lock = 0;
+	let mut depfiles_written = 0;
+	// With jsonl, the roots are analyzed in parallel instead of one at a time.
+	let sequential = match options.format {
+		output::Format::Jsonl => {
+			stream::run(&options, &cache, &roots, jobs, jobserver::from_env())?;
+			&[][..]
+		},
+		_ => &roots[..],
+	};
+	if let (Mode::Deps, Some(location)) = (&mode, &patch_depfiles) {
+		let paths = roots.iter().map(|root| Ok((root.path.as_path(), depfile::path(location, &root.path)?)));
+		depfile::check_collisions(paths.collect::<Result<Vec<_>, String>>()?.into_iter())?;
+	}
+	for root in sequential {
+		let deadline = Deadline::new(&root.path);
+		match mode {
+			Mode::RawImports => {
+				// Report each root's own imports exactly as written, without touching any other file.
+				let imports = cache.parsed(&options, &root.path)?;
+				let mut as_str: Vec<_> = Vec::new();
+				for import in imports.iter() {
+					let path = import.path.to_string_lossy();
+					if !as_str.contains(&path) {
+						as_str.push(path);
+					}
+				}
+				println!("{}: {}", root.path.display(), as_str.join(" "));
+			},
+			Mode::Deps => {
+				let deps = resolve_deps(&options, &cache, deadline, &root.jpaths, &root.path)?;
+				match (&patch_depfiles, &depfile) {
+					(Some(location), _) => {
+						let contents = output::format_depfile(&options, &cache, deadline, root, &deps)?;
+						if depfile::patch(&depfile::path(location, &root.path)?, &contents)? {
+							depfiles_written += 1;
+						}
+					},
+					(None, Some(path)) => {
+						depfile::patch(path, &output::format_depfile(&options, &cache, deadline, root, &deps)?)?;
+					},
+					(None, None) => emit(&[output::format_deps(&options, &cache, deadline, root, &deps)?, options.format.line_end().into()].concat())?,
+				}
+				if options.group_by_lock {
+					let lock = cache.lock_for(&options, &root.path)?;
+					cross_lock += output::cross_lock(&options, &cache, lock.as_deref(), deps.iter())?.len();
+				}
+			},
+			Mode::Age => {
+				let deps = resolve_deps(&options, &cache, deadline, &root.jpaths, &root.path)?;
+				let deps: Vec<_> = deps.into_iter().collect();
+				age::report(&age_settings, &root.path, &deps)?;
+			},
+			Mode::Stats => stats::report(&options, &cache, deadline, root)?,
+			Mode::Annotate => annotate::report(&options, &cache, deadline, root)?,
+			Mode::Duplicates => duplicates += duplicates::report(&options, &cache, root)?,
+			Mode::RDeps | Mode::Affected | Mode::Graph | Mode::Worker | Mode::Check | Mode::Lint | Mode::Repro | Mode::Reduce | Mode::Grep | Mode::Plan | Mode::Partition | Mode::Health => unreachable!("handled above"),
+			#[cfg(feature = "git")]
+			Mode::History => unreachable!("handled above"),
+		}
+	}
+	if let Some(path) = &output_file {
+		depfile::write(path, &output_buffer)?;
+	}
+	if let Some(location) = &patch_depfiles {
+		eprintln!("Updated {} of {} depfiles {}", depfiles_written, roots.len(), location);
+	}
+	if duplicates > 0 {
+		return Err(format!("Found {} sets of files with duplicate contents", duplicates));
+	}
+	if cross_lock > 0 {
+		return Err(format!("Found {} deps under a different jsonnetfile.lock.json to the roots needing them", cross_lock));
+	}
+	if let Some(batch_file) = batch_file {
+		batch::run(&options, &cache, &jpaths, &batch_file, jobs, jobserver::from_env())?;
+	}
+	if self_check {
+		let problems = self_check::run(&options, &cache, &roots)?;
+		if problems > 0 {
+			return Err(format!("Self-check found {} problems", problems));
+		}
 	}
 	Ok(())
 }
@@ -282,7 +2048,7 @@ fn main() -> std::process::ExitCode {
 		Ok(()) => 0,
 		Err(e) => {
 			eprintln!("{}", e);
-			1
+			if limits::exceeded() { limits::EXIT_CODE } else { 1 }
 		}
 	}.into()
 }
@@ -0,0 +1,57 @@
+// Writing deps as a Nix list of paths, so a derivation's source can be filtered down to exactly the files
+// that are needed, eg. with lib.fileset.unions. Nix path literals are relative to the file they're written in,
+// so paths are written relative to the directory the .nix file will be in, given by --nix-root.
+
+use crate::resolve::{normalize, relative};
+use crate::Options;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+// A Nix string, with everything Nix treats specially escaped.
+fn string(s: &str) -> String {
+	let mut escaped = String::from("\"");
+	for c in s.chars() {
+		match c {
+			'"' | '\\' | '$' => {
+				escaped.push('\\');
+				escaped.push(c);
+			},
+			'\n' => escaped.push_str("\\n"),
+			'\r' => escaped.push_str("\\r"),
+			'\t' => escaped.push_str("\\t"),
+			c => escaped.push(c),
+		}
+	}
+	escaped.push('"');
+	escaped
+}
+
+// The relative path as a Nix path. Names a path literal can't contain are appended as a string instead,
+// which Nix turns back into a path.
+fn path_expr(relative: &Path) -> String {
+	let relative = relative.to_string_lossy();
+	let literal = relative.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-' | '+' | '/'))
+		&& !relative.ends_with('/');
+	match (literal, relative.starts_with("../")) {
+		(true, true) => relative.into_owned(),
+		(true, false) => format!("./{}", relative),
+		(false, _) => format!("(./. + {})", string(&format!("/{}", relative))),
+	}
+}
+
+// The deps as a Nix list, one per line. Unresolved deps are listed in a comment, as for make.
+pub fn list<'a>(options: &Options, deps: impl Iterator<Item = &'a PathBuf>, unresolved: &BTreeSet<&PathBuf>) -> Result<String, String> {
+	let cwd = std::env::current_dir().map_err(|e| format!("Failed to get current directory: {}", e))?;
+	let base = normalize(&cwd.join(&options.nix_root));
+	let paths: BTreeSet<String> = deps.map(|dep| {
+		path_expr(&relative(&base, &normalize(&cwd.join(dep))).expect("base and dep are absolute"))
+	}).collect();
+	let mut lines = vec!["[".to_string()];
+	lines.extend(paths.into_iter().map(|path| format!("  {}", path)));
+	if !unresolved.is_empty() {
+		let as_str: Vec<_> = unresolved.iter().map(|p| p.to_string_lossy()).collect();
+		lines.push(format!("  # unresolved: {}", as_str.join(" ")));
+	}
+	lines.push("]".into());
+	Ok(lines.join("\n"))
+}
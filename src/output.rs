@@ -0,0 +1,1046 @@
+use crate::diagram::Diagram;
+use crate::graph::{Diff, Graph};
+use crate::json::Value;
+use crate::scan::{ImportKind, Span};
+use crate::{bazel, nix, resolve, template, yaml};
+use crate::{closure_analyses, root_files, stats, unconditional_deps, Analysis, Cache, Deadline, Metadata, Options, Root};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+// The versions of the output that can be asked for with --output-version. Output that existing consumers may rely on
+// only changes in a new version, so they can keep getting what they expect until they're ready to move.
+// 1: the original output, with paths in make rules written as is.
+// 2: paths in make rules escaped as make expects, and json objects for each root include "output_version".
+// 3: make rules no longer list a root among its own prereqs, and a root in an import cycle has the whole cycle as targets.
+// 4: make rules leave out deps which weren't found, which are then only listed in the unresolved comment.
+pub const OUTPUT_VERSIONS: [u32; 4] = [1, 2, 3, 4];
+// What's written without --output-version. This stays the oldest until consumers have had time to move,
+// with a warning whenever the difference matters.
+pub const DEFAULT_OUTPUT_VERSION: u32 = 1;
+
+static WARNED_UNESCAPED: AtomicBool = AtomicBool::new(false);
+static WARNED_CYCLE: AtomicBool = AtomicBool::new(false);
+
+fn output_version(options: &Options) -> u32 {
+	options.output_version.unwrap_or(DEFAULT_OUTPUT_VERSION)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+	// FILE: DEP DEP DEP
+	Make,
+	// One JSON object per line.
+	Json,
+	// The same as json, but with roots analyzed in parallel and each output as soon as it's done. Only supported for deps.
+	Jsonl,
+	// The same as json, but with each object as a YAML document.
+	Yaml,
+	// A JSON object per root, giving each file in its deps with the files it imports directly. Only supported for the deps of each root.
+	JsonTree,
+	// The root's imports as an indented tree, like cargo tree, for humans. Only supported for the deps of each root.
+	Tree,
+	// A few aligned lines summarizing each root, for humans. Only supported for deps.
+	Table,
+	// FILE: DEP DEP DEP, escaped the way ninja expects. Only supported for the deps of a single root.
+	NinjaDepfile,
+	// A Bazel rule listing the deps as srcs. Only supported for the deps of each root.
+	Bazel,
+	// A Tup rule for each root, with its deps as inputs. Only supported for the deps of each root.
+	Tup,
+	// A Nix list of paths. Only supported for the deps of a single root, or with --union.
+	Nix,
+	// Each dep followed by a NUL, for xargs -0 and the like. Only supported for deps, rdeps and affected.
+	Print0,
+	// source,target,kind for each edge of the import graph. Only supported for graph.
+	Csv,
+	// A SARIF log of the problems found, for code scanning tools. Only supported for lint.
+	Sarif,
+	// The --template, rendered for each root. Only supported for the deps of each root.
+	Template,
+	// A rendering of the import graph. Only supported for graph.
+	Diagram(Diagram),
+}
+
+impl Format {
+	pub fn name(&self) -> &'static str {
+		match self {
+			Format::Make => "make",
+			Format::Json => "json",
+			Format::Jsonl => "jsonl",
+			Format::Yaml => "yaml",
+			Format::JsonTree => "json-tree",
+			Format::Tree => "tree",
+			Format::Table => "table",
+			Format::NinjaDepfile => "ninja-depfile",
+			Format::Bazel => "bazel",
+			Format::Tup => "tup",
+			Format::Nix => "nix",
+			Format::Print0 => "print0",
+			Format::Csv => "csv",
+			Format::Sarif => "sarif",
+			Format::Template => "template",
+			Format::Diagram(diagram) => diagram.name(),
+		}
+	}
+
+	pub fn parse(name: &str) -> Result<Format, String> {
+		match name {
+			"make" => Ok(Format::Make),
+			"json" => Ok(Format::Json),
+			"jsonl" => Ok(Format::Jsonl),
+			"yaml" => Ok(Format::Yaml),
+			"json-tree" => Ok(Format::JsonTree),
+			"tree" => Ok(Format::Tree),
+			"table" => Ok(Format::Table),
+			"ninja-depfile" => Ok(Format::NinjaDepfile),
+			"bazel" => Ok(Format::Bazel),
+			"tup" => Ok(Format::Tup),
+			"nix" => Ok(Format::Nix),
+			"print0" => Ok(Format::Print0),
+			"csv" => Ok(Format::Csv),
+			"sarif" => Ok(Format::Sarif),
+			_ => match Diagram::parse(name)? {
+				Some(diagram) => Ok(Format::Diagram(diagram)),
+				None => Err(format!("Unknown format {:?}, expected one of: make, json, jsonl, yaml, json-tree, tree, table, ninja-depfile, bazel, tup, nix, print0, csv, sarif, {}", name, Diagram::names().join(", "))),
+			},
+		}
+	}
+
+	// Whether this is json, jsonl or yaml, which have the same structure.
+	pub fn is_structured(&self) -> bool {
+		matches!(self, Format::Json | Format::Jsonl | Format::Yaml)
+	}
+
+	// Render a piece of json or yaml output.
+	pub fn render(&self, value: &Value) -> String {
+		match self {
+			Format::Yaml => yaml::document(value),
+			_ => value.to_string(),
+		}
+	}
+
+	// What to follow each piece of output with. Print0 output is already terminated.
+	pub fn line_end(&self) -> &'static str {
+		match self {
+			Format::Print0 => "",
+			_ => "\n",
+		}
+	}
+}
+
+pub fn path_value(path: &Path) -> Value {
+	Value::String(path.to_string_lossy().into_owned())
+}
+
+pub fn paths_value<'a>(paths: impl Iterator<Item = &'a PathBuf>) -> Value {
+	Value::Array(paths.map(|path| path_value(path)).collect())
+}
+
+// A path as it's written in the output of deps: relative to --relative-to if it was given, or else as it was found.
+pub fn shown<'a>(options: &Options, path: &'a Path) -> Cow<'a, Path> {
+	let Some((dir, cwd)) = &options.relative_to else {
+		return Cow::Borrowed(path);
+	};
+	// Both are absolute, so there's always a way from one to the other.
+	let relative = resolve::relative(dir, &resolve::normalize(&cwd.join(path))).expect("dir and path are absolute");
+	Cow::Owned(if relative.as_os_str().is_empty() { ".".into() } else { relative })
+}
+
+fn shown_value(options: &Options, path: &Path) -> Value {
+	path_value(&shown(options, path))
+}
+
+fn shown_values<'a>(options: &Options, paths: impl Iterator<Item = &'a PathBuf>) -> Value {
+	Value::Array(paths.map(|path| shown_value(options, path)).collect())
+}
+
+// Format the deps of a root as a single line of output. Deps are sorted in every format, so that the output
+// is the same from run to run and only changes when the deps do.
+// This is bytes rather than a string as paths in --format print0 are written exactly as they are.
+pub fn format_deps(options: &Options, cache: &Cache, deadline: Deadline, root: &Root, deps: &HashSet<PathBuf>) -> Result<Vec<u8>, String> {
+	// Deps which weren't found anywhere are listed separately, so they aren't mistaken for ones we know are right.
+	let analyses = closure_analyses(options, cache, deadline, &root.jpaths, &root.path)?;
+	let unresolved: BTreeSet<&PathBuf> = analyses.iter().flat_map(|(_, analysis)| analysis.unresolved.iter()).collect();
+	let sorted: BTreeSet<&PathBuf> = deps.iter().collect();
+	let resolved = sorted.iter().copied().filter(|path| !unresolved.contains(path));
+	Ok(match options.format {
+		Format::Table => format_table(options, root, deps, &analyses, &unresolved)?,
+		Format::Make | Format::Csv | Format::Sarif | Format::Diagram(_) => make_rule(options, &root.path, target(options, root).as_deref(), resolved, &unresolved, &analyses)?,
+		Format::NinjaDepfile => ninja_rule(options, &root.path, target(options, root).as_deref(), &resolved.collect())?,
+		Format::Bazel => bazel::rule(options, &root.path, resolved, &unresolved)?,
+		Format::Tup => tup_rule(options, &root.path, resolved, &unresolved)?,
+		Format::Nix => nix::list(options, resolved, &unresolved)?,
+		Format::Print0 => return Ok(print0_list(options, Some(&root.path), resolved)),
+		Format::JsonTree => json_tree(options, cache, root, &sorted, &unresolved, &analyses)?,
+		Format::Tree => tree(options, cache, root, &unresolved, &analyses)?,
+		Format::Template => template_vars(options, cache, deadline, root, deps, &unresolved).map(|vars| {
+			options.template.as_ref().expect("--template sets the template").render(&vars)
+		})?,
+		Format::Json | Format::Jsonl | Format::Yaml => {
+			let unconditional = unconditional_deps(options, cache, deadline, &root.jpaths, &root.path)?;
+			// Combine the origins of every import of each dep, from anywhere in the closure.
+			let mut origins: BTreeMap<&Path, BTreeSet<_>> = BTreeMap::new();
+			for (_, analysis) in &analyses {
+				for (dep, origin) in &analysis.origins {
+					origins.entry(dep).or_default().insert(*origin);
+				}
+			}
+			// The root's own imports. Files given on the command line are evaluated with every root,
+			// so they count as imports of it.
+			let analysis = cache.analysis(options, &root.jpaths, &root.path)?;
+			let mut items = vec![
+				("root".into(), shown_value(options, &root.path)),
+				("leaf_deps".into(), shown_values(options, direct(&analysis.leaf_deps, &options.str_files).iter())),
+				("deep_deps".into(), shown_values(options, direct(&analysis.deep_deps, &options.code_files).iter())),
+				("deps".into(), shown_values(options, resolved)),
+				("unresolved".into(), shown_values(options, unresolved.iter().copied())),
+				// Deps which may not actually be loaded, as every chain of imports leading to them
+				// passes through a conditional import.
+				("conditional".into(), shown_values(options, sorted.iter().copied().filter(|path| !unconditional.contains(*path)))),
+				// Where in the AST each dep was imported from. The root itself is not included,
+				// unless it is imported by another file.
+				("origins".into(), Value::Object(origins.into_iter().map(|(dep, origins)| {
+					let names = origins.into_iter().map(|origin| Value::String(origin.name().into())).collect();
+					(shown(options, dep).to_string_lossy().into_owned(), Value::Array(names))
+				}).collect())),
+			];
+			if options.group_by_lock {
+				items.extend(lock_items(options, cache, &root.path, sorted.iter().copied())?);
+			}
+			if options.structured_data {
+				// As with origins, a file parsed as data anywhere in the closure counts, even if it's also imported plainly elsewhere.
+				let mut data_inputs: BTreeMap<&Path, BTreeSet<_>> = BTreeMap::new();
+				for (_, analysis) in &analyses {
+					for (dep, format) in &analysis.data_inputs {
+						data_inputs.entry(dep).or_default().insert(*format);
+					}
+				}
+				items.push(("structured_data".into(), Value::Object(data_inputs.into_iter().map(|(dep, formats)| {
+					let names = formats.into_iter().map(|format| Value::String(format.name().into())).collect();
+					(shown(options, dep).to_string_lossy().into_owned(), Value::Array(names))
+				}).collect())));
+			}
+			if options.occurrences {
+				items.push(("occurrences".into(), occurrences_value(options, cache, &analyses)?));
+			}
+			if options.provenance {
+				items.push(("provenance".into(), provenance_value(options, cache, root, &analyses)?));
+			}
+			if options.hash {
+				// Unresolved deps which don't exist have nothing to hash, so are left out.
+				let mut hashes = Vec::new();
+				for dep in &sorted {
+					if let Some(digest) = cache.digest(options, dep)? {
+						hashes.push((shown(options, dep).to_string_lossy().into_owned(), Value::String(digest)));
+					}
+				}
+				items.push(("hashes".into(), Value::Object(hashes)));
+			}
+			if options.with_metadata {
+				let mut metadata = Vec::new();
+				for dep in &sorted {
+					if let Some(Metadata { size, mtime }) = cache.file_metadata(options, dep)? {
+						metadata.push((shown(options, dep).to_string_lossy().into_owned(), Value::Object(vec![
+							("size".into(), Value::Number(size as f64)),
+							("mtime".into(), Value::Number(mtime as f64)),
+						])));
+					}
+				}
+				items.push(("metadata".into(), Value::Object(metadata)));
+			}
+			if !root.meta.is_empty() {
+				items.push(("meta".into(), Value::Object(root.meta.iter().map(|(key, value)| {
+					(key.clone(), Value::String(value.clone()))
+				}).collect())));
+			}
+			if output_version(options) >= 2 {
+				items.push(("output_version".into(), Value::Number(output_version(options) as f64)));
+			}
+			options.format.render(&Value::Object(items))
+		},
+	}.into_bytes())
+}
+
+// For each dep, every file in the closure that imports it, with how many times and where.
+// A file with the same import more than once has them all listed, unlike elsewhere where they're only counted once.
+fn occurrences_value(options: &Options, cache: &Cache, analyses: &[(PathBuf, Arc<Analysis>)]) -> Result<Value, String> {
+	let mut occurrences: BTreeMap<&Path, BTreeMap<&Path, Vec<Span>>> = BTreeMap::new();
+	for (file, analysis) in analyses {
+		let imports = cache.parsed(options, file)?;
+		for (import, target) in imports.iter().zip(&analysis.targets) {
+			occurrences.entry(target).or_default().entry(file).or_default().push(import.span);
+		}
+	}
+	Ok(Value::Object(occurrences.into_iter().map(|(dep, files)| {
+		let files = files.into_iter().map(|(file, spans)| Value::Object(vec![
+			("file".into(), shown_value(options, file)),
+			("count".into(), Value::Number(spans.len() as f64)),
+			("locations".into(), Value::Array(spans.iter().map(|span| Value::Object(vec![
+				("line".into(), Value::Number(span.line as f64)),
+				("start".into(), Value::Number(span.start as f64)),
+				("end".into(), Value::Number(span.end as f64)),
+			])).collect())),
+		])).collect();
+		(shown(options, dep).to_string_lossy().into_owned(), Value::Array(files))
+	}).collect()))
+}
+
+// For each dep, how it was pulled in: each kind of import it was imported with, and by which file.
+fn provenance_value(options: &Options, cache: &Cache, root: &Root, analyses: &[(PathBuf, Arc<Analysis>)]) -> Result<Value, String> {
+	let mut provenance: BTreeMap<&Path, BTreeSet<(&Path, ImportKind)>> = BTreeMap::new();
+	for (file, analysis) in analyses {
+		let imports = cache.parsed(options, file)?;
+		for (import, target) in imports.iter().zip(&analysis.targets) {
+			provenance.entry(target).or_default().insert((file, import.kind));
+		}
+	}
+	// Files given on the command line are evaluated with the root, so they count as imports of it.
+	for (files, kind) in [(&options.code_files, ImportKind::Import), (&options.str_files, ImportKind::ImportStr)] {
+		for file in files {
+			provenance.entry(file).or_default().insert((&root.path, kind));
+		}
+	}
+	Ok(Value::Object(provenance.into_iter().map(|(dep, imports)| {
+		let imports = imports.into_iter().map(|(file, kind)| Value::Object(vec![
+			("kind".into(), Value::String(kind.name().into())),
+			("file".into(), shown_value(options, file)),
+		])).collect();
+		(shown(options, dep).to_string_lossy().into_owned(), Value::Array(imports))
+	}).collect()))
+}
+
+// The root and every dep, each with the files it imports directly, as in the json leaf_deps and deep_deps
+// of a root. Deps which aren't jsonnet files have empty lists.
+fn json_tree(
+	options: &Options,
+	cache: &Cache,
+	root: &Root,
+	deps: &BTreeSet<&PathBuf>,
+	unresolved: &BTreeSet<&PathBuf>,
+	analyses: &[(PathBuf, Arc<Analysis>)],
+) -> Result<String, String> {
+	let mut files: BTreeMap<&Path, (BTreeSet<&PathBuf>, BTreeSet<&PathBuf>)> = deps.iter().map(|dep| (dep.as_path(), Default::default())).collect();
+	for (file, analysis) in analyses {
+		files.insert(file, (analysis.leaf_deps.iter().collect(), analysis.deep_deps.iter().collect()));
+	}
+	// Files given on the command line are evaluated with the root, so they count as imports of it.
+	let analysis = cache.analysis(options, &root.jpaths, &root.path)?;
+	let (root_leaf_deps, root_deep_deps) = (direct(&analysis.leaf_deps, &options.str_files), direct(&analysis.deep_deps, &options.code_files));
+	files.insert(&root.path, (root_leaf_deps.iter().collect(), root_deep_deps.iter().collect()));
+	Ok(Value::Object(vec![
+		("root".into(), shown_value(options, &root.path)),
+		("files".into(), Value::Object(files.into_iter().map(|(file, (leaf_deps, deep_deps))| {
+			(shown(options, file).to_string_lossy().into_owned(), Value::Object(vec![
+				("leaf_deps".into(), shown_values(options, leaf_deps.into_iter())),
+				("deep_deps".into(), shown_values(options, deep_deps.into_iter())),
+			]))
+		}).collect())),
+		("unresolved".into(), shown_values(options, unresolved.iter().copied())),
+	]).to_string())
+}
+
+// The root's imports as a tree, with each file's imports indented under it, sorted, leaf deps and deep deps together:
+//   main.jsonnet
+//   ├── a.libsonnet
+//   │   └── b.libsonnet
+//   └── b.libsonnet (*)
+// A file's imports are only shown the first time it appears, and later appearances are marked (*) if it has any. An import of
+// a file already on the way from the root to it is marked (cycle), and one that wasn't found (unresolved).
+// The tree is walked with a stack rather than by recursing, as chains of imports can be long.
+fn tree(
+	options: &Options,
+	cache: &Cache,
+	root: &Root,
+	unresolved: &BTreeSet<&PathBuf>,
+	analyses: &[(PathBuf, Arc<Analysis>)],
+) -> Result<String, String> {
+	let analyses: HashMap<&Path, &Analysis> = analyses.iter().map(|(file, analysis)| (file.as_path(), analysis.as_ref())).collect();
+	// Each file a file imports, and whether it's imported as code, so its own imports go under it.
+	let imports = |leaf_deps: &[PathBuf], deep_deps: &[PathBuf]| -> Vec<(PathBuf, bool)> {
+		let imports: BTreeSet<(PathBuf, bool)> = leaf_deps.iter().map(|dep| (dep.clone(), false))
+			.chain(deep_deps.iter().map(|dep| (dep.clone(), true)))
+			.collect();
+		imports.into_iter().collect()
+	};
+	// Files given on the command line are evaluated with the root, so they count as imports of it.
+	let analysis = cache.analysis(options, &root.jpaths, &root.path)?;
+	let root_imports = imports(&direct(&analysis.leaf_deps, &options.str_files), &direct(&analysis.deep_deps, &options.code_files));
+	let mut lines = vec![shown(options, &root.path).display().to_string()];
+	let mut expanded = HashSet::from([root.path.clone()]);
+	let mut ancestors = vec![root.path.clone()];
+	// Each import still to be shown, with how deep it is, whether it's the last of its file's imports,
+	// and what to start its line with. These are pushed in reverse, so they're popped in order.
+	let mut stack: Vec<(PathBuf, bool, usize, bool, String)> = Vec::new();
+	let push = |stack: &mut Vec<_>, imports: Vec<(PathBuf, bool)>, depth: usize, prefix: &str| {
+		let count = imports.len();
+		for (i, (file, code)) in imports.into_iter().enumerate().rev() {
+			stack.push((file, code, depth, i + 1 == count, prefix.to_string()));
+		}
+	};
+	push(&mut stack, root_imports, 1, "");
+	while let Some((file, code, depth, last, prefix)) = stack.pop() {
+		ancestors.truncate(depth);
+		let mut line = format!("{}{}{}", prefix, if last { "└── " } else { "├── " }, shown(options, &file).display());
+		if unresolved.contains(&file) {
+			line.push_str(" (unresolved)");
+		}
+		// Files which import nothing have nothing to expand, so there's no need to mark them.
+		let analysis = analyses.get(file.as_path()).filter(|analysis| code && !(analysis.leaf_deps.is_empty() && analysis.deep_deps.is_empty()));
+		if analysis.is_some() && ancestors.contains(&file) {
+			line.push_str(" (cycle)");
+		} else if analysis.is_some() && !expanded.insert(file.clone()) {
+			line.push_str(" (*)");
+		} else if let Some(analysis) = analysis {
+			let prefix = format!("{}{}", prefix, if last { "    " } else { "│   " });
+			push(&mut stack, imports(&analysis.leaf_deps, &analysis.deep_deps), depth + 1, &prefix);
+			ancestors.push(file);
+		}
+		lines.push(line);
+	}
+	Ok(lines.join("\n"))
+}
+
+// The deps followed by any of extra that aren't already among them.
+fn direct(deps: &[PathBuf], extra: &[PathBuf]) -> Vec<PathBuf> {
+	let mut all = deps.to_vec();
+	all.extend(extra.iter().filter(|path| !deps.contains(path)).cloned());
+	all
+}
+
+// The variables a --template can use. Lists are sorted, so the output only changes when the deps do,
+// and each has the same meaning as in json.
+pub const TEMPLATE_SCALARS: [&str; 1] = ["root"];
+pub const TEMPLATE_LISTS: [&str; 5] = ["deps", "leaf_deps", "deep_deps", "unresolved", "conditional"];
+
+fn template_vars(
+	options: &Options,
+	cache: &Cache,
+	deadline: Deadline,
+	root: &Root,
+	deps: &HashSet<PathBuf>,
+	unresolved: &BTreeSet<&PathBuf>,
+) -> Result<Vec<(&'static str, template::Var)>, String> {
+	let list = |paths: &mut dyn Iterator<Item = &PathBuf>| {
+		let paths: BTreeSet<_> = paths.map(|path| shown(options, path).to_string_lossy().into_owned()).collect();
+		template::Var::Many(paths.into_iter().collect())
+	};
+	let analysis = cache.analysis(options, &root.jpaths, &root.path)?;
+	let unconditional = unconditional_deps(options, cache, deadline, &root.jpaths, &root.path)?;
+	Ok(vec![
+		("root", template::Var::One(shown(options, &root.path).to_string_lossy().into_owned())),
+		("deps", list(&mut deps.iter().filter(|path| !unresolved.contains(path)))),
+		("leaf_deps", list(&mut direct(&analysis.leaf_deps, &options.str_files).iter())),
+		("deep_deps", list(&mut direct(&analysis.deep_deps, &options.code_files).iter())),
+		("unresolved", list(&mut unresolved.iter().copied())),
+		("conditional", list(&mut deps.iter().filter(|path| !unconditional.contains(*path)))),
+	])
+}
+
+// A path in a make rule. From output version 2 it's escaped, as make would otherwise split it at spaces, and take
+// # for a comment and $ for a variable. Before that it's written as is, with a warning (once) if that matters
+// and the version wasn't asked for, as that's now relying on the old default. With --strict-paths, it's an error
+// either way.
+fn make_path(options: &Options, path: &Path) -> Result<String, String> {
+	let path = shown(options, path);
+	let path_str = path.to_string_lossy();
+	check_strict(options, &path_str)?;
+	let escaped = escape(&path_str);
+	if output_version(options) >= 2 {
+		return Ok(escaped);
+	}
+	if escaped != path_str && options.output_version.is_none() && !WARNED_UNESCAPED.swap(true, Ordering::Relaxed) {
+		eprintln!(
+			"Warning: {} is written as is, so make will misread it. Pass --output-version 2 to escape paths, \
+			or --output-version 1 to keep writing them as is without this warning", path_str,
+		);
+	}
+	Ok(path_str.into_owned())
+}
+
+// With --strict-paths, fail on a path which would need escaping in a depfile, or which can't be written to one at all.
+fn check_strict(options: &Options, path: &str) -> Result<(), String> {
+	if options.strict_paths && path.contains(|c: char| c.is_whitespace() || c == '$' || c == '#') {
+		return Err(format!("{:?} contains whitespace, $ or #, which --strict-paths doesn't allow", path));
+	}
+	Ok(())
+}
+
+// The shortest chain of imports from the root back to itself, starting and ending with the root,
+// or empty if it isn't in an import cycle.
+fn import_cycle(root: &Path, analyses: &[(PathBuf, Arc<Analysis>)]) -> Vec<PathBuf> {
+	let analyses: HashMap<&Path, &Analysis> = analyses.iter().map(|(file, analysis)| (file.as_path(), analysis.as_ref())).collect();
+	let mut parents: HashMap<&Path, &Path> = HashMap::new();
+	let mut queue = VecDeque::from([root]);
+	while let Some(file) = queue.pop_front() {
+		for dep in analyses.get(file).map(|analysis| analysis.deep_deps.as_slice()).unwrap_or_default() {
+			if dep == root {
+				let mut chain = vec![root.to_path_buf()];
+				let mut file = file;
+				while file != root {
+					chain.push(file.to_path_buf());
+					file = parents[file];
+				}
+				chain.push(root.to_path_buf());
+				chain.reverse();
+				return chain;
+			}
+			if !parents.contains_key(dep.as_path()) {
+				parents.insert(dep, file);
+				queue.push_back(dep);
+			}
+		}
+	}
+	Vec::new()
+}
+
+// Every file in the same import cycle as the root, ie. which imports it, directly or not, and which it imports.
+fn cycle_members<'a>(root: &'a Path, analyses: &'a [(PathBuf, Arc<Analysis>)]) -> BTreeSet<&'a Path> {
+	let mut importers: HashMap<&Path, Vec<&Path>> = HashMap::new();
+	for (file, analysis) in analyses {
+		for dep in &analysis.deep_deps {
+			importers.entry(dep).or_default().push(file);
+		}
+	}
+	// Everything in the analyses is imported by the root, so it's only a matter of which of them import it back.
+	let mut members = BTreeSet::from([root]);
+	let mut to_expand = vec![root];
+	while let Some(file) = to_expand.pop() {
+		for importer in importers.get(file).into_iter().flatten() {
+			if members.insert(importer) {
+				to_expand.push(importer);
+			}
+		}
+	}
+	members
+}
+
+// The placeholders a --target can use, and what each is for a given root.
+const TARGET_PLACEHOLDERS: [&str; 4] = ["root", "dir", "name", "stem"];
+
+fn placeholder(name: &str, root: &Path) -> String {
+	let part = match name {
+		"root" => Some(root.as_os_str()),
+		"dir" => root.parent().map(|dir| if dir.as_os_str().is_empty() { Path::new(".") } else { dir }).map(|dir| dir.as_os_str()),
+		"name" => root.file_name(),
+		"stem" => root.file_stem(),
+		_ => unreachable!("placeholders are checked when parsing"),
+	};
+	part.unwrap_or_default().to_string_lossy().into_owned()
+}
+
+// Fill in each {PLACEHOLDER} in a target, from --target or for a particular root, eg. "build/{stem}.yaml". With None for the root, only checks that
+// every placeholder is known.
+pub fn render_target(pattern: &str, root: Option<&Path>) -> Result<String, String> {
+	let mut target = String::new();
+	let mut rest = pattern;
+	while let Some(start) = rest.find('{') {
+		target.push_str(&rest[..start]);
+		let (name, after) = rest[start + 1..].split_once('}').ok_or_else(|| format!("Target {:?} has a {{ without a matching }}", pattern))?;
+		if !TARGET_PLACEHOLDERS.contains(&name) {
+			return Err(format!("Unknown placeholder {{{}}} in target {:?}, expected one of: {}", name, pattern, TARGET_PLACEHOLDERS.map(|name| format!("{{{}}}", name)).join(", ")));
+		}
+		if let Some(root) = root {
+			target.push_str(&placeholder(name, root));
+		}
+		rest = after;
+	}
+	target.push_str(rest);
+	Ok(target)
+}
+
+// The target of the root's rule, if one was given for the root or with --target.
+fn target(options: &Options, root: &Root) -> Option<PathBuf> {
+	let pattern = root.target.as_deref().or(options.target.as_deref())?;
+	Some(render_target(pattern, Some(&root.path)).expect("targets are checked when parsing").into())
+}
+
+// A make rule for the root. Make drops any prereq that's also the target, or that leads back to it, but complains
+// about each one, so from output version 3 the root is left out of its own prereqs, and a root in an import cycle
+// gets a rule with every file in the cycle as targets and none of them as prereqs. Before that, the first root
+// found in a cycle gets a warning if the version wasn't asked for. Given a target, that's the only target, and since
+// it's not the root, none of this matters and the root is a prereq like any other.
+fn make_rule<'a>(
+	options: &Options,
+	root: &Path,
+	target: Option<&Path>,
+	deps: impl Iterator<Item = &'a PathBuf>,
+	unresolved: &BTreeSet<&'a PathBuf>,
+	analyses: &[(PathBuf, Arc<Analysis>)],
+) -> Result<String, String> {
+	let grouped = output_version(options) >= 3 && target.is_none();
+	let cycle = match grouped {
+		true => cycle_members(root, analyses),
+		false => BTreeSet::from([root]),
+	};
+	if !grouped && target.is_none() && options.output_version.is_none() && !WARNED_CYCLE.load(Ordering::Relaxed) {
+		let chain = import_cycle(root, analyses);
+		if !chain.is_empty() && !WARNED_CYCLE.swap(true, Ordering::Relaxed) {
+			let as_str: Vec<_> = chain.iter().map(|p| p.to_string_lossy()).collect();
+			eprintln!(
+				"Warning: {} is in an import cycle ({}), so make will complain of a circular dependency. \
+				Pass --output-version 3 to write the cycle as a single rule, or --output-version 1 to keep this output without this warning",
+				root.display(), as_str.join(" -> "),
+			);
+		}
+	}
+	// Until version 4, deps which weren't found are listed where they were assumed to be, as well as in the comment,
+	// as they may well be generated files that haven't been made yet. Then make rebuilds once they are.
+	let missing = unresolved.iter().copied().filter(|_| output_version(options) < 4);
+	let deps: BTreeSet<_> = deps.chain(missing).filter(|dep| !grouped || !cycle.contains(dep.as_path())).collect();
+	let targets: Vec<_> = match target {
+		Some(target) => vec![make_path(options, target)?],
+		None => std::iter::once(root).chain(cycle.iter().copied().filter(|file| *file != root)).map(|p| make_path(options, p)).collect::<Result<_, _>>()?,
+	};
+	let as_str: Vec<_> = deps.iter().map(|p| make_path(options, p)).collect::<Result<_, _>>()?;
+	let mut rule = format!("{}: {}", targets.join(" "), as_str.join(" "));
+	// An empty rule for each dep, like gcc -MP, so make doesn't fail when one is deleted or renamed.
+	if options.phony {
+		for dep in deps.iter().filter(|dep| !cycle.contains(dep.as_path())) {
+			rule.push_str(&format!("\n{}:", make_path(options, dep)?));
+		}
+	}
+	if !unresolved.is_empty() {
+		let as_str: Vec<_> = unresolved.iter().map(|p| shown(options, p).to_string_lossy().into_owned()).collect();
+		rule.push_str(&format!("\n# unresolved: {}", as_str.join(" ")));
+	}
+	Ok(rule)
+}
+
+// Each path followed by a NUL. Paths can't contain NULs, so this is unambiguous whatever else is in them.
+// With --print0-roots, the list is preceded by the root and followed by an empty entry, so that lists for
+// multiple roots can be told apart. As with ninja_rule, there's nowhere to put unresolved deps.
+fn print0_list<'a>(options: &Options, root: Option<&Path>, paths: impl Iterator<Item = &'a PathBuf>) -> Vec<u8> {
+	let mut list = Vec::new();
+	let root = root.filter(|_| options.print0_roots);
+	for path in root.into_iter().chain(paths.map(|path| path.as_path())) {
+		list.extend_from_slice(shown(options, path).as_os_str().as_bytes());
+		list.push(0);
+	}
+	if root.is_some() {
+		list.push(0);
+	}
+	list
+}
+
+// Write output as is, which may not be UTF-8 with --format print0.
+pub fn write_stdout(text: &[u8]) -> Result<(), String> {
+	std::io::stdout().lock().write_all(text).map_err(|e| format!("Failed to write output: {}", e))
+}
+
+// Escape a path the way both make and ninja unescape it in a depfile: `\ ` (doubling any backslashes before it),
+// `\#` and `$$`. Any other backslash is passed through as is.
+fn escape(path: &str) -> String {
+	let mut escaped = String::new();
+	let mut backslashes = 0;
+	for c in path.chars() {
+		match c {
+			' ' => {
+				escaped.extend(std::iter::repeat_n('\\', backslashes + 1));
+				escaped.push(' ');
+			},
+			'#' => escaped.push_str("\\#"),
+			'$' => escaped.push_str("$$"),
+			c => escaped.push(c),
+		}
+		backslashes = if c == '\\' { backslashes + 1 } else { 0 };
+	}
+	escaped
+}
+
+// Escape a path for a ninja depfile, failing if it's one ninja can't read back.
+fn ninja_escape(options: &Options, path: &Path) -> Result<String, String> {
+	let path = shown(options, path);
+	let path = path.to_string_lossy();
+	if path.ends_with(':') {
+		return Err(format!("Can't write {} to a ninja depfile, as ninja would take it for a target", path));
+	}
+	if path.contains(['\n', '\0']) {
+		return Err(format!("Can't write {:?} to a ninja depfile", path));
+	}
+	check_strict(options, &path)?;
+	Ok(escape(&path))
+}
+
+// A ninja depfile rule. Ninja doesn't allow comments, so unlike make_rule this has nowhere to list unresolved deps.
+// They're left out regardless, which at worst means ninja misses a rebuild when a guessed file is created.
+fn ninja_rule(options: &Options, root: &Path, target: Option<&Path>, deps: &BTreeSet<&PathBuf>) -> Result<String, String> {
+	let deps: Vec<_> = deps.iter().map(|dep| ninja_escape(options, dep)).collect::<Result<_, _>>()?;
+	Ok(format!("{}: {}", ninja_escape(options, target.unwrap_or(root))?, deps.join(" ")))
+}
+
+// A Tup rule which runs the command with the root as its input, eg. ": main.jsonnet | lib.libsonnet |> jsonnet %f -o %o |> %B.json".
+// Tup needs every file the command reads to exist or be generated by another rule, so the other deps are order-only inputs,
+// which aren't included in %f, and unresolved deps are left out and listed in a comment instead.
+fn tup_rule<'a>(options: &Options, root: &Path, deps: impl Iterator<Item = &'a PathBuf>, unresolved: &BTreeSet<&PathBuf>) -> Result<String, String> {
+	// Tup splits inputs on whitespace, with no way to escape it.
+	let path_str = |path: &Path| match shown(options, path).to_string_lossy() {
+		s if s.contains(char::is_whitespace) => Err(format!("Can't write {:?} to a Tup rule, as it contains whitespace", s)),
+		s => Ok(s.into_owned()),
+	};
+	let mut deps: Vec<_> = deps.filter(|dep| dep.as_path() != root).map(|dep| path_str(dep)).collect::<Result<_, _>>()?;
+	deps.sort();
+	let mut inputs = path_str(root)?;
+	if !deps.is_empty() {
+		inputs = format!("{} | {}", inputs, deps.join(" "));
+	}
+	let mut rule = format!(": {} |> {} |> {}", inputs, options.tup_command, options.tup_output);
+	if !unresolved.is_empty() {
+		let as_str: Vec<_> = unresolved.iter().map(|p| shown(options, p).to_string_lossy().into_owned()).collect();
+		rule.push_str(&format!("\n# unresolved: {}", as_str.join(" ")));
+	}
+	Ok(rule)
+}
+
+// Format the deps of a root as the contents of a depfile, in the make (or ninja) format.
+pub fn format_depfile(options: &Options, cache: &Cache, deadline: Deadline, root: &Root, deps: &HashSet<PathBuf>) -> Result<String, String> {
+	let analyses = closure_analyses(options, cache, deadline, &root.jpaths, &root.path)?;
+	let unresolved: BTreeSet<&PathBuf> = analyses.iter().flat_map(|(_, analysis)| analysis.unresolved.iter()).collect();
+	let resolved: BTreeSet<_> = deps.iter().filter(|path| !unresolved.contains(path)).collect();
+	Ok(match options.format {
+		Format::NinjaDepfile => ninja_rule(options, &root.path, target(options, root).as_deref(), &resolved)?,
+		_ => make_rule(options, &root.path, target(options, root).as_deref(), resolved.into_iter(), &unresolved, &analyses)?,
+	} + "\n")
+}
+
+// The length of the longest chain of imports needed to reach any dep, taking the shortest chain to each.
+pub fn depth(options: &Options, root: &Root, analyses: &[(PathBuf, Arc<Analysis>)]) -> usize {
+	let analyses: HashMap<&Path, &Analysis> = analyses.iter().map(|(file, analysis)| (file.as_path(), analysis.as_ref())).collect();
+	let mut depths: HashMap<&Path, usize> = HashMap::new();
+	let files = root_files(options, &root.path);
+	let mut queue: std::collections::VecDeque<(&Path, usize)> = files.iter().map(|file| (file.as_path(), 0)).collect();
+	while let Some((file, depth)) = queue.pop_front() {
+		if depths.contains_key(file) {
+			continue;
+		}
+		depths.insert(file, depth);
+		if let Some(analysis) = analyses.get(file) {
+			for dep in analysis.leaf_deps.iter().chain(analysis.deep_deps.iter()) {
+				queue.push_back((dep, depth + 1));
+			}
+		}
+	}
+	// Files passed with --ext-str-file and the like are loaded directly.
+	let str_depth = if options.str_files.is_empty() { 0 } else { 1 };
+	depths.into_values().max().unwrap_or(0).max(str_depth)
+}
+
+// Format a summary of the root's deps, along with the full lists if verbose is set.
+fn format_table(
+	options: &Options,
+	root: &Root,
+	deps: &HashSet<PathBuf>,
+	analyses: &[(PathBuf, Arc<Analysis>)],
+	unresolved: &BTreeSet<&PathBuf>,
+) -> Result<String, String> {
+	let warnings: Vec<String> = unresolved.iter().map(|path| format!("{} was not found", shown(options, path).display())).collect();
+	let size = stats::total_size(&mut HashMap::new(), deps)?;
+	let mut lines = vec![shown(options, &root.path).display().to_string()];
+	for (label, value) in [
+		("files", deps.len().to_string()),
+		("depth", depth(options, root, analyses).to_string()),
+		("size", stats::format_size(size)),
+		("warnings", warnings.len().to_string()),
+	] {
+		lines.push(format!("\t{:<10}{}", label, value));
+	}
+	if options.verbose {
+		let deps: BTreeSet<_> = deps.iter().collect();
+		lines.push("\tdeps:".into());
+		lines.extend(deps.iter().map(|dep| format!("\t\t{}", shown(options, dep).display())));
+		if !warnings.is_empty() {
+			lines.push("\twarnings:".into());
+			lines.extend(warnings.iter().map(|warning| format!("\t\t{}", warning)));
+		}
+	}
+	Ok(lines.join("\n"))
+}
+
+fn chains_value(chains: &[Vec<PathBuf>]) -> Value {
+	Value::Array(chains.iter().map(|chain| paths_value(chain.iter())).collect())
+}
+
+// Format the deps of all roots together, given each dep and the roots which need it.
+// For make format this is one file per line, so it may be multiple lines or none at all.
+// With --group-by-lock, the roots all have the same jsonnetfile.lock.json.
+pub fn format_union(options: &Options, cache: &Cache, roots: &[&Root], needed_by: &BTreeMap<PathBuf, Vec<&Path>>) -> Result<Vec<u8>, String> {
+	Ok(match options.format {
+		Format::Nix => nix::list(options, needed_by.keys(), &BTreeSet::new())?,
+		Format::Print0 => return Ok(print0_list(options, None, needed_by.keys())),
+		Format::Make | Format::Table | Format::NinjaDepfile | Format::Bazel | Format::Tup | Format::Csv | Format::Sarif | Format::Template | Format::JsonTree | Format::Tree | Format::Diagram(_) => {
+			let as_str: Vec<_> = needed_by.keys().map(|p| shown(options, p).to_string_lossy().into_owned()).collect();
+			as_str.join("\n")
+		},
+		Format::Json | Format::Jsonl | Format::Yaml => {
+			let mut items = vec![
+				("roots".into(), shown_values(options, roots.iter().map(|root| &root.path).collect::<BTreeSet<_>>().into_iter())),
+				("deps".into(), shown_values(options, needed_by.keys())),
+				("needed_by".into(), Value::Object(needed_by.iter().map(|(dep, roots)| {
+					let roots: BTreeSet<_> = roots.iter().collect();
+					(shown(options, dep).to_string_lossy().into_owned(), Value::Array(roots.into_iter().map(|root| shown_value(options, root)).collect()))
+				}).collect())),
+			];
+			if let (true, Some(root)) = (options.group_by_lock, roots.first()) {
+				items.extend(lock_items(options, cache, &root.path, needed_by.keys())?);
+			}
+			options.format.render(&Value::Object(items))
+		},
+	}.into_bytes())
+}
+
+// The deps which belong to a different project to the one with the given lock file, judging by their own
+// nearest jsonnetfile.lock.json. Deps outside of any project, eg. shared libraries, are fine.
+pub fn cross_lock<'a>(options: &Options, cache: &Cache, lock: Option<&Path>, deps: impl Iterator<Item = &'a PathBuf>) -> Result<Vec<&'a PathBuf>, String> {
+	let mut cross = Vec::new();
+	for dep in deps {
+		if let Some(dep_lock) = cache.lock_for(options, dep)? {
+			if Some(dep_lock.as_path()) != lock {
+				cross.push(dep);
+			}
+		}
+	}
+	cross.sort();
+	Ok(cross)
+}
+
+// The "lock" and "cross_lock" fields for the root's json output.
+fn lock_items<'a>(options: &Options, cache: &Cache, root: &Path, deps: impl Iterator<Item = &'a PathBuf>) -> Result<Vec<(String, Value)>, String> {
+	let lock = cache.lock_for(options, root)?;
+	let cross = cross_lock(options, cache, lock.as_deref(), deps)?;
+	Ok(vec![
+		("lock".into(), lock.as_deref().map_or(Value::Null, |lock| shown_value(options, lock))),
+		("cross_lock".into(), shown_values(options, cross.into_iter())),
+	])
+}
+
+// Format the files which depend on a target as a single line of output.
+// Chains are only included in json format, and only if any were requested.
+pub fn format_rdeps(options: &Options, target: &Path, rdeps: &BTreeSet<PathBuf>, chains: Option<&[Vec<PathBuf>]>) -> Vec<u8> {
+	match options.format {
+		Format::Print0 => return print0_list(options, Some(target), rdeps.iter()),
+		Format::Make | Format::Table | Format::NinjaDepfile | Format::Bazel | Format::Tup | Format::Nix | Format::Csv | Format::Sarif | Format::Template | Format::JsonTree | Format::Tree | Format::Diagram(_) => {
+			let as_str: Vec<_> = rdeps.iter().map(|p| p.to_string_lossy()).collect();
+			format!("{}: {}", target.display(), as_str.join(" "))
+		},
+		Format::Json | Format::Jsonl | Format::Yaml => {
+			let mut items = vec![
+				("target".into(), path_value(target)),
+				("rdeps".into(), paths_value(rdeps.iter())),
+			];
+			if let Some(chains) = chains {
+				items.push(("chains".into(), chains_value(chains)));
+			}
+			options.format.render(&Value::Object(items))
+		},
+	}.into_bytes()
+}
+
+// Format the files affected by a change to any of the changed files.
+// For make format this is one file per line, so it may be multiple lines or none at all.
+pub fn format_affected(options: &Options, changed: &[&Path], affected: &BTreeSet<PathBuf>, chains: Option<&[Vec<PathBuf>]>) -> Vec<u8> {
+	match options.format {
+		Format::Print0 => return print0_list(options, None, affected.iter()),
+		Format::Make | Format::Table | Format::NinjaDepfile | Format::Bazel | Format::Tup | Format::Nix | Format::Csv | Format::Sarif | Format::Template | Format::JsonTree | Format::Tree | Format::Diagram(_) => {
+			let as_str: Vec<_> = affected.iter().map(|p| p.to_string_lossy()).collect();
+			as_str.join("\n")
+		},
+		Format::Json | Format::Jsonl | Format::Yaml => {
+			let mut items = vec![
+				("changed".into(), Value::Array(changed.iter().map(|path| path_value(path)).collect())),
+				("affected".into(), paths_value(affected.iter())),
+			];
+			if let Some(chains) = chains {
+				items.push(("chains".into(), chains_value(chains)));
+			}
+			options.format.render(&Value::Object(items))
+		},
+	}.into_bytes()
+}
+
+// A notification for one owner of files affected by a change, as JSON whatever the output format, with a
+// summary in "text" so that it can be posted to a Slack webhook as is. The owner is None for files without one.
+pub fn format_notification(changed: &[&Path], owner: Option<&str>, affected: &[&PathBuf]) -> String {
+	let files = match affected.len() {
+		1 => "1 file".to_string(),
+		n => format!("{} files", n),
+	};
+	let owned = match owner {
+		Some(owner) => format!("owned by {}", owner),
+		None => "with no owner".into(),
+	};
+	let changed_str: Vec<_> = changed.iter().map(|path| path.to_string_lossy()).collect();
+	Value::Object(vec![
+		("owner".into(), owner.map_or(Value::Null, |owner| Value::String(owner.into()))),
+		("text".into(), Value::String(format!("Changes to {} affect {} {}", changed_str.join(", "), files, owned))),
+		("changed".into(), Value::Array(changed.iter().map(|path| path_value(path)).collect())),
+		("affected".into(), paths_value(affected.iter().copied())),
+	]).to_string()
+}
+
+// Quote a CSV field if needed, as per RFC 4180.
+fn csv_field(field: &str) -> String {
+	if field.contains([',', '"', '\n', '\r']) {
+		format!("\"{}\"", field.replace('"', "\"\""))
+	} else {
+		field.to_string()
+	}
+}
+
+// Format the import graph. For make format this is one line per file, listing the files it imports.
+// The hashes and IDs are only included in json format.
+pub fn format_graph(options: &Options, graph: &Graph, hashes: &BTreeMap<PathBuf, String>, ids: &BTreeMap<PathBuf, String>) -> String {
+	match options.format {
+		Format::Diagram(diagram) => diagram.render(graph),
+		Format::Csv => {
+			// Each edge once per kind of import behind it, or with no kind for snapshots that predate recording imports.
+			let mut kinds: BTreeMap<(&Path, &Path), BTreeSet<&str>> = BTreeMap::new();
+			for import in &graph.imports {
+				kinds.entry((&import.from, &import.to)).or_default().insert(import.kind.name());
+			}
+			let mut lines = vec!["source,target,kind".to_string()];
+			for (from, deps) in &graph.edges {
+				for dep in deps {
+					let row = |kind: &str| [&*from.to_string_lossy(), &*dep.to_string_lossy(), kind].map(csv_field).join(",");
+					match kinds.get(&(from.as_path(), dep.as_path())) {
+						Some(kinds) => lines.extend(kinds.iter().map(|kind| row(kind))),
+						None => lines.push(row("")),
+					}
+				}
+			}
+			lines.join("\n")
+		},
+		Format::Make | Format::Table | Format::NinjaDepfile | Format::Bazel | Format::Tup | Format::Nix | Format::Print0 | Format::Sarif | Format::Template | Format::JsonTree | Format::Tree => {
+			let lines: Vec<_> = graph.edges.iter().map(|(from, deps)| {
+				let as_str: Vec<_> = deps.iter().map(|p| p.to_string_lossy()).collect();
+				format!("{}: {}", from.display(), as_str.join(" "))
+			}).collect();
+			lines.join("\n")
+		},
+		Format::Json | Format::Jsonl | Format::Yaml => options.format.render(&Value::Object(vec![
+			("nodes".into(), paths_value(graph.edges.keys())),
+			("edges".into(), Value::Array(graph.edges.iter().flat_map(|(from, deps)| {
+				deps.iter().map(move |dep| pair_value(from, dep))
+			}).collect())),
+			// Exactly where each edge comes from, so tools can point at or rewrite the import without parsing.
+			("imports".into(), Value::Array(graph.imports.iter().map(|import| Value::Object(vec![
+				("from".into(), path_value(&import.from)),
+				("to".into(), path_value(&import.to)),
+				("kind".into(), Value::String(import.kind.name().into())),
+				("path".into(), path_value(&import.path)),
+				("start".into(), Value::Number(import.span.start as f64)),
+				("end".into(), Value::Number(import.span.end as f64)),
+				("line".into(), Value::Number(import.span.line as f64)),
+			])).collect())),
+			// So that graph diff can tell when a file has moved.
+			("hashes".into(), Value::Object(hashes.iter().map(|(file, hash)| {
+				(file.to_string_lossy().into_owned(), Value::String(hash.clone()))
+			}).collect())),
+			// So that other systems can join snapshots taken in different places.
+			("ids".into(), Value::Object(ids.iter().map(|(file, id)| {
+				(file.to_string_lossy().into_owned(), Value::String(id.clone()))
+			}).collect())),
+		])),
+	}
+}
+
+// Format how a root's deps changed in one commit. For make format this is a line for the commit,
+// followed by a line for each dep added or removed, as for watch --diff-output.
+#[cfg(feature = "git")]
+pub fn format_history(options: &Options, change: &crate::history::Change) -> String {
+	match options.format {
+		Format::Json | Format::Jsonl | Format::Yaml => options.format.render(&Value::Object(vec![
+			("commit".into(), Value::String(change.commit.clone())),
+			("subject".into(), Value::String(change.subject.clone())),
+			("added".into(), paths_value(change.added.iter())),
+			("removed".into(), paths_value(change.removed.iter())),
+		])),
+		Format::Make | Format::Table | Format::NinjaDepfile | Format::Bazel | Format::Tup | Format::Nix | Format::Print0 | Format::Csv | Format::Sarif | Format::Template | Format::JsonTree | Format::Tree | Format::Diagram(_) => {
+			let mut lines = vec![format!("{} {}", change.commit, change.subject)];
+			lines.extend(change.removed.iter().map(|dep| format!("- {}", dep.display())));
+			lines.extend(change.added.iter().map(|dep| format!("+ {}", dep.display())));
+			lines.join("\n")
+		},
+	}
+}
+
+// Format what a --patch-depfiles run would do for one root. This is JSON whatever the output format,
+// as --format decides what the depfiles contain.
+pub fn format_plan(step: &crate::plan::Step) -> String {
+	Value::Object(vec![
+		("root".into(), path_value(&step.root)),
+		("depfile".into(), path_value(&step.depfile)),
+		("action".into(), Value::String(step.action.name().into())),
+		("added".into(), paths_value(step.added.iter())),
+		("removed".into(), paths_value(step.removed.iter())),
+		("triggered_by".into(), paths_value(step.triggered_by.iter())),
+	]).to_string()
+}
+
+// Format a health report. The numbers come first, and are all that's compared with health --against.
+pub fn format_health(report: &crate::health::Report) -> Value {
+	let number = |n: usize| Value::Number(n as f64);
+	Value::Object(vec![
+		("score".into(), Value::Number(report.score)),
+		("roots".into(), number(report.roots)),
+		("files".into(), number(report.files)),
+		("cycles".into(), number(report.cycles.len())),
+		("max_depth".into(), number(report.max_depth)),
+		("average_fan_in".into(), Value::Number(report.average_fan_in)),
+		("orphans".into(), number(report.orphans.len())),
+		("budget_violations".into(), number(report.violations.len())),
+		("cycle_files".into(), Value::Array(report.cycles.iter().map(|cycle| paths_value(cycle.iter())).collect())),
+		("orphan_files".into(), paths_value(report.orphans.iter())),
+		("violations".into(), Value::Array(report.violations.iter().map(|violation| Value::Object(vec![
+			("root".into(), path_value(&violation.root)),
+			("budget".into(), Value::String(violation.budget.into())),
+			("value".into(), number(violation.value)),
+			("limit".into(), number(violation.limit)),
+		])).collect())),
+	])
+}
+
+// Format one batch of roots from partition. For make format this is the roots on one line.
+pub fn format_batch(options: &Options, batch: &[&Path]) -> String {
+	match options.format {
+		Format::Json | Format::Jsonl | Format::Yaml => options.format.render(&Value::Array(batch.iter().map(|root| path_value(root)).collect())),
+		Format::Make | Format::Table | Format::NinjaDepfile | Format::Bazel | Format::Tup | Format::Nix | Format::Print0 | Format::Csv | Format::Sarif | Format::Template | Format::JsonTree | Format::Tree | Format::Diagram(_) => {
+			let as_str: Vec<_> = batch.iter().map(|root| root.to_string_lossy()).collect();
+			as_str.join(" ")
+		},
+	}
+}
+
+fn pair_value(a: &Path, b: &Path) -> Value {
+	Value::Array(vec![path_value(a), path_value(b)])
+}
+
+fn pairs_value(pairs: &[(PathBuf, PathBuf)]) -> Value {
+	Value::Array(pairs.iter().map(|(a, b)| pair_value(a, b)).collect())
+}
+
+// Format the differences between two graphs. For make format this is one line per change,
+// so it may be multiple lines or none at all.
+pub fn format_diff(format: Format, diff: &Diff) -> String {
+	match format {
+		Format::Make | Format::Table | Format::NinjaDepfile | Format::Bazel | Format::Tup | Format::Nix | Format::Print0 | Format::Csv | Format::Sarif | Format::Template | Format::JsonTree | Format::Tree | Format::Diagram(_) => {
+			let mut lines = Vec::new();
+			lines.extend(diff.moved.iter().map(|(from, to)| format!("moved {} -> {}", from.display(), to.display())));
+			lines.extend(diff.removed.iter().map(|file| format!("removed {}", file.display())));
+			lines.extend(diff.added.iter().map(|file| format!("added {}", file.display())));
+			lines.extend(diff.removed_edges.iter().map(|(from, to)| format!("removed import {} -> {}", from.display(), to.display())));
+			lines.extend(diff.added_edges.iter().map(|(from, to)| format!("added import {} -> {}", from.display(), to.display())));
+			lines.join("\n")
+		},
+		Format::Json | Format::Jsonl | Format::Yaml => format.render(&Value::Object(vec![
+			("moved".into(), pairs_value(&diff.moved)),
+			("removed".into(), paths_value(diff.removed.iter())),
+			("added".into(), paths_value(diff.added.iter())),
+			("removed_edges".into(), pairs_value(&diff.removed_edges)),
+			("added_edges".into(), pairs_value(&diff.added_edges)),
+		])),
+	}
+}
@@ -0,0 +1,83 @@
+// Splitting roots into batches that can be evaluated independently, so an orchestrator can render batches
+// in parallel while roots sharing deps are rendered together, and so share a cache rather than each
+// evaluating the same files again. Only dirty deps count: the changed files if given, or otherwise every dep.
+
+use crate::resolve::normalize;
+use crate::{resolve_deps, Cache, Deadline, Options, Root};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+// Find the representative of i's batch, flattening the chain to it as we go.
+fn find(parents: &mut [usize], i: usize) -> usize {
+	let mut root = i;
+	while parents[root] != root {
+		root = parents[root];
+	}
+	let mut i = i;
+	while parents[i] != root {
+		i = std::mem::replace(&mut parents[i], root);
+	}
+	root
+}
+
+// Order the roots in a batch so that any root which is a dep of another comes before it, and otherwise as given.
+fn order<'a>(batch: Vec<&'a Path>, deps: &HashMap<&Path, HashSet<PathBuf>>) -> Vec<&'a Path> {
+	let mut ordered = Vec::new();
+	let mut remaining = batch;
+	while !remaining.is_empty() {
+		let normalized: HashSet<PathBuf> = remaining.iter().map(|root| normalize(root)).collect();
+		// A root is ready once no other remaining root is in its deps. If they all depend on each other,
+		// there's no safe order, so take them as given.
+		let is_ready = |root: &&Path| !deps[root].iter().any(|dep| *dep != normalize(root) && normalized.contains(dep));
+		let (mut ready, rest): (Vec<&Path>, Vec<&Path>) = remaining.into_iter().partition(is_ready);
+		if ready.is_empty() {
+			ordered.extend(rest);
+			break;
+		}
+		ordered.append(&mut ready);
+		remaining = rest;
+	}
+	ordered
+}
+
+// The roots with any dirty deps, in batches such that no two batches share a dirty dep. Batches are in the order
+// of the first root in each, and unchanged roots are left out. Changed files are compared after normalizing.
+pub fn run<'a>(options: &Options, cache: &Cache, roots: &'a [Root], changed: Option<&[PathBuf]>) -> Result<Vec<Vec<&'a Path>>, String> {
+	let changed: Option<HashSet<PathBuf>> = changed.map(|changed| changed.iter().map(|path| normalize(path)).collect());
+	let mut deps: HashMap<&Path, HashSet<PathBuf>> = HashMap::new();
+	let mut dirty: Vec<&Path> = Vec::new();
+	// The first root found with each dirty dep, which every other root with it is joined to.
+	let mut owners: HashMap<PathBuf, usize> = HashMap::new();
+	let mut parents = Vec::new();
+	for root in roots {
+		let root_deps: HashSet<PathBuf> = resolve_deps(options, cache, Deadline::new(&root.path), &root.jpaths, &root.path)?.iter().map(|dep| normalize(dep)).collect();
+		let dirty_deps: Vec<&PathBuf> = root_deps.iter().filter(|dep| changed.as_ref().is_none_or(|changed| changed.contains(*dep))).collect();
+		if dirty_deps.is_empty() || deps.contains_key(root.path.as_path()) {
+			continue;
+		}
+		let i = dirty.len();
+		dirty.push(&root.path);
+		parents.push(i);
+		for dep in dirty_deps {
+			match owners.get(dep) {
+				Some(&owner) => {
+					let (a, b) = (find(&mut parents, owner), find(&mut parents, i));
+					parents[b] = a;
+				},
+				None => { owners.insert(dep.clone(), i); },
+			}
+		}
+		deps.insert(&root.path, root_deps);
+	}
+	let mut batches: Vec<Vec<&Path>> = Vec::new();
+	let mut batch_of: HashMap<usize, usize> = HashMap::new();
+	for (i, root) in dirty.into_iter().enumerate() {
+		let representative = find(&mut parents, i);
+		let batch = *batch_of.entry(representative).or_insert_with(|| {
+			batches.push(Vec::new());
+			batches.len() - 1
+		});
+		batches[batch].push(root);
+	}
+	Ok(batches.into_iter().map(|batch| order(batch, &deps)).collect())
+}
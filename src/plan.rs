@@ -0,0 +1,92 @@
+// Working out what a --patch-depfiles run would do, without writing anything, so the effect of a change
+// can be reviewed before CI re-renders everything it touches. For each root this is whether its depfile
+// would be created or updated, how its deps would change, and which changed files in its deps are why
+// it needs rebuilding.
+
+use crate::depfile::{self, Location};
+use crate::resolve::normalize;
+use crate::{output, resolve_deps, Cache, Deadline, Options, Root};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+pub enum Action {
+	Create,
+	Update,
+	Keep,
+}
+
+impl Action {
+	pub fn name(&self) -> &'static str {
+		match self {
+			Action::Create => "create",
+			Action::Update => "update",
+			Action::Keep => "keep",
+		}
+	}
+}
+
+// What would happen for one root.
+pub struct Step {
+	pub root: PathBuf,
+	pub depfile: PathBuf,
+	pub action: Action,
+	pub added: BTreeSet<PathBuf>,
+	pub removed: BTreeSet<PathBuf>,
+	pub triggered_by: BTreeSet<PathBuf>,
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+	std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+// The deps listed in a depfile, by their absolute, normalized path, so the same dep is the same however it's written,
+// eg. with --relative-to, along with how it was written. Paths in it are relative to base.
+fn listed_deps(contents: &str, base: &Path) -> BTreeMap<PathBuf, PathBuf> {
+	depfile::parse_deps(contents, |dep| base.join(dep).exists())
+		.into_iter().map(|dep| (normalize(&base.join(&dep)), dep)).collect()
+}
+
+// The step for each root that would have its depfile written or would need rebuilding, in the order given.
+// A root needs rebuilding if any of its deps, before or after, changed: that is, is one of changed if that's given,
+// or otherwise was modified after the root's depfile was last written.
+pub fn run(options: &Options, cache: &Cache, roots: &[Root], location: &Location, changed: Option<&[PathBuf]>) -> Result<Vec<Step>, String> {
+	let cwd = std::env::current_dir().map_err(|e| format!("Failed to get current directory: {}", e))?;
+	let base = options.relative_to.as_ref().map_or(cwd.as_path(), |(dir, _)| dir.as_path());
+	let changed: Option<HashSet<PathBuf>> = changed.map(|changed| changed.iter().map(|path| normalize(&cwd.join(path))).collect());
+	let mut steps = Vec::new();
+	for root in roots {
+		let deadline = Deadline::new(&root.path);
+		let deps = resolve_deps(options, cache, deadline, &root.jpaths, &root.path)?;
+		let path = depfile::path(location, &root.path)?;
+		let contents = output::format_depfile(options, cache, deadline, root, &deps)?;
+		let new = listed_deps(&contents, base);
+		let existing = depfile::read(&path)?;
+		let old = existing.as_deref().map(|existing| listed_deps(existing, base)).unwrap_or_default();
+		let action = match &existing {
+			None => Action::Create,
+			Some(existing) if *existing != contents => Action::Update,
+			Some(_) => Action::Keep,
+		};
+		let written = modified(&path);
+		// Where a dep is written differently before and after, it's shown as it will be.
+		let triggered_by: BTreeMap<&PathBuf, &PathBuf> = old.iter().chain(&new).filter(|(dep, _)| match (&changed, written) {
+			(Some(changed), _) => changed.contains(*dep),
+			(None, Some(written)) => modified(dep).is_some_and(|dep_modified| dep_modified > written),
+			// With no depfile, there's nothing to compare to, so everything is new rather than changed.
+			(None, None) => false,
+		}).collect();
+		if matches!(action, Action::Keep) && triggered_by.is_empty() {
+			continue;
+		}
+		steps.push(Step {
+			root: root.path.clone(),
+			depfile: path,
+			action,
+			added: new.iter().filter(|(dep, _)| !old.contains_key(*dep)).map(|(_, shown)| shown.clone()).collect(),
+			removed: old.iter().filter(|(dep, _)| !new.contains_key(*dep)).map(|(_, shown)| shown.clone()).collect(),
+			triggered_by: triggered_by.into_values().cloned().collect(),
+		});
+	}
+	Ok(steps)
+}
@@ -0,0 +1,154 @@
+// Shrinking a root that shows a problem down to a minimal example which still shows it. The root and its deps
+// are copied to a directory, laid out as for repro, and then pieces are removed one at a time, keeping each
+// removal only if the check command still fails: first whole files, then the contents of importstr files,
+// then expressions in each jsonnet file, which are replaced with null, biggest first.
+
+use crate::repro::Layout;
+use crate::{closure_analyses, scan, Cache, Deadline, Options, Root};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+pub struct Settings {
+	// Run with sh -c, from the copy of the current directory. The problem is present if it fails.
+	pub check: String,
+	// Where to put the copy, which must not already exist.
+	pub output: PathBuf,
+}
+
+struct Checker<'a> {
+	command: &'a str,
+	cwd: &'a Path,
+	runs: usize,
+}
+
+impl Checker<'_> {
+	// Whether the problem is still present.
+	fn fails(&mut self) -> Result<bool, String> {
+		self.runs += 1;
+		let status = Command::new("sh")
+			.arg("-c")
+			.arg(self.command)
+			.current_dir(self.cwd)
+			.stdin(Stdio::null())
+			.stdout(Stdio::null())
+			.stderr(Stdio::null())
+			.status()
+			.map_err(|e| format!("Failed to run check {:?}: {}", self.command, e))?;
+		Ok(!status.success())
+	}
+}
+
+fn read(path: &Path) -> Result<String, String> {
+	std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))
+}
+
+fn read_bytes(path: &Path) -> Result<Vec<u8>, String> {
+	std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))
+}
+
+fn write(path: &Path, contents: impl AsRef<[u8]>) -> Result<(), String> {
+	std::fs::write(path, contents).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+// Try to replace one expression in the file with null, returning whether one was.
+fn reduce_expression(checker: &mut Checker, path: &Path) -> Result<bool, String> {
+	let contents = read(path)?;
+	// A file that doesn't parse may be the problem, so it's left as it is.
+	let Ok(mut spans) = scan::parse_expression_spans(path, &contents) else {
+		return Ok(false);
+	};
+	spans.sort_by_key(|span| std::cmp::Reverse(span.end - span.start));
+	for span in spans {
+		if span.end - span.start <= "null".len() {
+			break;
+		}
+		let reduced = format!("{}null{}", &contents[..span.start], &contents[span.end..]);
+		write(path, &reduced)?;
+		if checker.fails()? {
+			return Ok(true);
+		}
+	}
+	write(path, &contents)?;
+	Ok(false)
+}
+
+// Copy the root and its deps to the output directory and reduce them. Returns the reduced root,
+// and how many files are left.
+pub fn run(options: &Options, cache: &Cache, root: &Root, settings: &Settings) -> Result<(PathBuf, usize), String> {
+	if settings.output.exists() {
+		return Err(format!("{} already exists", settings.output.display()));
+	}
+	let mut code = BTreeSet::new();
+	let mut leaf = BTreeSet::new();
+	for (file, analysis) in closure_analyses(options, cache, Deadline::new(&root.path), &root.jpaths, &root.path)? {
+		leaf.extend(analysis.leaf_deps.iter().cloned());
+		code.insert(file);
+	}
+	leaf.extend(options.str_files.iter().cloned());
+	leaf.retain(|file| !code.contains(file) && file.exists());
+	let jpaths = root.jpaths.iter().map(|path| path.as_path());
+	let layout = Layout::new(code.iter().chain(leaf.iter()).map(|path| path.as_path()).chain(jpaths).chain(options.config.paths()), false);
+	let copy = |file: &Path| settings.output.join(layout.place(file));
+	for file in code.iter().chain(leaf.iter()) {
+		let path = copy(file);
+		std::fs::create_dir_all(path.parent().unwrap()).map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+		std::fs::copy(file, &path).map_err(|e| format!("Failed to copy {} to {}: {}", file.display(), path.display(), e))?;
+	}
+
+	let cwd = settings.output.join(layout.cwd());
+	std::fs::create_dir_all(&cwd).map_err(|e| format!("Failed to create {}: {}", cwd.display(), e))?;
+	let mut checker = Checker { command: &settings.check, cwd: &cwd, runs: 0 };
+	if !checker.fails()? {
+		return Err(format!("The check passes before reducing anything, so there's nothing to reduce. The copy is in {}", settings.output.display()));
+	}
+
+	// The root stays, so there's something left to check. Removing expressions can leave files unused,
+	// so keep going round until nothing more can be removed.
+	let root_copy = copy(&root.path);
+	let mut remaining: Vec<PathBuf> = code.iter().chain(leaf.iter()).map(|file| copy(file)).collect();
+	let leaf: Vec<PathBuf> = leaf.iter().map(|file| copy(file)).collect();
+	loop {
+		let mut reduced = false;
+		let mut i = 0;
+		while i < remaining.len() {
+			let path = remaining[i].clone();
+			if path == root_copy {
+				i += 1;
+				continue;
+			}
+			let contents = read_bytes(&path)?;
+			std::fs::remove_file(&path).map_err(|e| format!("Failed to remove {}: {}", path.display(), e))?;
+			if checker.fails()? {
+				remaining.remove(i);
+				reduced = true;
+			} else {
+				write(&path, contents)?;
+				i += 1;
+			}
+		}
+		for path in &remaining {
+			if leaf.contains(path) {
+				// These needn't be UTF-8.
+				let contents = read_bytes(path)?;
+				if !contents.is_empty() {
+					write(path, "")?;
+					if checker.fails()? {
+						reduced = true;
+					} else {
+						write(path, &contents)?;
+					}
+				}
+			} else {
+				while reduce_expression(&mut checker, path)? {
+					reduced = true;
+				}
+			}
+		}
+		if !reduced {
+			break;
+		}
+	}
+	eprintln!("Ran the check {} times", checker.runs);
+	Ok((root_copy, remaining.len()))
+}
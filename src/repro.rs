@@ -0,0 +1,214 @@
+// Packaging up the files needed to reproduce an analysis as a tarball, for bug reports: every file in the deps
+// of the roots, plus the config file. By default jsonnet files are shrunk to just their imports, and other
+// files are emptied, as that's all that affects the deps. Everything goes under repro/ in the archive:
+//   repro/repro.json   how to run it, ie. which directory to run from and the arguments to give
+//   repro/config.json  the --config file, if there was one, with its paths adjusted to match
+// With --jsonnetfile-jpaths, each jsonnetfile.json used is included too, with just its local dependencies.
+//   repro/files/...    the files, in the same layout relative to each other as they were
+//   repro/root/...     any files with absolute paths
+
+use crate::graph::redact_path;
+use crate::json::Value;
+use crate::resolve::{normalize, relative};
+use crate::scan::{Import, Origin};
+use crate::{jsonnetfile, tar};
+use crate::{closure_analyses, Cache, Deadline, Options, Root};
+use std::collections::BTreeSet;
+use std::path::{Component, Path, PathBuf};
+
+pub struct Settings {
+	pub output: PathBuf,
+	// Keep only the imports of each file, rather than the whole file.
+	pub shrink: bool,
+	// Replace every name in every path with a hash of it.
+	pub redact: bool,
+}
+
+// Where each file goes in the archive (or other copy of the files). Relative paths may start with "..", so the current directory is nested
+// under enough levels of "_" for all of them to stay inside repro/files.
+pub struct Layout {
+	pub depth: usize,
+	redact: bool,
+}
+
+// How many ".." a normalized relative path starts with, and the rest of it.
+fn split_parents(path: &Path) -> (usize, PathBuf) {
+	let parents = path.components().take_while(|c| *c == Component::ParentDir).count();
+	(parents, path.components().skip(parents).collect())
+}
+
+impl Layout {
+	pub fn new<'a>(paths: impl Iterator<Item = &'a Path>, redact: bool) -> Layout {
+		let depth = paths.map(|path| split_parents(&normalize(path)).0).max().unwrap_or(0);
+		Layout { depth, redact }
+	}
+
+	fn name(&self, rest: &Path) -> PathBuf {
+		if self.redact { redact_path(rest) } else { rest.to_owned() }
+	}
+
+	// The path, as seen from the current directory of the reproduction.
+	pub fn relative(&self, path: &Path) -> PathBuf {
+		let path = normalize(path);
+		if path.has_root() {
+			let rest: PathBuf = path.components().filter(|c| !matches!(c, Component::RootDir | Component::Prefix(_))).collect();
+			let mut relative: PathBuf = std::iter::repeat_n("..", self.depth + 1).collect();
+			relative.push("root");
+			return relative.join(self.name(&rest));
+		}
+		let (parents, rest) = split_parents(&path);
+		let relative: PathBuf = std::iter::repeat_n("..", parents).collect();
+		relative.join(self.name(&rest))
+	}
+
+	// The current directory of the reproduction, relative to repro/ (or wherever the files are copied to).
+	pub fn cwd(&self) -> PathBuf {
+		let mut cwd = PathBuf::from("files");
+		cwd.extend(std::iter::repeat_n("_", self.depth));
+		cwd
+	}
+
+	// Where the file goes, relative to repro/ (likewise).
+	pub fn place(&self, path: &Path) -> PathBuf {
+		normalize(&self.cwd().join(self.relative(path)))
+	}
+}
+
+// Quote the import path as a jsonnet string. JSON strings are valid jsonnet.
+fn import_expr(import: &Import, path: &Path) -> String {
+	format!("{} {}", import.kind.name(), Value::String(path.to_string_lossy().into_owned()))
+}
+
+// A stand-in for the import which keeps where it appears, and so whether it's conditional.
+fn shrink_import(import: &Import, path: &Path) -> String {
+	let import_expr = import_expr(import, path);
+	let expr = match import.origin {
+		Origin::Plain => import_expr,
+		Origin::IfBranch => format!("if true then {} else null", import_expr),
+		Origin::FunctionBody => format!("function() {}", import_expr),
+		Origin::ParamDefault => format!("function(x={}) x", import_expr),
+		Origin::ObjCompKey => format!("{{ [{}]: null for x in [] }}", import_expr),
+		Origin::ObjCompValue => format!("{{ [x]: {} for x in [] }}", import_expr),
+		Origin::ArrCompValue => format!("[{} for x in []]", import_expr),
+		Origin::Assert => format!("assert {}; null", import_expr),
+		Origin::AssertMessage => format!("assert true : {}; null", import_expr),
+	};
+	// Something further out than its origin can make an import conditional, eg. an assert inside a function.
+	if import.conditional && !import.origin.is_conditional() {
+		format!("function() {}", expr)
+	} else {
+		expr
+	}
+}
+
+fn shrink(imports: &[Import], redact: bool) -> String {
+	let mut lines = vec!["// Shrunk by jsonnet_dep_graph repro to just its imports.".to_string(), "[".into()];
+	for import in imports {
+		let path = if redact { redact_path(&import.path) } else { import.path.clone() };
+		lines.push(format!("\t{},", shrink_import(import, &path)));
+	}
+	lines.push("]\n".into());
+	lines.join("\n")
+}
+
+fn read(path: &Path) -> Result<Vec<u8>, String> {
+	std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))
+}
+
+// Write the archive for the roots, returning how many files it contains.
+pub fn create(options: &Options, cache: &Cache, roots: &[Root], settings: &Settings) -> Result<usize, String> {
+	// Jsonnet files, then the files they importstr, which are only needed if they exist.
+	let mut code = BTreeSet::new();
+	let mut leaf = BTreeSet::new();
+	for root in roots {
+		for (file, analysis) in closure_analyses(options, cache, Deadline::new(&root.path), &root.jpaths, &root.path)? {
+			leaf.extend(analysis.leaf_deps.iter().filter(|dep| !analysis.unresolved.contains(dep)).cloned());
+			code.insert(file);
+		}
+	}
+	leaf.extend(options.str_files.iter().cloned());
+	leaf.retain(|file| !code.contains(file) && file.exists());
+
+	let jpaths: Vec<&Path> = roots.iter().flat_map(|root| root.jpaths.iter()).map(|path| path.as_path()).collect();
+	// Only those seen while finding the deps, which are all that can have been used.
+	let jsonnetfiles = match options.jsonnetfile_jpaths {
+		true => cache.jsonnetfiles(),
+		false => Vec::new(),
+	};
+	let jsonnetfile_paths = jsonnetfiles.iter().flat_map(|(dir, local_paths)| std::iter::once(dir).chain(local_paths.iter()));
+	let layout = Layout::new(
+		code.iter().chain(leaf.iter()).map(|path| path.as_path()).chain(jpaths.iter().copied()).chain(options.config.paths())
+			.chain(jsonnetfile_paths.map(|path| path.as_path())),
+		settings.redact,
+	);
+	let base = Path::new("repro");
+	let mut archive = tar::Archive::default();
+	for file in &code {
+		let contents = if settings.shrink {
+			shrink(&cache.parsed(options, file)?, settings.redact).into_bytes()
+		} else {
+			read(file)?
+		};
+		archive.add(&base.join(layout.place(file)), &contents)?;
+	}
+	for file in &leaf {
+		let contents = if settings.shrink { Vec::new() } else { read(file)? };
+		archive.add(&base.join(layout.place(file)), &contents)?;
+	}
+	for (dir, local_paths) in &jsonnetfiles {
+		let place = layout.place(dir);
+		let dependencies = local_paths.iter().map(|local_path| {
+			// Both are relative to repro/, so there's always a way from one to the other.
+			let directory = relative(&place, &layout.place(local_path)).expect("both are relative to repro/");
+			Value::Object(vec![("source".into(), Value::Object(vec![("local".into(), Value::Object(vec![
+				("directory".into(), Value::String(directory.to_string_lossy().into_owned())),
+			]))]))])
+		}).collect();
+		let contents = Value::Object(vec![("dependencies".into(), Value::Array(dependencies))]);
+		archive.add(&base.join(place).join(jsonnetfile::FILENAME), format!("{}\n", contents).as_bytes())?;
+	}
+
+	let string = |path: &Path| Value::String(path.to_string_lossy().into_owned());
+	let mut args = Vec::new();
+	for root in roots {
+		let jpaths: Vec<_> = root.jpaths.iter().map(|jpath| layout.relative(jpath).to_string_lossy().into_owned()).collect();
+		args.push("--root".to_string());
+		args.push(format!("{}:jpath={}", layout.relative(&root.path).display(), jpaths.join(",")));
+	}
+	// The variable names don't matter, only that the files are evaluated alongside each root.
+	for (i, file) in options.code_files.iter().enumerate() {
+		args.push("--ext-code-file".into());
+		args.push(format!("code{}={}", i, layout.relative(file).display()));
+	}
+	for (i, file) in options.str_files.iter().enumerate() {
+		args.push("--ext-str-file".into());
+		args.push(format!("str{}={}", i, layout.relative(file).display()));
+	}
+	if !options.fs_checks {
+		args.push("--no-fs-checks".into());
+	}
+	if options.jsonnetfile_jpaths {
+		args.push("--jsonnetfile-jpaths".into());
+	}
+	if let Some(config) = options.config.to_file_json(|path| layout.place(path)) {
+		archive.add(&base.join("config.json"), format!("{}\n", config).as_bytes())?;
+		args.push("--config".into());
+		let mut path: PathBuf = std::iter::repeat_n("..", layout.depth + 1).collect();
+		path.push("config.json");
+		args.push(path.to_string_lossy().into_owned());
+	}
+	let manifest = Value::Object(vec![
+		("version".into(), Value::String(env!("CARGO_PKG_VERSION").into())),
+		("cwd".into(), string(&layout.cwd())),
+		("args".into(), Value::Array(args.into_iter().map(Value::String).collect())),
+		("shrunk".into(), Value::Bool(settings.shrink)),
+		("redacted".into(), Value::Bool(settings.redact)),
+	]);
+	archive.add(&base.join("repro.json"), format!("{}\n", manifest).as_bytes())?;
+
+	let data = archive.finish();
+	let is_tar = settings.output.extension().is_some_and(|extension| extension == "tar");
+	let data = if is_tar { data } else { tar::gzip(&data) };
+	std::fs::write(&settings.output, data).map_err(|e| format!("Failed to write {}: {}", settings.output.display(), e))?;
+	Ok(code.len() + leaf.len() + jsonnetfiles.len())
+}
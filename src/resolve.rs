@@ -0,0 +1,159 @@
+use std::io::ErrorKind;
+use std::path::{Component, Path, PathBuf};
+
+// How paths are written once resolved, with --normalize-paths. Paths are compared as they're written, so this decides
+// whether the same file reached two ways, eg. as lib/../a.libsonnet and a.libsonnet, or through a symlinked library
+// path and directly, is counted once or twice.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Normalization {
+	// As found, ie. the import joined onto the directory it was found in.
+	Verbatim,
+	// With normalize, so any path to the same place is the same, unless it goes through a symlink.
+	Lexical,
+	// Absolute, with every symlink resolved, so a file is always the same path however it's reached.
+	Canonical,
+}
+
+impl Normalization {
+	pub fn name(&self) -> &'static str {
+		match self {
+			Normalization::Verbatim => "verbatim",
+			Normalization::Lexical => "lexical-normalize",
+			Normalization::Canonical => "canonicalize",
+		}
+	}
+
+	pub fn parse(name: &str) -> Result<Normalization, String> {
+		[Normalization::Verbatim, Normalization::Lexical, Normalization::Canonical].into_iter()
+			.find(|normalization| normalization.name() == name)
+			.ok_or_else(|| format!("Unknown path normalization {:?}, expected one of: verbatim, lexical-normalize, canonicalize", name))
+	}
+
+	pub fn apply(&self, path: PathBuf, fs: &crate::fs::Budget) -> Result<PathBuf, String> {
+		match self {
+			Normalization::Verbatim => Ok(path),
+			Normalization::Lexical => Ok(normalize(&path)),
+			Normalization::Canonical => canonicalize(&path, fs),
+		}
+	}
+}
+
+// The path with every symlink resolved. A file which doesn't exist, eg. an unresolved import, has the nearest
+// directory above it that does exist resolved instead, with the rest of the path normalized onto that.
+fn canonicalize(path: &Path, fs: &crate::fs::Budget) -> Result<PathBuf, String> {
+	let absolute = std::path::absolute(path).map_err(|e| format!("Failed to make {} absolute: {}", path.display(), e))?;
+	let mut existing = absolute.as_path();
+	let mut rest = Vec::new();
+	loop {
+		match fs.canonicalize(existing) {
+			Ok(canonical) => return Ok(normalize(&canonical.join(rest.into_iter().rev().collect::<PathBuf>()))),
+			Err(e) if e.kind() == ErrorKind::NotFound => match (existing.parent(), existing.file_name()) {
+				(Some(parent), Some(name)) => {
+					rest.push(name);
+					existing = parent;
+				},
+				_ => return Ok(normalize(&absolute)),
+			},
+			Err(e) => return Err(format!("Failed to canonicalize {}: {}", path.display(), e)),
+		}
+	}
+}
+
+pub struct Resolver<'a> {
+	pub base_dir: &'a Path,
+	pub jpaths: &'a [&'a Path],
+	// If false, never check which candidate exists and always use the local case.
+	pub fs_checks: bool,
+	// Limits on filesystem operations, used for existence checks.
+	pub fs: &'a crate::fs::Budget,
+	// How to write the path that's found.
+	pub normalization: Normalization,
+}
+
+impl<'a> Resolver<'a> {
+	pub fn resolve(&self, path: &Path) -> Result<PathBuf, String> {
+		Ok(self.resolve_checked(path)?.0)
+	}
+
+	// As resolve, but also returns whether the file wasn't found anywhere and so the local case was assumed.
+	pub fn resolve_checked(&self, path: &Path) -> Result<(PathBuf, bool), String> {
+		let (found, unresolved) = self.find(path)?;
+		Ok((self.normalization.apply(found, self.fs)?, unresolved))
+	}
+
+	fn find(&self, path: &Path) -> Result<(PathBuf, bool), String> {
+		// If path is absolute, no need to check anything either as the prefix doesn't matter.
+		if path.is_absolute() {
+			return Ok((path.to_owned(), false));
+		}
+		// If no jpaths set, this is a no-op and doesn't need to check for existence.
+		if self.jpaths.is_empty() {
+			return Ok((self.base_dir.join(path), false));
+		}
+		// If we've been told not to check, go by how the import is written instead.
+		// Something explicitly relative ("./foo", "../foo") or a bare file name is taken to be
+		// next to the importing file, anything else with a directory in it is in the first jpath.
+		if !self.fs_checks {
+			let local = matches!(path.components().next(), Some(Component::CurDir | Component::ParentDir))
+				|| path.parent().is_none_or(|parent| parent.as_os_str().is_empty());
+			let prefix = if local { self.base_dir } else { self.jpaths[0] };
+			return Ok((prefix.join(path), false));
+		}
+		// Find the first extant match.
+		// Fail if we can't determine existence for any candidate.
+		for prefix in std::iter::once(self.base_dir).chain(self.jpaths.iter().copied()) {
+			let candidate = prefix.join(path);
+			let exists = self.fs.exists(&candidate).map_err(|e|
+				format!("Could not check path {}: {}", path.display(), e)
+			)?;
+			if exists {
+				return Ok((candidate, false));
+			}
+		}
+		// None existed, fall back to the local case.
+		// This seems more useful than erroring.
+		// It will likely error later anyway, when we try to parse that file.
+		// However, this behaviour is useful if the subject is a leaf dep
+		// that is a generated file.
+		Ok((self.base_dir.join(path), true))
+	}
+}
+
+// Remove "." components, and ".." components along with the component before them.
+// This is purely lexical, so it may give the wrong answer if there are symlinks involved.
+pub fn normalize(path: &Path) -> PathBuf {
+	let mut parts: Vec<Component> = Vec::new();
+	for component in path.components() {
+		match component {
+			Component::CurDir => (),
+			Component::ParentDir => match parts.last() {
+				Some(Component::Normal(_)) => { parts.pop(); },
+				// ".." at the root is the root.
+				Some(Component::RootDir | Component::Prefix(_)) => (),
+				_ => parts.push(component),
+			},
+			_ => parts.push(component),
+		}
+	}
+	parts.iter().collect()
+}
+
+// The shortest relative path from dir to path, which should both be normalized.
+// None if there isn't one we can work out lexically, eg. dir is above the current directory.
+pub fn relative(dir: &Path, path: &Path) -> Option<PathBuf> {
+	if dir.has_root() != path.has_root() {
+		return None;
+	}
+	let dir: Vec<_> = dir.components().collect();
+	let path: Vec<_> = path.components().collect();
+	let common = dir.iter().zip(path.iter()).take_while(|(a, b)| a == b).count();
+	if dir[common..].contains(&Component::ParentDir) {
+		return None;
+	}
+	let mut result = PathBuf::new();
+	for _ in common..dir.len() {
+		result.push("..");
+	}
+	result.extend(&path[common..]);
+	Some(result)
+}
@@ -0,0 +1,55 @@
+// With --resource-summary, what a run used, printed to stderr once it's done (whether it succeeded or not),
+// so that CI runners can be sized from real numbers rather than guesswork.
+
+use crate::stats::format_size;
+use crate::{Cache, Options};
+use std::sync::atomic::Ordering;
+use std::time::Instant;
+
+// The largest the process has been resident in memory, in bytes. This is read from /proc to avoid needing libc,
+// so it's only known on Linux.
+fn peak_rss() -> Option<u64> {
+	let status = std::fs::read_to_string("/proc/self/status").ok()?;
+	// The line looks like "VmHWM:	   12345 kB"
+	let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+	let kb: u64 = line["VmHWM:".len()..].split_whitespace().next()?.parse().ok()?;
+	Some(kb * 1024)
+}
+
+// Prints the summary when dropped, so that it's printed however the run ends.
+pub struct Summary<'a> {
+	options: &'a Options,
+	cache: &'a Cache,
+	started: Instant,
+}
+
+impl Summary<'_> {
+	pub fn new<'a>(options: &'a Options, cache: &'a Cache) -> Summary<'a> {
+		Summary { options, cache, started: Instant::now() }
+	}
+}
+
+impl Drop for Summary<'_> {
+	fn drop(&mut self) {
+		let (reads, bytes_read, stats) = self.options.fs.usage();
+		let hits = self.cache.hits.load(Ordering::Relaxed);
+		let lookups = hits + self.cache.misses.load(Ordering::Relaxed);
+		let hit_ratio = match lookups {
+			0 => "none".to_string(),
+			_ => format!("{:.1}%", hits as f64 * 100.0 / lookups as f64),
+		};
+		eprintln!(
+			"Resource usage: {:.1}s, peak RSS {}, {} files parsed, {} read from {} files, {} stat calls, \
+			cache hit ratio {} ({} of {} lookups)",
+			self.started.elapsed().as_secs_f64(),
+			peak_rss().map_or("unknown".to_string(), format_size),
+			self.cache.parses.load(Ordering::Relaxed),
+			format_size(bytes_read),
+			reads,
+			stats,
+			hit_ratio,
+			hits,
+			lookups,
+		);
+	}
+}
@@ -0,0 +1,63 @@
+// Writing lint problems as a SARIF log (https://docs.oasis-open.org/sarif/sarif/v2.1.0/sarif-v2.1.0.html),
+// so that code scanning tools such as GitHub's can show them on the lines they're about.
+
+use crate::json::Value;
+use crate::lint::{Problem, RULES};
+use crate::resolve::normalize;
+use std::path::{Component, Path};
+
+fn text(s: &str) -> Value {
+	Value::Object(vec![("text".into(), Value::String(s.into()))])
+}
+
+// The path as a URI, with / between components and anything else not allowed in a path percent-encoded.
+// Relative paths stay relative, which code scanning takes as relative to the top of the repository.
+fn uri(path: &Path) -> String {
+	let path = normalize(path);
+	let parts: Vec<String> = path.components().filter_map(|component| match component {
+		Component::Normal(part) => Some(part),
+		Component::ParentDir => Some("..".as_ref()),
+		_ => None,
+	}).map(|part| {
+		part.to_string_lossy().bytes().map(|b| match b {
+			b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (b as char).to_string(),
+			b => format!("%{:02X}", b),
+		}).collect()
+	}).collect();
+	let prefix = if path.has_root() { "file:///" } else { "" };
+	format!("{}{}", prefix, parts.join("/"))
+}
+
+fn result(problem: &Problem) -> Value {
+	let location = Value::Object(vec![("physicalLocation".into(), Value::Object(vec![
+		("artifactLocation".into(), Value::Object(vec![("uri".into(), Value::String(uri(&problem.file)))])),
+		("region".into(), Value::Object(vec![("startLine".into(), Value::Number(problem.line as f64))])),
+	]))]);
+	Value::Object(vec![
+		("ruleId".into(), Value::String(problem.rule.into())),
+		("level".into(), Value::String("warning".into())),
+		("message".into(), text(&problem.message)),
+		("locations".into(), Value::Array(vec![location])),
+	])
+}
+
+// A log with a single run, containing a result for each problem.
+pub fn log<'a>(problems: impl Iterator<Item = &'a Problem>) -> Value {
+	let rules = RULES.iter().map(|(id, description)| Value::Object(vec![
+		("id".into(), Value::String(id.to_string())),
+		("shortDescription".into(), text(description)),
+	])).collect();
+	let driver = Value::Object(vec![
+		("name".into(), Value::String(env!("CARGO_PKG_NAME").into())),
+		("version".into(), Value::String(env!("CARGO_PKG_VERSION").into())),
+		("rules".into(), Value::Array(rules)),
+	]);
+	Value::Object(vec![
+		("$schema".into(), Value::String("https://json.schemastore.org/sarif-2.1.0.json".into())),
+		("version".into(), Value::String("2.1.0".into())),
+		("runs".into(), Value::Array(vec![Value::Object(vec![
+			("tool".into(), Value::Object(vec![("driver".into(), driver)])),
+			("results".into(), Value::Array(problems.map(result).collect())),
+		])])),
+	])
+}
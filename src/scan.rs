@@ -0,0 +1,587 @@
+use jrsonnet_parser::*;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Once;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ImportKind {
+	// A jsonnet file, which may itself have imports.
+	Import,
+	// A static file, which is included as a string and never examined further.
+	ImportStr,
+}
+
+impl ImportKind {
+	// The keyword used for this kind of import.
+	pub fn name(&self) -> &'static str {
+		match self {
+			ImportKind::Import => "import",
+			ImportKind::ImportStr => "importstr",
+		}
+	}
+
+	pub fn from_name(name: &str) -> Option<ImportKind> {
+		[ImportKind::Import, ImportKind::ImportStr].into_iter().find(|kind| kind.name() == name)
+	}
+}
+
+// The format of a file that is imported as a string only to be parsed as data, eg. `std.parseYaml(importstr "x.yaml")`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum DataFormat {
+	Json,
+	Yaml,
+}
+
+impl DataFormat {
+	pub fn name(&self) -> &'static str {
+		match self {
+			DataFormat::Json => "json",
+			DataFormat::Yaml => "yaml",
+		}
+	}
+
+	pub fn from_name(name: &str) -> Option<DataFormat> {
+		[DataFormat::Json, DataFormat::Yaml].into_iter().find(|format| format.name() == name)
+	}
+
+	// The format parsed by a std function, given the function's name.
+	fn from_std_function(name: &str) -> Option<DataFormat> {
+		match name {
+			"parseJson" => Some(DataFormat::Json),
+			"parseYaml" => Some(DataFormat::Yaml),
+			_ => None,
+		}
+	}
+}
+
+// Where in the AST an import was found. This is the innermost of these
+// that the import is inside.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Origin {
+	// None of the below.
+	Plain,
+	// The then or else branch of an if expression.
+	IfBranch,
+	// The body of a function or method.
+	FunctionBody,
+	// The default value of a function or method parameter.
+	// These are only evaluated if the function is called without that argument.
+	ParamDefault,
+	// The key of an object comprehension, evaluated once per item (possibly zero times).
+	ObjCompKey,
+	// The value of an object comprehension, evaluated once per item (possibly zero times).
+	ObjCompValue,
+	// The value of an array comprehension, evaluated once per item (possibly zero times).
+	ArrCompValue,
+	// The condition of an assert, either in an object or an expression.
+	Assert,
+	// The message of an assert, which is only evaluated if the assert fails.
+	AssertMessage,
+}
+
+impl Origin {
+	pub fn name(&self) -> &'static str {
+		match self {
+			Origin::Plain => "plain",
+			Origin::IfBranch => "if-branch",
+			Origin::FunctionBody => "function-body",
+			Origin::ParamDefault => "param-default",
+			Origin::ObjCompKey => "objcomp-key",
+			Origin::ObjCompValue => "objcomp-value",
+			Origin::ArrCompValue => "arrcomp-value",
+			Origin::Assert => "assert",
+			Origin::AssertMessage => "assert-message",
+		}
+	}
+
+	pub fn from_name(name: &str) -> Option<Origin> {
+		[
+			Origin::Plain, Origin::IfBranch, Origin::FunctionBody, Origin::ParamDefault, Origin::ObjCompKey,
+			Origin::ObjCompValue, Origin::ArrCompValue, Origin::Assert, Origin::AssertMessage,
+		].into_iter().find(|origin| origin.name() == name)
+	}
+
+	// Whether an import with this origin may not be evaluated, even if the expression
+	// containing it is.
+	pub fn is_conditional(&self) -> bool {
+		!matches!(self, Origin::Plain | Origin::Assert)
+	}
+}
+
+// What we know about the surroundings of the expression currently being scanned.
+#[derive(Clone, Copy)]
+struct Context {
+	// Whether the expression may not be evaluated, eg. because it is inside an if/else branch or a function.
+	conditional: bool,
+	origin: Origin,
+	// Whether the expression is in a branch of an if which we know is never taken. See constant.
+	disabled: bool,
+}
+
+impl Context {
+	// Returns the context for a sub-expression with the given origin.
+	fn enter(self, origin: Origin) -> Context {
+		let conditional = self.conditional || origin.is_conditional();
+		Context { conditional, origin, disabled: self.disabled }
+	}
+
+	// Returns the context for a sub-expression that may not be evaluated, but without an origin of its own,
+	// eg. a comprehension's if condition, which is evaluated once per item.
+	fn conditional(self) -> Context {
+		Context { conditional: true, ..self }
+	}
+}
+
+// A range of bytes in a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+	pub start: usize,
+	pub end: usize,
+	// The 1-indexed line number that the span starts on.
+	pub line: usize,
+}
+
+// An import as it appears in the source, before resolving it to a file.
+#[derive(Debug, Clone)]
+pub struct Import {
+	pub kind: ImportKind,
+	pub path: PathBuf,
+	// Whether the import is somewhere that may not be evaluated,
+	// ie. inside an if/else branch or a function.
+	pub conditional: bool,
+	pub origin: Origin,
+	// Where the import expression is in the file.
+	pub span: Span,
+	// If this is an importstr passed directly to a std function that parses it, the format it's parsed as.
+	pub data_format: Option<DataFormat>,
+}
+
+// A local binding whose value is an import, eg. `local foo = import "foo.libsonnet";`
+#[derive(Debug)]
+pub struct ImportLocal {
+	pub name: String,
+	// Where the local expression starts, ie. the offset of the `local` keyword.
+	pub start: usize,
+	// Where the import expression is.
+	pub import: Span,
+}
+
+// Parse a file and return all the imports it contains, in the order they appear.
+// Reading it is subject to the given limits on filesystem operations.
+// If ext_strs is given, imports in a branch of an if that can't be taken with those external variables are left out.
+pub fn parse_file(filepath: &Path, fs: &crate::fs::Budget, ext_strs: Option<&HashMap<String, String>>) -> Result<Vec<Import>, String> {
+	let contents = fs.read_to_string(filepath).map_err(|e|
+		format!("Failed to read {}: {}", filepath.display(), e)
+	)?;
+	parse_contents(filepath, &contents, ext_strs)
+}
+
+// As parse_file, but with the file's contents already read.
+pub fn parse_contents(filepath: &Path, contents: &str, ext_strs: Option<&HashMap<String, String>>) -> Result<Vec<Import>, String> {
+	Ok(scan_contents(filepath, contents, ext_strs, Spans::None)?.imports)
+}
+
+// Find every `local NAME = import PATH;` in the file, where that is the only binding in the local.
+pub fn parse_import_locals(filepath: &Path, contents: &str) -> Result<Vec<ImportLocal>, String> {
+	Ok(scan_contents(filepath, contents, None, Spans::None)?.locals)
+}
+
+// Where every expression in the file is, outermost first. Line numbers aren't filled in, as they'd take
+// a pass over the file for each one.
+pub fn parse_expression_spans(filepath: &Path, contents: &str) -> Result<Vec<Span>, String> {
+	Ok(scan_contents(filepath, contents, None, Spans::Exprs)?.exprs)
+}
+
+// Where every string literal in the file is, including its quotes, with line numbers.
+pub fn parse_string_spans(filepath: &Path, contents: &str) -> Result<Vec<Span>, String> {
+	Ok(scan_contents(filepath, contents, None, Spans::Strings)?.strings)
+}
+
+// Where the name of every object field which isn't computed is, including any quotes, with line numbers.
+pub fn parse_field_name_spans(filepath: &Path, contents: &str) -> Result<Vec<Span>, String> {
+	Ok(scan_contents(filepath, contents, None, Spans::Fields)?.fields)
+}
+
+// Which spans to collect while walking a file's AST, besides those of imports, which are always wanted.
+// Most runs need none of them, and there can be a great many.
+#[derive(Default, Clone, Copy, PartialEq, Eq)]
+enum Spans {
+	#[default]
+	None,
+	Exprs,
+	Strings,
+	Fields,
+}
+
+// Everything we find while walking a file's AST.
+#[derive(Default)]
+struct Scan<'a> {
+	// The external variables to work out if conditions with, if disabled imports should be left out.
+	ext_strs: Option<&'a HashMap<String, String>>,
+	spans: Spans,
+	imports: Vec<Import>,
+	locals: Vec<ImportLocal>,
+	exprs: Vec<Span>,
+	strings: Vec<Span>,
+	fields: Vec<Span>,
+	// Fixed field names, and where the member before them ended (or the object started). The parser doesn't
+	// say where the names themselves are, so they're found afterwards, after anything else in between.
+	field_names: Vec<(String, usize)>,
+}
+
+thread_local! {
+	// While parsing, where the parser panicked, if it has.
+	static PARSER_PANIC: RefCell<Option<Option<String>>> = const { RefCell::new(None) };
+}
+
+// The parser can panic on pathological input. One such file shouldn't take down a whole batch or --watch,
+// so it fails like any other file which can't be parsed, saying what the panic said and where.
+// The usual report of the panic, with a backtrace, is left out in favour of that.
+fn parse_isolated(filepath: &Path, contents: &str, settings: &ParserSettings) -> Result<Result<LocExpr, ParseError>, String> {
+	static HOOK: Once = Once::new();
+	HOOK.call_once(|| {
+		let default_hook = std::panic::take_hook();
+		std::panic::set_hook(Box::new(move |info| {
+			let parsing = PARSER_PANIC.with(|panic| match &mut *panic.borrow_mut() {
+				Some(location) => {
+					*location = info.location().map(|location| location.to_string());
+					true
+				},
+				None => false,
+			});
+			if !parsing {
+				default_hook(info);
+			}
+		}));
+	});
+	PARSER_PANIC.with(|panic| *panic.borrow_mut() = Some(None));
+	let parsed = std::panic::catch_unwind(|| parse(contents, settings));
+	let location = PARSER_PANIC.with(|panic| panic.borrow_mut().take()).flatten();
+	parsed.map_err(|panic| {
+		let message = panic.downcast_ref::<&str>().copied()
+			.or_else(|| panic.downcast_ref::<String>().map(|message| message.as_str()))
+			.unwrap_or("no message");
+		let location = location.map(|location| format!(" at {}", location)).unwrap_or_default();
+		format!("Internal parser panic on {}: {}{}", filepath.display(), message, location)
+	})
+}
+
+// The offset of the first thing at or after start which isn't whitespace, a comment or one of the given punctuation.
+fn skip_trivia(contents: &str, start: usize, punctuation: &[u8]) -> usize {
+	let bytes = contents.as_bytes();
+	let mut at = start;
+	while at < bytes.len() {
+		let rest = &bytes[at..];
+		at += if rest[0].is_ascii_whitespace() || punctuation.contains(&rest[0]) {
+			1
+		} else if rest[0] == b'#' || rest.starts_with(b"//") {
+			rest.iter().position(|&b| b == b'\n').unwrap_or(rest.len())
+		} else if rest.starts_with(b"/*") {
+			rest.windows(2).skip(2).position(|pair| pair == b"*/").map_or(rest.len(), |end| end + 4)
+		} else {
+			break;
+		};
+	}
+	at
+}
+
+// Where the field name is, if it's the next thing after the member before it. This includes its quotes, if it's
+// in a plain string without escapes. Names written any other way aren't found.
+fn field_name_span(contents: &str, name: &str, after: usize) -> Option<(usize, usize)> {
+	let start = skip_trivia(contents, after, b",{");
+	let rest = contents.get(start..)?;
+	let is_ident_char = |c: char| c.is_ascii_alphanumeric() || c == '_';
+	if rest.strip_prefix(name).is_some_and(|rest| !rest.starts_with(is_ident_char)) {
+		return Some((start, start + name.len()));
+	}
+	let quote = rest.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+	let quoted = rest[1..].strip_prefix(name)?.starts_with(quote);
+	quoted.then_some((start, start + name.len() + 2))
+}
+
+fn scan_contents<'a>(filepath: &Path, contents: &str, ext_strs: Option<&'a HashMap<String, String>>, spans: Spans) -> Result<Scan<'a>, String> {
+	let settings = ParserSettings {
+		loc_data: true,
+		file_name: filepath.to_owned().into(),
+	};
+
+	let ast = parse_isolated(filepath, contents, &settings)?.map_err(|e|
+		format!("Failed to parse {}: {}", filepath.display(), e)
+	)?;
+
+	let mut scan = Scan { ext_strs, spans, ..Scan::default() };
+	scan_ast(&mut scan, &ast, Context { conditional: false, origin: Origin::Plain, disabled: false });
+	// The parser only gives us byte offsets, so fill in line numbers now that we have all the imports,
+	// from where each line starts.
+	let line_starts: Vec<usize> = std::iter::once(0).chain(contents.match_indices('\n').map(|(i, _)| i + 1)).collect();
+	let line = |offset: usize| line_starts.partition_point(|&start| start <= offset);
+	for import in scan.imports.iter_mut() {
+		import.span.line = line(import.span.start);
+	}
+	for local in scan.locals.iter_mut() {
+		local.import.line = line(local.import.start);
+	}
+	for span in scan.strings.iter_mut() {
+		span.line = line(span.start);
+	}
+	for (name, after) in std::mem::take(&mut scan.field_names) {
+		if let Some((start, end)) = field_name_span(contents, &name, after) {
+			scan.fields.push(Span { start, end, line: line(start) });
+		}
+	}
+	Ok(scan)
+}
+
+fn span(expr: &LocExpr) -> Span {
+	// We always parse with loc_data set, so the location is present for anything that was in the source.
+	let ExprLocation(_, start, end) = expr.1.as_ref().unwrap();
+	Span { start: *start, end: *end, line: 0 }
+}
+
+fn scan_ast(out: &mut Scan, expr: &LocExpr, ctx: Context) {
+	// Some expressions the parser makes up have no location, eg. the string "b" in `a.b`.
+	if let (Spans::Exprs, Some(ExprLocation(_, start, end))) = (out.spans, expr.1.as_ref()) {
+		out.exprs.push(Span { start: *start, end: *end, line: 0 });
+	}
+	match &*expr.0 {
+		// Base cases: We found actual imports! Unless they can never be evaluated.
+		Expr::Import(_) | Expr::ImportStr(_) if ctx.disabled => (),
+		Expr::Import(path) => out.imports.push(Import { kind: ImportKind::Import, path: path.clone(), conditional: ctx.conditional, origin: ctx.origin, span: span(expr), data_format: None }),
+		Expr::ImportStr(path) => out.imports.push(Import { kind: ImportKind::ImportStr, path: path.clone(), conditional: ctx.conditional, origin: ctx.origin, span: span(expr), data_format: None }),
+		// Otherwise, recurse if needed
+		Expr::Arr(exprs) => for expr in exprs { scan_ast(out, expr, ctx) },
+		Expr::ArrComp(expr, compspecs) => {
+			scan_ast(out, expr, ctx.enter(Origin::ArrCompValue));
+			scan_compspecs(out, compspecs, ctx);
+		},
+		Expr::Obj(obj) => scan_obj(out, obj, expr.1.as_ref().map(|ExprLocation(_, start, _)| *start), ctx),
+		Expr::ObjExtend(expr, obj) => {
+			scan_ast(out, expr, ctx);
+			scan_obj(out, obj, expr.1.as_ref().map(|ExprLocation(_, _, end)| *end), ctx);
+		},
+		Expr::Parened(expr) => scan_ast(out, expr, ctx),
+		Expr::UnaryOp(_, expr) => scan_ast(out, expr, ctx),
+		Expr::BinaryOp(expr_a, _, expr_b) => {
+			scan_ast(out, expr_a, ctx);
+			scan_ast(out, expr_b, ctx);
+		},
+		Expr::AssertExpr(assert, expr) => {
+			scan_assert(out, assert, ctx);
+			scan_ast(out, expr, ctx);
+		},
+		Expr::LocalExpr(bindspecs, body) => {
+			if let [BindSpec { name, params: None, value }] = bindspecs.as_slice() {
+				if let Expr::Import(_) | Expr::ImportStr(_) = &*value.0 {
+					out.locals.push(ImportLocal { name: name.to_string(), start: span(expr).start, import: span(value) });
+				}
+			}
+			for bindspec in bindspecs {
+				scan_bindspec(out, bindspec, ctx);
+			}
+			scan_ast(out, body, ctx);
+		},
+		Expr::ErrorStmt(expr) => scan_ast(out, expr, ctx),
+		Expr::Apply(expr, args, _) => {
+			scan_ast(out, expr, ctx);
+			let data_format = std_function(expr).and_then(DataFormat::from_std_function);
+			for Arg(_, expr) in &args.0 {
+				scan_ast(out, expr, ctx);
+				// This only spots the import written inline, not one bound to a local first.
+				if let (Some(format), Expr::ImportStr(_), false) = (data_format, &*unparen(expr).0, ctx.disabled) {
+					out.imports.last_mut().unwrap().data_format = Some(format);
+				}
+			}
+		},
+		Expr::Index(expr_a, expr_b) => {
+			scan_ast(out, expr_a, ctx);
+			scan_ast(out, expr_b, ctx);
+		},
+		// Everything in a function only gets evaluated if the function is called.
+		Expr::Function(params, expr) => {
+			scan_params(out, params, ctx);
+			scan_ast(out, expr, ctx.enter(Origin::FunctionBody));
+		},
+		// Only one branch gets evaluated, though the condition always does.
+		Expr::IfElse{cond, cond_then, cond_else} => {
+			scan_ast(out, &cond.0, ctx);
+			let taken = out.ext_strs.and_then(|ext_strs| match constant(&cond.0, ext_strs) {
+				Some(Constant::Bool(taken)) => Some(taken),
+				_ => None,
+			});
+			let branch = |taken_if: bool| {
+				let ctx = ctx.enter(Origin::IfBranch);
+				Context { disabled: ctx.disabled || taken == Some(!taken_if), ..ctx }
+			};
+			scan_ast(out, cond_then, branch(true));
+			if let Some(expr) = cond_else {
+				scan_ast(out, expr, branch(false));
+			}
+		},
+		Expr::Slice(expr, SliceDesc{start, end, step}) => {
+			scan_ast(out, expr, ctx);
+			for expr in [start, end, step].into_iter().flatten() {
+				scan_ast(out, expr, ctx);
+			}
+		},
+		// Strings the parser makes up, like "b" in `a.b`, have no location.
+		Expr::Str(_) if expr.1.is_some() && out.spans == Spans::Strings => out.strings.push(span(expr)),
+		// Remaining cases are leaf nodes like other literals that we don't care about.
+		_ => (),
+	}
+}
+
+// A value that an if condition can be worked out to without evaluating the file.
+#[derive(PartialEq)]
+enum Constant {
+	Null,
+	Bool(bool),
+	Number(f64),
+	String(String),
+}
+
+// The value of the expression, if it's made only of literals, std.extVar of one of the given external variables,
+// and the !, &&, ||, == and != operators. Anything else, including a local bound to one of those, is unknown.
+fn constant(expr: &LocExpr, ext_strs: &HashMap<String, String>) -> Option<Constant> {
+	match &*expr.0 {
+		Expr::Literal(LiteralType::Null) => Some(Constant::Null),
+		Expr::Literal(LiteralType::True) => Some(Constant::Bool(true)),
+		Expr::Literal(LiteralType::False) => Some(Constant::Bool(false)),
+		Expr::Num(n) => Some(Constant::Number(*n)),
+		Expr::Str(s) => Some(Constant::String(s.to_string())),
+		Expr::Parened(expr) => constant(expr, ext_strs),
+		Expr::UnaryOp(UnaryOpType::Not, expr) => match constant(expr, ext_strs)? {
+			Constant::Bool(value) => Some(Constant::Bool(!value)),
+			_ => None,
+		},
+		// These short-circuit, so `false && x` is false whatever x is.
+		Expr::BinaryOp(a, op @ (BinaryOpType::And | BinaryOpType::Or), b) => {
+			let Constant::Bool(a) = constant(a, ext_strs)? else {
+				return None;
+			};
+			if a == (*op == BinaryOpType::Or) {
+				return Some(Constant::Bool(a));
+			}
+			match constant(b, ext_strs)? {
+				Constant::Bool(b) => Some(Constant::Bool(b)),
+				_ => None,
+			}
+		},
+		// Values of different types are never equal.
+		Expr::BinaryOp(a, op @ (BinaryOpType::Eq | BinaryOpType::Neq), b) => {
+			let equal = constant(a, ext_strs)? == constant(b, ext_strs)?;
+			Some(Constant::Bool(equal == (*op == BinaryOpType::Eq)))
+		},
+		Expr::Apply(function, args, _) if std_function(function) == Some("extVar") => match args.0.as_slice() {
+			[Arg(None, name)] => match &*name.0 {
+				Expr::Str(name) => ext_strs.get(&**name).cloned().map(Constant::String),
+				_ => None,
+			},
+			_ => None,
+		},
+		_ => None,
+	}
+}
+
+fn unparen(expr: &LocExpr) -> &LocExpr {
+	match &*expr.0 {
+		Expr::Parened(inner) => unparen(inner),
+		_ => expr,
+	}
+}
+
+// The name of the function, if the expression is `std.NAME`.
+fn std_function(expr: &LocExpr) -> Option<&str> {
+	match &*expr.0 {
+		Expr::Index(object, name) => match (&*object.0, &*name.0) {
+			(Expr::Var(var), Expr::Str(name)) if &**var == "std" => Some(name),
+			_ => None,
+		},
+		_ => None,
+	}
+}
+
+// Only the list of the first for is always evaluated. Every if condition and later list is evaluated once for
+// each item so far, which may be none.
+fn scan_compspecs(out: &mut Scan, compspecs: &[CompSpec], ctx: Context) {
+	for (i, compspec) in compspecs.iter().enumerate() {
+		let ctx = if i == 0 { ctx } else { ctx.conditional() };
+		match compspec {
+			CompSpec::IfSpec(data) => scan_ast(out, &data.0, ctx.conditional()),
+			CompSpec::ForSpec(data) => scan_ast(out, &data.1, ctx),
+		}
+	}
+}
+
+fn scan_params(out: &mut Scan, params: &ParamsDesc, ctx: Context) {
+	for Param(_, maybe_expr) in &*params.0 {
+		if let Some(expr) = maybe_expr {
+			scan_ast(out, expr, ctx.enter(Origin::ParamDefault));
+		}
+	}
+}
+
+fn scan_assert(out: &mut Scan, assert: &AssertStmt, ctx: Context) {
+	let AssertStmt(cond, maybe_msg) = assert;
+	scan_ast(out, cond, ctx.enter(Origin::Assert));
+	if let Some(msg) = maybe_msg {
+		scan_ast(out, msg, ctx.enter(Origin::AssertMessage));
+	}
+}
+
+fn scan_bindspec(out: &mut Scan, bindspec: &BindSpec, ctx: Context) {
+	let BindSpec{params, value, ..} = bindspec;
+	// If there are params, this is a function and we treat it the same as a function expression.
+	match params {
+		Some(params) => {
+			scan_params(out, params, ctx);
+			scan_ast(out, value, ctx.enter(Origin::FunctionBody));
+		},
+		None => scan_ast(out, value, ctx),
+	}
+}
+
+// The object's members start after start, if it's known.
+fn scan_obj(out: &mut Scan, obj: &ObjBody, start: Option<usize>, ctx: Context) {
+	match obj {
+		ObjBody::MemberList(members) => {
+			// Where the member before ended, which a field's name is the next thing after.
+			let mut after = start;
+			for member in members {
+				match member {
+					Member::Field(FieldMember{name, params, value, ..}) => {
+						match (name, after) {
+							(FieldName::Fixed(name), Some(after)) if out.spans == Spans::Fields => out.field_names.push((name.to_string(), after)),
+							(FieldName::Fixed(_), _) => (),
+							(FieldName::Dyn(expr), _) => scan_ast(out, expr, ctx),
+						}
+						// If there are params, this is a method and we treat it the same as a function expression.
+						match params {
+							Some(params) => {
+								scan_params(out, params, ctx);
+								scan_ast(out, value, ctx.enter(Origin::FunctionBody));
+							},
+							None => scan_ast(out, value, ctx),
+						}
+					},
+					Member::BindStmt(bindspec) => scan_bindspec(out, bindspec, ctx),
+					Member::AssertStmt(assert) => scan_assert(out, assert, ctx),
+				}
+				let last = match member {
+					Member::Field(FieldMember{value, ..}) => value,
+					Member::BindStmt(BindSpec{value, ..}) => value,
+					Member::AssertStmt(AssertStmt(cond, msg)) => msg.as_ref().unwrap_or(cond),
+				};
+				after = last.1.as_ref().map(|ExprLocation(_, _, end)| *end);
+			}
+		},
+		ObjBody::ObjComp(ObjComp{pre_locals, key, value, post_locals, compspecs}) => {
+			// Locals are evaluated along with the value, once for each item.
+			for bindspec in pre_locals { scan_bindspec(out, bindspec, ctx.conditional()); }
+			scan_ast(out, key, ctx.enter(Origin::ObjCompKey));
+			scan_ast(out, value, ctx.enter(Origin::ObjCompValue));
+			for bindspec in post_locals { scan_bindspec(out, bindspec, ctx.conditional()); }
+			scan_compspecs(out, compspecs, ctx);
+		},
+	}
+}
@@ -0,0 +1,103 @@
+// Checks of internal invariants after a run, to catch bugs in caching or graph construction
+// that would otherwise silently give wrong deps. Each discrepancy found is printed to stderr.
+
+use crate::scan::Import;
+use crate::{analyze_file, graph, resolve_deps, root_files, scan, Cache, Deadline, Options, Root};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+// The parts of an import that affect deps, as the spans recorded in an index aren't meaningful.
+fn summarize(imports: &[Import]) -> Vec<(&'static str, &Path, bool)> {
+	imports.iter().map(|import| (import.kind.name(), import.path.as_path(), import.conditional)).collect()
+}
+
+fn sorted(paths: &[PathBuf]) -> BTreeSet<&PathBuf> {
+	paths.iter().collect()
+}
+
+fn show(paths: &BTreeSet<&PathBuf>) -> String {
+	paths.iter().map(|path| path.display().to_string()).collect::<Vec<_>>().join(" ")
+}
+
+// Returns how many problems were found.
+pub fn run(options: &Options, cache: &Cache, roots: &[Root]) -> Result<usize, String> {
+	let mut problems = Vec::new();
+
+	// Every file that's imported should be a node in the graph.
+	let graph = graph::build(options, cache, roots)?;
+	for (file, deps) in &graph.edges {
+		for dep in deps {
+			if !graph.edges.contains_key(dep) {
+				problems.push(format!("{} imports {}, which is not in the graph", file.display(), dep.display()));
+			}
+		}
+	}
+
+	// Each root's deps should be exactly what can be reached from it in the graph.
+	for root in roots {
+		let deps: BTreeSet<PathBuf> = resolve_deps(options, cache, Deadline::new(&root.path), &root.jpaths, &root.path)?.into_iter().collect();
+		let files = root_files(options, &root.path);
+		let files: Vec<&Path> = files.iter().map(|file| file.as_path()).collect();
+		let mut reachable = graph.reachable(&files, false);
+		reachable.extend(options.str_files.iter().cloned());
+		if let Some(exec_root) = &options.exec_root {
+			reachable.remove(exec_root);
+		}
+		for dep in deps.difference(&reachable) {
+			problems.push(format!("{} is a dep of {} but can't be reached from it in the graph", dep.display(), root.path.display()));
+		}
+		for file in reachable.difference(&deps) {
+			problems.push(format!("{} can be reached from {} in the graph but isn't a dep of it", file.display(), root.path.display()));
+		}
+	}
+
+	// Cached imports should match what's on disk now. Copy the entries out first so we don't hold the lock.
+	let parsed: Vec<_> = cache.parsed.lock().unwrap().iter().map(|(path, imports)| (path.clone(), imports.clone())).collect();
+	for (path, cached) in &parsed {
+		// This one only exists on the command line.
+		if Some(path) == options.exec_root.as_ref() {
+			continue;
+		}
+		match scan::parse_file(path, &options.fs, options.skip_disabled.as_ref()) {
+			Ok(imports) if summarize(&imports) == summarize(cached) => (),
+			Ok(_) => problems.push(format!("Cached imports of {} don't match its current contents", path.display())),
+			Err(e) => problems.push(format!("Failed to re-read {}, which has cached imports: {}", path.display(), e)),
+		}
+	}
+
+	// And so should cached analyses. Use a fresh cache so that nothing cached is reused.
+	let analyses: Vec<_> = cache.analyses.lock().unwrap().iter()
+		.flat_map(|(jpaths, analyses)| analyses.iter().map(|(path, analysis)| (jpaths.clone(), path.clone(), analysis.clone())))
+		.collect();
+	let fresh = Cache::default();
+	for (jpaths, path, cached) in &analyses {
+		if Some(path) == options.exec_root.as_ref() {
+			continue;
+		}
+		let analysis = match analyze_file(options, &fresh, jpaths, path) {
+			Ok(analysis) => analysis,
+			Err(e) => {
+				problems.push(format!("Failed to re-analyze {}, which has a cached analysis: {}", path.display(), e));
+				continue;
+			},
+		};
+		for (kind, cached, current) in [
+			("leaf deps", &cached.leaf_deps, &analysis.leaf_deps),
+			("deep deps", &cached.deep_deps, &analysis.deep_deps),
+			("conditional deps", &cached.conditional, &analysis.conditional),
+			("unresolved deps", &cached.unresolved, &analysis.unresolved),
+		] {
+			let (cached, current) = (sorted(cached), sorted(current));
+			if cached != current {
+				problems.push(format!(
+					"Cached {} of {} are [{}], but are now [{}]", kind, path.display(), show(&cached), show(&current),
+				));
+			}
+		}
+	}
+
+	for problem in &problems {
+		eprintln!("self-check: {}", problem);
+	}
+	Ok(problems.len())
+}
@@ -0,0 +1,70 @@
+use crate::{closure, resolve_deps, Cache, Deadline, Options, Root};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+// Format a size in bytes for display, eg. "3.1 MB".
+pub fn format_size(bytes: u64) -> String {
+	const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+	if bytes < 1000 {
+		return format!("{} B", bytes);
+	}
+	let mut size = bytes as f64 / 1000.0;
+	let mut unit = 0;
+	while size >= 1000.0 && unit < UNITS.len() - 1 {
+		size /= 1000.0;
+		unit += 1;
+	}
+	format!("{:.1} {}", size, UNITS[unit])
+}
+
+// The total size of the files, plus how many there are.
+// Files that don't exist (which can only be leaf deps) count as empty.
+pub fn total_size(sizes: &mut HashMap<PathBuf, u64>, files: &HashSet<PathBuf>) -> Result<u64, String> {
+	let mut total = 0;
+	for file in files {
+		let size = match sizes.get(file) {
+			Some(size) => *size,
+			None => {
+				let size = match std::fs::metadata(file) {
+					Ok(metadata) => metadata.len(),
+					Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+					Err(e) => return Err(format!("Could not stat {}: {}", file.display(), e)),
+				};
+				sizes.insert(file.clone(), size);
+				size
+			},
+		};
+		total += size;
+	}
+	Ok(total)
+}
+
+// Print the number and total size of the files in the root's closure, then the same for each of
+// its direct imports, largest first. Files reachable through more than one import are counted for each.
+pub fn report(options: &Options, cache: &Cache, deadline: Deadline, root: &Root) -> Result<(), String> {
+	let mut sizes = HashMap::new();
+	let deps = resolve_deps(options, cache, deadline, &root.jpaths, &root.path)?;
+	let total = total_size(&mut sizes, &deps)?;
+	println!("{}: {} files, {}", root.path.display(), deps.len(), format_size(total));
+
+	// Files given on the command line are evaluated with every root, so they're attributed
+	// like any other import.
+	let analysis = cache.analysis(options, &root.jpaths, &root.path)?;
+	let deep = analysis.deep_deps.iter().chain(options.code_files.iter());
+	let leaf = analysis.leaf_deps.iter().chain(options.str_files.iter());
+	let mut costs: Vec<(&Path, usize, u64)> = Vec::new();
+	for dep in deep {
+		let deps = closure(options, cache, deadline, &root.jpaths, vec![dep.clone()], Some(&root.path))?;
+		costs.push((dep, deps.len(), total_size(&mut sizes, &deps)?));
+	}
+	for dep in leaf {
+		let deps = HashSet::from([dep.clone()]);
+		costs.push((dep, 1, total_size(&mut sizes, &deps)?));
+	}
+	costs.sort_by(|a, b| b.2.cmp(&a.2).then(b.1.cmp(&a.1)).then(a.0.cmp(b.0)));
+	costs.dedup_by(|a, b| a.0 == b.0);
+	for (dep, files, size) in costs {
+		println!("\t{} files, {}\t{}", files, format_size(size), dep.display());
+	}
+	Ok(())
+}
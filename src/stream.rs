@@ -0,0 +1,59 @@
+// Analyzing the roots in parallel for --format jsonl, outputting each one's line as soon as it's done rather than
+// in the order given, so that with thousands of roots a consumer can start on the first results straight away.
+// A root that fails doesn't stop the others: its line is {"root": FILE, "error": MESSAGE} instead.
+
+use crate::jobserver::{self, Jobserver};
+use crate::json::Value;
+use crate::{output, resolve_deps, Cache, Deadline, Options, Root};
+use std::io::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+fn run_root(options: &Options, cache: &Cache, root: &Root, cross_lock: &AtomicUsize) -> Result<Vec<u8>, String> {
+	let deadline = Deadline::new(&root.path);
+	let deps = resolve_deps(options, cache, deadline, &root.jpaths, &root.path)?;
+	let line = output::format_deps(options, cache, deadline, root, &deps)?;
+	if options.group_by_lock {
+		let lock = cache.lock_for(options, &root.path)?;
+		cross_lock.fetch_add(output::cross_lock(options, cache, lock.as_deref(), deps.iter())?.len(), Ordering::Relaxed);
+	}
+	Ok(line)
+}
+
+// Analyze every root using up to the given number of threads, and job slots from make's jobserver if given.
+// Fails at the end if any root failed.
+pub fn run(options: &Options, cache: &Cache, roots: &[Root], workers: usize, jobserver: Option<Jobserver>) -> Result<(), String> {
+	let next = AtomicUsize::new(0);
+	let failures = AtomicUsize::new(0);
+	let cross_lock = AtomicUsize::new(0);
+	let tokens = jobserver.map(Jobserver::tokens);
+	let (next, failures, cross_lock, tokens) = (&next, &failures, &cross_lock, tokens.as_ref());
+	std::thread::scope(|scope| {
+		for worker in 0..workers.min(roots.len()) {
+			scope.spawn(move || {
+				while let Some(_token) = jobserver::slot(tokens, worker, || next.load(Ordering::Relaxed) < roots.len()) {
+					let Some(root) = roots.get(next.fetch_add(1, Ordering::Relaxed)) else {
+						break;
+					};
+					let line = run_root(options, cache, root, cross_lock).unwrap_or_else(|e| {
+						failures.fetch_add(1, Ordering::Relaxed);
+						Value::Object(vec![
+							("root".into(), Value::String(root.path.to_string_lossy().into_owned())),
+							("error".into(), Value::String(e)),
+						]).to_string().into_bytes()
+					});
+					// Flush each line as it's written, so it isn't held back in a buffer when stdout is a pipe.
+					let mut stdout = std::io::stdout().lock();
+					if let Err(e) = stdout.write_all(&line).and_then(|_| stdout.write_all(b"\n")).and_then(|_| stdout.flush()) {
+						eprintln!("Failed to write output: {}", e);
+						failures.fetch_add(1, Ordering::Relaxed);
+					}
+				}
+			});
+		}
+	});
+	match (failures.load(Ordering::Relaxed), cross_lock.load(Ordering::Relaxed)) {
+		(0, 0) => Ok(()),
+		(0, n) => Err(format!("Found {} deps under a different jsonnetfile.lock.json to the roots needing them", n)),
+		(n, _) => Err(format!("Failed to analyze {} of {} roots", n, roots.len())),
+	}
+}
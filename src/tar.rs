@@ -0,0 +1,170 @@
+// Writing tar archives, optionally gzipped, as specified by POSIX (ustar) and RFC 1952.
+// We only need to write small archives of text files, so the gzip stream uses uncompressed
+// deflate blocks rather than implementing compression. Any gzip reader accepts these.
+
+use std::path::Path;
+
+const BLOCK: usize = 512;
+
+// The largest a stored deflate block can be.
+const MAX_STORED: usize = 0xffff;
+
+#[derive(Default)]
+pub struct Archive {
+	data: Vec<u8>,
+}
+
+// Write value as octal into field, zero padded and NUL terminated.
+fn octal(field: &mut [u8], value: u64) {
+	let width = field.len() - 1;
+	let digits = format!("{:0width$o}", value);
+	field[..width].copy_from_slice(digits.as_bytes());
+	field[width] = 0;
+}
+
+impl Archive {
+	// Add a regular file. Directories don't need entries of their own, as they're created as needed when extracting.
+	pub fn add(&mut self, name: &Path, contents: &[u8]) -> Result<(), String> {
+		let name = name.to_str().ok_or_else(|| format!("Can't add {} to an archive, as it isn't valid UTF-8", name.display()))?;
+		// Names over 100 bytes are split into a prefix and name at a slash.
+		let (prefix, name) = match name.len() {
+			0..=100 => ("", name),
+			_ => name.char_indices()
+				.filter(|&(i, c)| c == '/' && i <= 155 && name.len() - i - 1 <= 100)
+				.map(|(i, _)| (&name[..i], &name[i + 1..]))
+				.next()
+				.ok_or_else(|| format!("Can't add {} to an archive, as its name is too long", name))?,
+		};
+		let mut header = [0u8; BLOCK];
+		header[..name.len()].copy_from_slice(name.as_bytes());
+		octal(&mut header[100..108], 0o644);
+		octal(&mut header[108..116], 0);
+		octal(&mut header[116..124], 0);
+		octal(&mut header[124..136], contents.len() as u64);
+		octal(&mut header[136..148], 0);
+		header[156] = b'0';
+		header[257..263].copy_from_slice(b"ustar\0");
+		header[263..265].copy_from_slice(b"00");
+		header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+		// The checksum is calculated with its own field as spaces.
+		header[148..156].copy_from_slice(b"        ");
+		let checksum: u64 = header.iter().map(|&b| b as u64).sum();
+		octal(&mut header[148..155], checksum);
+		header[155] = b' ';
+		self.data.extend_from_slice(&header);
+		self.data.extend_from_slice(contents);
+		self.data.resize(self.data.len().div_ceil(BLOCK) * BLOCK, 0);
+		Ok(())
+	}
+
+	// The archive, ending with the two empty blocks that mark its end.
+	pub fn finish(mut self) -> Vec<u8> {
+		self.data.resize(self.data.len() + 2 * BLOCK, 0);
+		self.data
+	}
+}
+
+fn crc32(data: &[u8]) -> u32 {
+	let mut crc = !0u32;
+	for &byte in data {
+		crc ^= byte as u32;
+		for _ in 0..8 {
+			crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb88320 } else { crc >> 1 };
+		}
+	}
+	!crc
+}
+
+pub fn gzip(data: &[u8]) -> Vec<u8> {
+	// Magic, deflate, no flags, no mtime, no extra flags, unknown OS.
+	let mut out = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255];
+	let mut chunks = data.chunks(MAX_STORED).peekable();
+	if chunks.peek().is_none() {
+		// Even an empty stream needs one final block.
+		out.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+	}
+	while let Some(chunk) = chunks.next() {
+		let last = chunks.peek().is_none();
+		let len = chunk.len() as u16;
+		out.push(last as u8);
+		out.extend_from_slice(&len.to_le_bytes());
+		out.extend_from_slice(&(!len).to_le_bytes());
+		out.extend_from_slice(chunk);
+	}
+	out.extend_from_slice(&crc32(data).to_le_bytes());
+	out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+	out
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// A NUL terminated octal field.
+	fn field(header: &[u8], range: std::ops::Range<usize>) -> u64 {
+		let text = std::str::from_utf8(&header[range]).unwrap().trim_end_matches(['\0', ' ']);
+		u64::from_str_radix(text, 8).unwrap()
+	}
+
+	#[test]
+	fn ustar_header() {
+		let mut archive = Archive::default();
+		archive.add(Path::new("dir/a.txt"), b"hello").unwrap();
+		let data = archive.finish();
+		let header = &data[..BLOCK];
+		assert_eq!(&header[..10], b"dir/a.txt\0");
+		assert_eq!(&header[100..108], b"0000644\0");
+		assert_eq!(&header[108..116], b"0000000\0");
+		assert_eq!(&header[116..124], b"0000000\0");
+		assert_eq!(&header[124..136], b"00000000005\0");
+		assert_eq!(&header[136..148], b"00000000000\0");
+		assert_eq!(header[156], b'0');
+		assert_eq!(&header[257..263], b"ustar\0");
+		assert_eq!(&header[263..265], b"00");
+		assert!(header[345..500].iter().all(|&b| b == 0));
+		// The checksum field is six digits, a NUL and a space, and is the sum of the header with it as spaces.
+		assert_eq!(&header[154..156], b"\0 ");
+		let sum: u64 = header[..148].iter().chain(b"        ").chain(&header[156..]).map(|&b| b as u64).sum();
+		assert_eq!(field(header, 148..156), sum);
+		assert_eq!(&data[BLOCK..BLOCK + 5], b"hello");
+	}
+
+	#[test]
+	fn long_names() {
+		// Exactly 100 bytes fits in the name field alone.
+		let name = "a".repeat(100);
+		let mut archive = Archive::default();
+		archive.add(Path::new(&name), b"").unwrap();
+		let data = archive.finish();
+		assert_eq!(&data[..100], name.as_bytes());
+		assert!(data[345..500].iter().all(|&b| b == 0));
+
+		// Longer ones are split at a slash, with the rest in the prefix.
+		let prefix = format!("{}/{}", "d".repeat(60), "e".repeat(60));
+		let name = format!("{}/{}", prefix, "f".repeat(90));
+		let mut archive = Archive::default();
+		archive.add(Path::new(&name), b"").unwrap();
+		let data = archive.finish();
+		assert_eq!(&data[..91], format!("{}\0", "f".repeat(90)).as_bytes());
+		assert_eq!(&data[345..345 + prefix.len() + 1], format!("{}\0", prefix).as_bytes());
+
+		// Unless there's nowhere to split them so that both parts fit.
+		let mut archive = Archive::default();
+		assert!(archive.add(Path::new(&format!("dir/{}", "f".repeat(101))), b"").is_err());
+		assert!(archive.add(Path::new(&format!("{}/name", "d".repeat(156))), b"").is_err());
+		assert!(archive.add(Path::new(&"a".repeat(101)), b"").is_err());
+	}
+
+	#[test]
+	fn padding_and_end() {
+		assert_eq!(Archive::default().finish(), vec![0; 2 * BLOCK]);
+		for (len, blocks) in [(0, 0), (1, 1), (511, 1), (512, 1), (513, 2)] {
+			let mut archive = Archive::default();
+			archive.add(Path::new("a"), &vec![b'x'; len]).unwrap();
+			let data = archive.finish();
+			// The header, the contents padded to whole blocks, then two empty blocks.
+			assert_eq!(data.len(), (1 + blocks + 2) * BLOCK, "{} bytes", len);
+			assert!(data[BLOCK + len..].iter().all(|&b| b == 0), "{} bytes", len);
+		}
+	}
+}
@@ -0,0 +1,172 @@
+// Templates for --template, so any text format can be generated without a formatter being built in for it.
+// The syntax is a small subset of handlebars:
+// - {{NAME}} is replaced with the value of NAME. A list is written with a space between each item.
+// - {{NAME|json}} is the value as JSON, ie. a quoted string or an array of them.
+// - {{#each NAME}}...{{/each}} repeats what's between them for each item of the list NAME, with {{this}} as the item.
+// - \{{ is a literal {{.
+// Everything else is copied as is, including newlines.
+
+use crate::json::Value;
+
+#[derive(Clone, Copy)]
+enum Filter {
+	Plain,
+	Json,
+}
+
+enum Part {
+	Text(String),
+	Var(String, Filter),
+	Each(String, Vec<Part>),
+}
+
+pub struct Template {
+	// The text it was parsed from.
+	pub source: String,
+	parts: Vec<Part>,
+}
+
+// The value of a variable.
+pub enum Var {
+	One(String),
+	Many(Vec<String>),
+}
+
+impl Var {
+	fn write(&self, filter: Filter, out: &mut String) {
+		match (self, filter) {
+			(Var::One(value), Filter::Plain) => out.push_str(value),
+			(Var::Many(values), Filter::Plain) => out.push_str(&values.join(" ")),
+			(Var::One(value), Filter::Json) => out.push_str(&Value::String(value.clone()).to_string()),
+			(Var::Many(values), Filter::Json) => {
+				out.push_str(&Value::Array(values.iter().map(|value| Value::String(value.clone())).collect()).to_string())
+			},
+		}
+	}
+}
+
+impl Template {
+	// Parse the template, checking that every variable it uses is one of the given scalars or lists,
+	// and that only lists are used with #each.
+	pub fn parse(text: &str, scalars: &[&str], lists: &[&str]) -> Result<Template, String> {
+		// The parts of each #each we're inside, innermost last, under the parts of the template itself.
+		let mut stack: Vec<(Option<String>, Vec<Part>)> = vec![(None, Vec::new())];
+		let mut text_part = String::new();
+		let mut rest = text;
+		while !rest.is_empty() {
+			if let Some(after) = rest.strip_prefix("\\{{") {
+				text_part.push_str("{{");
+				rest = after;
+				continue;
+			}
+			let Some(after) = rest.strip_prefix("{{") else {
+				let c = rest.chars().next().unwrap();
+				text_part.push(c);
+				rest = &rest[c.len_utf8()..];
+				continue;
+			};
+			let (tag, after) = after.split_once("}}").ok_or("Template has a {{ without a matching }}")?;
+			rest = after;
+			let in_each = stack.len() > 1;
+			let parts = &mut stack.last_mut().unwrap().1;
+			if !text_part.is_empty() {
+				parts.push(Part::Text(std::mem::take(&mut text_part)));
+			}
+			let tag = tag.trim();
+			if let Some(name) = tag.strip_prefix("#each ") {
+				let name = name.trim();
+				if !lists.contains(&name) {
+					return Err(format!("Can't use {{{{#each {}}}}} in template, expected one of: {}", name, lists.join(", ")));
+				}
+				stack.push((Some(name.to_string()), Vec::new()));
+			} else if tag == "/each" {
+				let (name, parts) = match stack.pop() {
+					Some((Some(name), parts)) => (name, parts),
+					_ => return Err("Template has a {{/each}} without a matching {{#each}}".into()),
+				};
+				stack.last_mut().unwrap().1.push(Part::Each(name, parts));
+			} else {
+				let (name, filter) = match tag.split_once('|').map(|(name, filter)| (name.trim(), filter.trim())) {
+					None => (tag, Filter::Plain),
+					Some((name, "json")) => (name, Filter::Json),
+					Some((_, filter)) => return Err(format!("Unknown filter {:?} in template, expected json", filter)),
+				};
+				let known = scalars.contains(&name) || lists.contains(&name) || (in_each && name == "this");
+				if !known {
+					return Err(format!("Unknown variable {:?} in template, expected one of: {}", name, [scalars, lists].concat().join(", ")));
+				}
+				parts.push(Part::Var(name.to_string(), filter));
+			}
+		}
+		if !text_part.is_empty() {
+			stack.last_mut().unwrap().1.push(Part::Text(text_part));
+		}
+		match stack.pop() {
+			Some((None, parts)) if stack.is_empty() => Ok(Template { source: text.into(), parts }),
+			_ => Err("Template has an {{#each}} without a matching {{/each}}".into()),
+		}
+	}
+
+	// Render the template. vars must have a value for every scalar and list it was parsed with.
+	pub fn render(&self, vars: &[(&str, Var)]) -> String {
+		let mut out = String::new();
+		render_parts(&self.parts, vars, None, &mut out);
+		out
+	}
+}
+
+fn render_parts(parts: &[Part], vars: &[(&str, Var)], this: Option<&str>, out: &mut String) {
+	let get = |name: &str| vars.iter().find(|(var, _)| *var == name).map(|(_, value)| value);
+	for part in parts {
+		match part {
+			Part::Text(text) => out.push_str(text),
+			Part::Var(name, filter) if name == "this" => Var::One(this.unwrap_or_default().to_string()).write(*filter, out),
+			Part::Var(name, filter) => get(name).expect("template variables were checked when parsing").write(*filter, out),
+			Part::Each(name, body) => {
+				if let Some(Var::Many(items)) = get(name) {
+					for item in items {
+						render_parts(body, vars, Some(item), out);
+					}
+				}
+			},
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn render(text: &str) -> Result<String, String> {
+		let template = Template::parse(text, &["root"], &["deps"])?;
+		Ok(template.render(&[
+			("root", Var::One("main.jsonnet".into())),
+			("deps", Var::Many(vec!["a.libsonnet".into(), "b \"c\".txt".into()])),
+		]))
+	}
+
+	#[test]
+	fn substitutes_placeholders() {
+		assert_eq!(render("{{root}}: {{deps}}\n").unwrap(), "main.jsonnet: a.libsonnet b \"c\".txt\n");
+		assert_eq!(render("{{ root }}").unwrap(), "main.jsonnet");
+		assert_eq!(render("{{root|json}} {{deps | json}}").unwrap(), r#""main.jsonnet" ["a.libsonnet","b \"c\".txt"]"#);
+		assert_eq!(render("{{#each deps}}[{{this}}]{{/each}}").unwrap(), "[a.libsonnet][b \"c\".txt]");
+		assert_eq!(render("{{#each deps}}{{root}}<-{{this|json}};{{/each}}").unwrap(), r#"main.jsonnet<-"a.libsonnet";main.jsonnet<-"b \"c\".txt";"#);
+		assert_eq!(render("no placeholders, ünïcode").unwrap(), "no placeholders, ünïcode");
+	}
+
+	#[test]
+	fn literal_braces() {
+		assert_eq!(render("\\{{root}}").unwrap(), "{{root}}");
+		assert_eq!(render("\\{{ {{root}} }}").unwrap(), "{{ main.jsonnet }}");
+		// Only a pair opens a placeholder, and a lone closing pair is copied as is.
+		assert_eq!(render("{ } { {root} }} {").unwrap(), "{ } { {root} }} {");
+	}
+
+	#[test]
+	fn errors() {
+		for bad in ["{{root", "{{nope}}", "{{this}}", "{{root|yaml}}", "{{#each root}}{{/each}}", "{{#each deps}}", "{{/each}}"] {
+			assert!(render(bad).is_err(), "{:?} should fail", bad);
+		}
+	}
+}
@@ -0,0 +1,198 @@
+use crate::json::Value;
+use crate::{output, resolve_deps, Cache, Deadline, Options, Root};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::PathBuf;
+use std::process::Command;
+use std::time::{Duration, Instant, SystemTime};
+
+// How often to check watched files for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+// If more files than this change at once, eg. a checkout of another branch, we stop working out what each change
+// affects and instead start again from scratch, as if the watch had just started.
+const MAX_CHANGES: usize = 1000;
+
+pub struct Settings {
+	// Shell command to run after each change, with {roots} replaced by the affected roots.
+	pub on_change: Option<String>,
+	// After the first output, only output which deps were added or removed.
+	pub diff_output: bool,
+	// How long changes must stop for before acting on them.
+	pub debounce: Duration,
+}
+
+impl Settings {
+	pub fn new() -> Settings {
+		Settings { on_change: None, diff_output: false, debounce: POLL_INTERVAL }
+	}
+}
+
+// The mtime of each watched file, or None if it doesn't exist.
+type Snapshot = HashMap<PathBuf, Option<SystemTime>>;
+
+fn snapshot<'a>(paths: impl Iterator<Item = &'a PathBuf>) -> Snapshot {
+	paths.map(|path| {
+		let mtime = std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+		(path.clone(), mtime)
+	}).collect()
+}
+
+fn changed_paths(old: &Snapshot, new: &Snapshot) -> Vec<PathBuf> {
+	new.iter()
+		.filter(|(path, mtime)| old.get(*path) != Some(mtime))
+		.map(|(path, _)| path.clone())
+		.collect()
+}
+
+// The index of each root depending on each file, so a change only means finding the deps of the roots it affects.
+fn dependents(all_deps: &[HashSet<PathBuf>]) -> HashMap<PathBuf, Vec<usize>> {
+	let mut dependents: HashMap<PathBuf, Vec<usize>> = HashMap::new();
+	for (i, deps) in all_deps.iter().enumerate() {
+		for dep in deps {
+			dependents.entry(dep.clone()).or_default().push(i);
+		}
+	}
+	dependents
+}
+
+// Quote a string so that sh will treat it as a single word.
+pub fn shell_quote(s: &str) -> String {
+	format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+// Get the deps of a root, reporting but otherwise ignoring errors. Returns whether it succeeded.
+// On error we keep watching the previous deps, on the assumption that an error is most likely
+// due to an edit in progress and the user will fix the file shortly.
+fn update_deps(options: &Options, cache: &Cache, deadline: Deadline, root: &Root, deps: &mut HashSet<PathBuf>) -> bool {
+	match resolve_deps(options, cache, deadline, &root.jpaths, &root.path) {
+		Ok(new_deps) => {
+			*deps = new_deps;
+			true
+		},
+		Err(e) => {
+			eprintln!("{}", e);
+			deps.insert(root.path.clone());
+			false
+		},
+	}
+}
+
+fn print_deps(options: &Options, cache: &Cache, deadline: Deadline, root: &Root, deps: &HashSet<PathBuf>) {
+	match output::format_deps(options, cache, deadline, root, deps) {
+		Ok(line) => if let Err(e) = output::write_stdout(&[line, b"\n".to_vec()].concat()) {
+			eprintln!("{}", e);
+		},
+		Err(e) => eprintln!("{}", e),
+	}
+}
+
+// Output which deps of the root were added and removed, if any.
+fn print_diff(options: &Options, root: &Root, old: &HashSet<PathBuf>, new: &HashSet<PathBuf>) {
+	let removed: BTreeSet<_> = old.difference(new).collect();
+	let added: BTreeSet<_> = new.difference(old).collect();
+	if removed.is_empty() && added.is_empty() {
+		return;
+	}
+	match options.format {
+		output::Format::Json | output::Format::Jsonl | output::Format::Yaml => {
+			let paths = |paths: &BTreeSet<&PathBuf>| Value::Array(paths.iter().map(|path| {
+				Value::String(path.to_string_lossy().into_owned())
+			}).collect());
+			println!("{}", options.format.render(&Value::Object(vec![
+				("root".into(), Value::String(root.path.to_string_lossy().into_owned())),
+				("added".into(), paths(&added)),
+				("removed".into(), paths(&removed)),
+			])));
+		},
+		output::Format::Make | output::Format::Table | output::Format::NinjaDepfile | output::Format::Bazel | output::Format::Tup | output::Format::Nix | output::Format::Print0 | output::Format::Csv | output::Format::Sarif | output::Format::Template | output::Format::JsonTree | output::Format::Tree | output::Format::Diagram(_) => {
+			println!("{}:", root.path.display());
+			for dep in removed {
+				println!("- {}", dep.display());
+			}
+			for dep in added {
+				println!("+ {}", dep.display());
+			}
+		},
+	}
+}
+
+fn run_command(template: &str, roots: &[&Root]) {
+	let roots: Vec<_> = roots.iter().map(|root| shell_quote(&root.path.to_string_lossy())).collect();
+	let command = template.replace("{roots}", &roots.join(" "));
+	match Command::new("sh").arg("-c").arg(&command).status() {
+		Ok(status) if status.success() => (),
+		Ok(status) => eprintln!("Command {:?} failed: {}", command, status),
+		Err(e) => eprintln!("Failed to run command {:?}: {}", command, e),
+	}
+}
+
+// Output the deps of each root, then watch all those deps and output them again
+// for any root affected by a change. Never returns.
+pub fn run(options: &Options, cache: &Cache, roots: &[Root], settings: &Settings) -> ! {
+	let mut all_deps: Vec<HashSet<PathBuf>> = roots.iter().map(|root| {
+		let mut deps = HashSet::new();
+		let deadline = Deadline::new(&root.path);
+		if update_deps(options, cache, deadline, root, &mut deps) {
+			print_deps(options, cache, deadline, root, &deps);
+		}
+		deps
+	}).collect();
+	let mut mtimes = snapshot(all_deps.iter().flatten());
+	let mut roots_of = dependents(&all_deps);
+
+	loop {
+		std::thread::sleep(POLL_INTERVAL);
+		let mut latest = snapshot(mtimes.keys());
+		if changed_paths(&mtimes, &latest).is_empty() {
+			continue;
+		}
+		// Wait for changes to stop for the debounce time before acting, so that eg. a checkout or codegen run
+		// touching many files only causes one update.
+		let mut quiet_since = Instant::now();
+		loop {
+			std::thread::sleep(POLL_INTERVAL.min(settings.debounce));
+			let next = snapshot(mtimes.keys());
+			if !changed_paths(&latest, &next).is_empty() {
+				latest = next;
+				quiet_since = Instant::now();
+			} else if quiet_since.elapsed() >= settings.debounce {
+				break;
+			}
+		}
+		let changed = changed_paths(&mtimes, &latest);
+		// Too many changes to be worth following individually, so forget everything and treat every root as affected.
+		let rescan = changed.len() > MAX_CHANGES;
+		if rescan {
+			eprintln!("{} files changed, analyzing every root again", changed.len());
+			cache.clear();
+		} else {
+			cache.invalidate(&changed);
+		}
+
+		// A root can only depend on a file after the change if it depended on a changed file before it, as otherwise
+		// nothing it reads has changed.
+		let affected_roots: BTreeSet<usize> = match rescan {
+			true => (0..roots.len()).collect(),
+			false => changed.iter().filter_map(|path| roots_of.get(path)).flatten().copied().collect(),
+		};
+		let mut affected = Vec::new();
+		for i in affected_roots {
+			let (root, deps) = (&roots[i], &mut all_deps[i]);
+			let old_deps = settings.diff_output.then(|| deps.clone());
+			let deadline = Deadline::new(&root.path);
+			match (update_deps(options, cache, deadline, root, deps), &old_deps) {
+				(false, _) => (),
+				(true, Some(old_deps)) => print_diff(options, root, old_deps, deps),
+				(true, None) => print_deps(options, cache, deadline, root, deps),
+			}
+			affected.push(root);
+		}
+		if let Some(on_change) = &settings.on_change {
+			if !affected.is_empty() {
+				run_command(on_change, &affected);
+			}
+		}
+		mtimes = snapshot(all_deps.iter().flatten());
+		roots_of = dependents(&all_deps);
+	}
+}
@@ -0,0 +1,105 @@
+// Worker mode reads tasks from stdin, one JSON object per line, and writes the analysis
+// of each to stdout in the same order, so that a coordinator can spread the analysis of
+// a large tree over many processes or machines. Each task is:
+// {"file": PATH, "jpaths": [PATH, ...]}
+// where jpaths is optional and defaults to the global --jpath list. Each result is:
+// {"file": PATH, "leaf_deps": [PATH, ...], "deep_deps": [PATH, ...], "conditional": [PATH, ...],
+//  "origins": [[PATH, ORIGIN], ...]}
+// or {"file": PATH, "error": MESSAGE} if the task failed.
+// Once it's done, the coordinator can merge every worker's results into one graph with "worker merge".
+
+use crate::graph::Graph;
+use crate::json::{self, Value};
+use crate::output::paths_value;
+use crate::{Analysis, Cache, Options};
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+
+fn parse_task(line: &str, default_jpaths: &[PathBuf]) -> Result<(PathBuf, Vec<PathBuf>), String> {
+	let value = json::parse(line)?;
+	let file = value.get("file")
+		.ok_or("Task is missing file")?
+		.as_str().ok_or("Task file must be a string")?;
+	let jpaths = match value.get("jpaths") {
+		None => default_jpaths.to_vec(),
+		Some(jpaths) => jpaths.as_array()
+			.ok_or("Task jpaths must be a list")?
+			.iter()
+			.map(|jpath| jpath.as_str().map(PathBuf::from).ok_or("Task jpaths must be strings"))
+			.collect::<Result<_, _>>()?,
+	};
+	Ok((file.into(), jpaths))
+}
+
+fn analysis_value(file: &str, analysis: &Analysis) -> Value {
+	Value::Object(vec![
+		("file".into(), Value::String(file.into())),
+		("leaf_deps".into(), paths_value(analysis.leaf_deps.iter())),
+		("deep_deps".into(), paths_value(analysis.deep_deps.iter())),
+		("conditional".into(), paths_value(analysis.conditional.iter())),
+		("unresolved".into(), paths_value(analysis.unresolved.iter())),
+		("origins".into(), Value::Array(analysis.origins.iter().map(|(dep, origin)| Value::Array(vec![
+			Value::String(dep.to_string_lossy().into_owned()),
+			Value::String(origin.name().into()),
+		])).collect())),
+	])
+}
+
+// Handle tasks until stdin is closed. A failing task is reported in its result and doesn't stop the worker.
+pub fn run(options: &Options, cache: &Cache, default_jpaths: &[PathBuf]) -> Result<(), String> {
+	let stdin = std::io::stdin();
+	let mut stdout = std::io::stdout();
+	for line in stdin.lock().lines() {
+		let line = line.map_err(|e| format!("Failed to read task: {}", e))?;
+		if line.trim().is_empty() {
+			continue;
+		}
+		let result = parse_task(&line, default_jpaths).and_then(|(file, jpaths)| {
+			let analysis = cache.analysis(options, &jpaths, &options.normalized(file.clone())?)?;
+			Ok(analysis_value(&file.to_string_lossy(), &analysis))
+		});
+		let result = result.unwrap_or_else(|e| {
+			// Echo back the file if we can, so the coordinator knows which task failed.
+			let file = json::parse(&line).ok()
+				.and_then(|value| value.get("file").and_then(|file| file.as_str()).map(String::from));
+			Value::Object(vec![
+				("file".into(), file.map_or(Value::Null, Value::String)),
+				("error".into(), Value::String(e)),
+			])
+		});
+		// Flush after every result, as the coordinator is likely waiting on it.
+		writeln!(stdout, "{}", result)
+			.and_then(|()| stdout.flush())
+			.map_err(|e| format!("Failed to write result: {}", e))?;
+	}
+	Ok(())
+}
+
+// Merge the results written by workers, from each of the files given, into the graph of every file analyzed.
+// This has the edges but not the import expressions behind them, like a graph saved before they were recorded.
+// Fails if any task failed, as the graph would be missing the file's imports.
+pub fn merge(shards: &[PathBuf]) -> Result<Graph, String> {
+	let mut graph = Graph::default();
+	for shard in shards {
+		let contents = std::fs::read_to_string(shard).map_err(|e| format!("Failed to read {}: {}", shard.display(), e))?;
+		for (i, line) in contents.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+			let bad = |e: &str| format!("Bad result on line {} of {}: {}", i + 1, shard.display(), e);
+			let value = json::parse(line).map_err(|e| bad(&e))?;
+			let file = value.get("file").and_then(|file| file.as_str()).ok_or_else(|| bad("missing file"))?;
+			if let Some(error) = value.get("error") {
+				return Err(format!("{}: task for {} failed: {}", shard.display(), file, error.as_str().unwrap_or_default()));
+			}
+			let paths = |key: &str| -> Result<Vec<PathBuf>, String> {
+				value.get(key).and_then(|paths| paths.as_array()).ok_or_else(|| bad(&format!("missing {}", key)))?
+					.iter().map(|path| path.as_str().map(PathBuf::from).ok_or_else(|| bad(&format!("expected {} to be strings", key))))
+					.collect()
+			};
+			let deps: Vec<PathBuf> = paths("leaf_deps")?.into_iter().chain(paths("deep_deps")?).collect();
+			for dep in &deps {
+				graph.edges.entry(dep.clone()).or_default();
+			}
+			graph.edges.entry(Path::new(file).to_owned()).or_default().extend(deps);
+		}
+	}
+	Ok(graph)
+}
@@ -0,0 +1,131 @@
+// Serializing values as YAML, for --format yaml. The structure is exactly that of the json format,
+// written in block style, with each piece of output as its own document so that a stream of them can be read.
+
+use crate::json::Value;
+
+// Whether the string can be written without quotes and still be read back as the same string.
+// This is deliberately strict, so anything unusual gets quoted.
+fn is_plain(s: &str) -> bool {
+	let reserved = ["null", "true", "false", "yes", "no", "on", "off", "y", "n", ".inf", ".nan"];
+	let Some(first) = s.chars().next() else {
+		return false;
+	};
+	(first.is_ascii_alphanumeric() || matches!(first, '_' | '.' | '/'))
+		&& s.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '/' | '-' | '+' | '@'))
+		&& !reserved.contains(&s.to_ascii_lowercase().as_str())
+		&& s.parse::<f64>().is_err()
+		// eg. 0x10 and 1_000, which YAML 1.1 readers take for numbers.
+		&& !(first.is_ascii_digit() && s.chars().all(|c| c.is_ascii_hexdigit() || matches!(c, 'x' | 'o' | '_' | '.')))
+}
+
+// JSON strings are valid YAML double-quoted strings.
+fn string(s: &str) -> String {
+	if is_plain(s) { s.to_string() } else { Value::String(s.into()).to_string() }
+}
+
+// The value on one line, if it's a scalar or an empty collection.
+fn inline(value: &Value) -> Option<String> {
+	match value {
+		Value::String(s) => Some(string(s)),
+		Value::Array(items) if items.is_empty() => Some("[]".into()),
+		Value::Object(items) if items.is_empty() => Some("{}".into()),
+		Value::Array(_) | Value::Object(_) => None,
+		// Other scalars are written the same as in JSON.
+		value => Some(value.to_string()),
+	}
+}
+
+// The lines of a non-empty collection, at no indentation.
+fn block(value: &Value) -> Vec<String> {
+	let mut lines = Vec::new();
+	match value {
+		Value::Object(items) => for (key, value) in items {
+			match inline(value) {
+				Some(value) => lines.push(format!("{}: {}", string(key), value)),
+				None => {
+					lines.push(format!("{}:", string(key)));
+					lines.extend(block(value).into_iter().map(|line| format!("  {}", line)));
+				},
+			}
+		},
+		Value::Array(items) => for item in items {
+			match inline(item) {
+				Some(item) => lines.push(format!("- {}", item)),
+				// The first line of the item goes alongside the dash, eg. "- key: value".
+				None => for (i, line) in block(item).into_iter().enumerate() {
+					lines.push(format!("{} {}", if i == 0 { "-" } else { " " }, line));
+				},
+			}
+		},
+		value => lines.extend(inline(value)),
+	}
+	lines
+}
+
+// The value as a YAML document, without a trailing newline.
+pub fn document(value: &Value) -> String {
+	match inline(value) {
+		Some(value) => format!("--- {}", value),
+		None => format!("---\n{}", block(value).join("\n")),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn quotes_strings_that_need_it() {
+		for plain in ["main.jsonnet", "lib/a-b.libsonnet", "_x", ".hidden", "/abs/path", "a+b@c"] {
+			assert_eq!(string(plain), plain);
+		}
+		for (s, quoted) in [
+			("", r#""""#),
+			("a: b", r#""a: b""#),
+			("a:b", r#""a:b""#),
+			("-x", r#""-x""#),
+			("- x", r#""- x""#),
+			("true", r#""true""#),
+			("False", r#""False""#),
+			("null", r#""null""#),
+			("~", r#""~""#),
+			("yes", r#""yes""#),
+			("12", r#""12""#),
+			("1.5", r#""1.5""#),
+			("0x10", r#""0x10""#),
+			("1_000", r#""1_000""#),
+			(".inf", r#"".inf""#),
+			("#comment", "\"#comment\""),
+			("a b", r#""a b""#),
+			("it's", r#""it's""#),
+			("say \"hi\"", r#""say \"hi\"""#),
+			("line\nbreak", r#""line\nbreak""#),
+			("café", r#""café""#),
+		] {
+			assert_eq!(string(s), quoted, "{:?}", s);
+		}
+	}
+
+	#[test]
+	fn documents() {
+		assert_eq!(document(&Value::String("a".into())), "--- a");
+		assert_eq!(document(&Value::Array(Vec::new())), "--- []");
+		let value = Value::Object(vec![
+			("root".into(), Value::String("main.jsonnet".into())),
+			("deps".into(), Value::Array(vec![Value::String("a: b".into()), Value::Object(vec![("size".into(), Value::Number(1.0))])])),
+			("empty".into(), Value::Object(Vec::new())),
+			("nested".into(), Value::Object(vec![("null".into(), Value::Null), ("ok".into(), Value::Bool(true))])),
+		]);
+		assert_eq!(document(&value), [
+			"---",
+			"root: main.jsonnet",
+			"deps:",
+			"  - \"a: b\"",
+			"  - size: 1",
+			"empty: {}",
+			"nested:",
+			"  \"null\": null",
+			"  ok: true",
+		].join("\n"));
+	}
+}